@@ -12,6 +12,7 @@ pub struct EasyFileSystem {
     pub block_device: Arc<dyn BlockDevice>,
     pub inode_bitmap: Bitmap,
     pub data_bitmap: Bitmap,
+    total_blocks: u32,
     inode_area_start_block: u32,
     data_area_start_block: u32,
 }
@@ -43,6 +44,7 @@ impl EasyFileSystem {
             block_device: block_device.clone(),
             inode_bitmap,
             data_bitmap,
+            total_blocks,
             inode_area_start_block: 1 + inode_bitmap_blocks,
             data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
         };
@@ -94,6 +96,10 @@ impl EasyFileSystem {
         // release efs lock
         Inode::new(block_id, block_offset, Arc::clone(efs), block_device)
     }
+    /// Number of blocks on the device, as recorded in the super block
+    pub fn total_blocks(&self) -> usize {
+        self.total_blocks as usize
+    }
     /// Get inode by id
     pub fn get_disk_inode_pos(&self, inode_id: u32) -> (u32, usize) {
         let inode_size = core::mem::size_of::<DiskInode>();
@@ -150,6 +156,7 @@ impl EasyFileSystem {
                         (1 + inode_total_blocks) as usize,
                         super_block.data_bitmap_blocks as usize,
                     ),
+                    total_blocks: super_block.total_blocks,
                     inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
                     data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
                 };
@@ -76,6 +76,8 @@ impl SuperBlock {
 pub enum DiskInodeType {
     File,
     Directory,
+    /// A named pipe, it never owns data blocks
+    Fifo,
 }
 
 /// A indirect block
@@ -107,6 +109,10 @@ impl DiskInode {
     pub fn is_dir(&self) -> bool {
         self.type_ == DiskInodeType::Directory
     }
+    /// Whether this inode is a named pipe
+    pub fn is_fifo(&self) -> bool {
+        self.type_ == DiskInodeType::Fifo
+    }
     /// Whether this inode is a file
     #[allow(unused)]
     pub fn is_file(&self) -> bool {
@@ -0,0 +1,105 @@
+//! Kernel backtraces.
+//!
+//! The kernel is built with frame pointers: every frame saves the return
+//! address at `fp - 8` and the caller's frame pointer at `fp - 16`. Return
+//! addresses are resolved against the symbol table the Makefile writes into
+//! the `.ksyms` section after linking, one `ADDRESS TYPE NAME` line per
+//! function as `nm -n` prints them.
+use core::arch::{asm, global_asm};
+use core::fmt::{self, Display, Formatter};
+
+use crate::config::KERNEL_STACK_SIZE;
+
+global_asm!(include_str!("ksyms.S"));
+
+/// Frames printed at most
+const MAX_DEPTH: usize = 32;
+
+/// The symbol table, empty if the Makefile did not fill it in
+fn ksyms() -> &'static str {
+    extern "C" {
+        fn sksyms();
+        fn eksyms();
+    }
+    let table = unsafe {
+        core::slice::from_raw_parts(
+            sksyms as usize as *const u8,
+            eksyms as usize - sksyms as usize,
+        )
+    };
+    // the space is padded with zeros
+    let len = table
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(table.len());
+    core::str::from_utf8(&table[..len]).unwrap_or("")
+}
+
+/// The function containing `addr` and the offset of `addr` into it
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    let mut found = None;
+    for line in ksyms().lines() {
+        let mut fields = line.splitn(3, ' ');
+        let start = match fields.next().map(|start| usize::from_str_radix(start, 16)) {
+            Some(Ok(start)) => start,
+            _ => continue,
+        };
+        // sorted by address
+        if start > addr {
+            break;
+        }
+        if let Some(name) = fields.nth(1) {
+            found = Some((name, addr - start));
+        }
+    }
+    found
+}
+
+/// An address printed as `function+offset` when the symbol table knows it
+pub struct Symbolized(pub usize);
+
+impl Display for Symbolized {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match lookup(self.0) {
+            Some((name, offset)) => write!(f, "{:#x} <{}+{:#x}>", self.0, name, offset),
+            None => write!(f, "{:#x} <??>", self.0),
+        }
+    }
+}
+
+/// A return address, printed with the function of the call before it. The
+/// call is 2 or 4 bytes long, so the function is looked up at `ra - 1`,
+/// which is always inside it, and the offset shown is the one of `ra`
+pub struct ReturnAddress(pub usize);
+
+impl Display for ReturnAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match lookup(self.0 - 1) {
+            Some((name, offset)) => write!(f, "{:#x} <{}+{:#x}>", self.0, name, offset + 1),
+            None => write!(f, "{:#x} <??>", self.0),
+        }
+    }
+}
+
+/// Print the return addresses of the frames above the caller
+pub fn print_backtrace() {
+    let mut fp: usize;
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp);
+    }
+    // frames only go up the stack, and not past its size
+    let limit = fp + KERNEL_STACK_SIZE;
+    println!("backtrace:");
+    for depth in 0..MAX_DEPTH {
+        let ra = unsafe { *((fp - 8) as *const usize) };
+        let caller_fp = unsafe { *((fp - 16) as *const usize) };
+        if ra == 0 {
+            break;
+        }
+        println!("  #{:<2} {}", depth, ReturnAddress(ra));
+        if caller_fp <= fp || caller_fp > limit || caller_fp % 8 != 0 {
+            break;
+        }
+        fp = caller_fp;
+    }
+}
@@ -1,6 +1,9 @@
-use crate::drivers::{BLOCK_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE, UART};
+use crate::drivers::{BLOCK_DEVICE, EXTRA_BLOCK_DEVICES, KEYBOARD_DEVICE, MOUSE_DEVICE, UART};
 use crate::drivers::chardev::CharDevice;
 use crate::drivers::plic::{IntrTargetPriority, PLIC};
+use crate::net::net_interrupt_handler;
+use crate::smp::hart_id;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 
 
@@ -25,24 +28,60 @@ pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
 pub type CharDeviceImpl = crate::drivers::chardev::NS16550a<VIRT_UART>;
 
 pub const VIRT_PLIC: usize = 0xC00_0000;
+/// virtio-mmio transports of the virt machine, one page each
+pub const VIRTIO_MMIO_BASE: usize = 0x1000_1000;
+pub const VIRTIO_MMIO_SIZE: usize = 0x1000;
+pub const VIRTIO_MMIO_SLOTS: usize = 8;
 pub const VIRT_UART: usize = 0x1000_0000;
 #[allow(unused)]
 pub const VIRTGPU_XRES: u32 = 1280;
 #[allow(unused)]
 pub const VIRTGPU_YRES: u32 = 800;
 
+/// External interrupt sources handled by `irq_handler`
+pub const IRQ_NAMES: &[(usize, &str)] = &[
+    (4, "virtio-net"),
+    (5, "virtio-keyboard"),
+    (6, "virtio-mouse"),
+    (8, "virtio-blk"),
+    (10, "uart"),
+];
+
+const IRQ_COUNTER_INIT: AtomicUsize = AtomicUsize::new(0);
+/// Number of times each PLIC source has been claimed
+static IRQ_COUNTS: [AtomicUsize; 16] = [IRQ_COUNTER_INIT; 16];
+
+/// Get how many interrupts have been taken from PLIC source `irq`
+pub fn irq_count(irq: usize) -> usize {
+    IRQ_COUNTS[irq].load(Ordering::Relaxed)
+}
+
+/// Give every interrupt source its priority, once for the whole machine
 pub fn device_init() {
+    let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
+    //irq nums: 4 net, 5 keyboard, 6 mouse, 8 block, 10 uart
+    for intr_src_id in [4usize, 5, 6, 8, 10] {
+        plic.set_priority(intr_src_id, 1);
+    }
+    for (intr_src_id, _) in EXTRA_BLOCK_DEVICES.iter() {
+        plic.set_priority(*intr_src_id, 1);
+    }
+}
+
+/// Route the interrupt sources to the supervisor context of `hart_id`,
+/// whichever hart claims an interrupt first handles it
+pub fn hart_init(hart_id: usize) {
     use riscv::register::sie;
     let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let hart_id: usize = 0;
     let supervisor = IntrTargetPriority::Supervisor;
     let machine = IntrTargetPriority::Machine;
     plic.set_threshold(hart_id, supervisor, 0);
     plic.set_threshold(hart_id, machine, 1);
-    //irq nums: 5 keyboard, 6 mouse, 8 block, 10 uart
-    for intr_src_id in [5usize, 6, 8, 10] {
+    for intr_src_id in [4usize, 5, 6, 8, 10] {
         plic.enable(hart_id, supervisor, intr_src_id);
-        plic.set_priority(intr_src_id, 1);
+    }
+    for (intr_src_id, _) in EXTRA_BLOCK_DEVICES.iter() {
+        plic.enable(hart_id, supervisor, *intr_src_id);
     }
     unsafe {
         sie::set_sext();
@@ -51,15 +90,27 @@ pub fn device_init() {
 
 pub fn irq_handler() {
     let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let intr_src_id = plic.claim(0, IntrTargetPriority::Supervisor);
+    let hart_id = hart_id();
+    let intr_src_id = plic.claim(hart_id, IntrTargetPriority::Supervisor) as usize;
+    // another hart took it first
+    if intr_src_id == 0 {
+        return;
+    }
+    if let Some(counter) = IRQ_COUNTS.get(intr_src_id) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
     match intr_src_id {
+        4 => net_interrupt_handler(),
         5 => KEYBOARD_DEVICE.handle_irq(),
         6 => MOUSE_DEVICE.handle_irq(),
         8 => BLOCK_DEVICE.handle_irq(),
         10 => UART.handle_irq(),
-        _ => panic!("unsupported IRQ {}", intr_src_id),
+        _ => match EXTRA_BLOCK_DEVICES.iter().find(|(irq, _)| *irq == intr_src_id) {
+            Some((_, device)) => device.handle_irq(),
+            None => panic!("unsupported IRQ {}", intr_src_id),
+        },
     }
-    plic.complete(0, IntrTargetPriority::Supervisor, intr_src_id);
+    plic.complete(hart_id, IntrTargetPriority::Supervisor, intr_src_id as u32);
 }
 
 //ref:: https://github.com/andre-richter/qemu-exit
@@ -0,0 +1,110 @@
+//! The kernel command line, `/chosen/bootargs` of the device tree.
+//!
+//! The firmware passes the device tree address in `a1`. It is read once at
+//! boot, before the frame allocator may hand out the memory it sits in, so
+//! the arguments are copied into a static buffer.
+//!
+//! Arguments are `key=value` pairs separated by spaces,
+//! `make run BOOTARGS="..."` passes them through QEMU.
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/// Longest command line kept, the rest is cut off
+const CMDLINE_MAX: usize = 256;
+
+static mut CMDLINE: [u8; CMDLINE_MAX] = [0; CMDLINE_MAX];
+static mut CMDLINE_LEN: usize = 0;
+
+fn read_be32(addr: usize) -> u32 {
+    u32::from_be(unsafe { (addr as *const u32).read_volatile() })
+}
+
+/// Length of the nul-terminated string at `addr`
+fn c_str_len(addr: usize) -> usize {
+    let mut len = 0;
+    while unsafe { ((addr + len) as *const u8).read_volatile() } != 0 {
+        len += 1;
+    }
+    len
+}
+
+fn c_str(addr: usize) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(addr as *const u8, c_str_len(addr)) }
+}
+
+/// Find `/chosen/bootargs` in the flattened device tree at `dtb`
+fn find_bootargs(dtb: usize) -> Option<&'static [u8]> {
+    if dtb == 0 || dtb % 4 != 0 || read_be32(dtb) != FDT_MAGIC {
+        return None;
+    }
+    let structs = dtb + read_be32(dtb + 8) as usize;
+    let strings = dtb + read_be32(dtb + 12) as usize;
+    let mut offset = structs;
+    let mut depth = 0;
+    let mut in_chosen = false;
+    loop {
+        let token = read_be32(offset);
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = c_str(offset);
+                offset += (name.len() + 4) & !3;
+                depth += 1;
+                in_chosen = depth == 2 && name == b"chosen";
+            }
+            FDT_END_NODE => {
+                depth -= 1;
+                in_chosen = false;
+                if depth == 0 {
+                    return None;
+                }
+            }
+            FDT_PROP => {
+                let len = read_be32(offset) as usize;
+                let name = c_str(strings + read_be32(offset + 4) as usize);
+                let value = offset + 8;
+                offset = value + ((len + 3) & !3);
+                if in_chosen && name == b"bootargs" {
+                    let value = unsafe { core::slice::from_raw_parts(value as *const u8, len) };
+                    // the property includes its terminating nul
+                    return Some(value.split(|&c| c == 0).next().unwrap_or(&[]));
+                }
+            }
+            FDT_NOP => {}
+            _ => return None,
+        }
+    }
+}
+
+/// Copy the command line out of the device tree, must run before `mm::init`
+pub fn init(dtb: usize) {
+    if let Some(bootargs) = find_bootargs(dtb) {
+        let len = bootargs.len().min(CMDLINE_MAX);
+        unsafe {
+            CMDLINE[..len].copy_from_slice(&bootargs[..len]);
+            CMDLINE_LEN = len;
+        }
+    }
+}
+
+/// The whole command line, empty if there was none
+pub fn cmdline() -> &'static str {
+    let bytes = unsafe { &CMDLINE[..CMDLINE_LEN] };
+    core::str::from_utf8(bytes).unwrap_or("")
+}
+
+/// The value of `key=value` on the command line
+pub fn param(key: &str) -> Option<&'static str> {
+    cmdline().split_ascii_whitespace().find_map(|arg| {
+        let (k, v) = arg.split_once('=')?;
+        if k == key {
+            Some(v)
+        } else {
+            None
+        }
+    })
+}
@@ -1,6 +1,13 @@
 //! Constants used in rCore
 
+/// Initially mapped part of a user stack, page faults grow it from there
 pub const USER_STACK_SIZE: usize = 4096 * 2;
+/// How far a user stack grows unless `thread_create` asks for another size
+pub const USER_STACK_LIMIT: usize = 1024 * 1024;
+/// Largest user stack, each thread has this much address space for it
+pub const USER_STACK_MAX: usize = 16 * 1024 * 1024;
+/// Largest user heap, `brk` moves its end between the program and the stacks
+pub const USER_HEAP_MAX: usize = 64 * 1024 * 1024;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 20;
 pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;//3145728
 pub const MEMORY_END: usize = 0x88000000; //2155872256
@@ -11,6 +18,14 @@ pub const MAX_SYSCALL_NUM: usize = 500;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
 pub const CLOCK_FREQ: usize = 12500000;
+/// Harts the kernel can run on, entry.asm has a boot stack for each
+pub const MAX_HARTS: usize = 8;
+/// capacity in bytes of the kernel log read by `dmesg`
+pub const LOG_BUFFER_SIZE: usize = 64 * 1024;
+/// capacity in bytes of the ring buffer behind every pipe
+pub const PIPE_BUFFER_SIZE: usize = 4096;
+/// tmpfs may hold at most 1/TMPFS_FRAME_SHARE of all physical frames
+pub const TMPFS_FRAME_SHARE: usize = 4;
 
 pub use crate::board::{MMIO};
 
@@ -1,7 +1,10 @@
 use core::fmt::{Arguments, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use crate::drivers::chardev::CharDevice;
 use crate::drivers::UART;
 use crate::sbi::console_putchar;
+use crate::smp::hart_id;
+use crate::sync::SpinNoIrqLock;
 
 struct Stdout;
 
@@ -16,8 +19,20 @@ impl Write for Stdout {
     }
 }
 
+/// Keeps the lines of different harts from interleaving
+static PRINT_LOCK: SpinNoIrqLock<()> = SpinNoIrqLock::new(());
+/// Hart holding `PRINT_LOCK`, so a panic while printing can still print
+static PRINTING_HART: AtomicUsize = AtomicUsize::new(usize::MAX);
+
 pub fn print(args:Arguments){
+    if PRINTING_HART.load(Ordering::Relaxed) == hart_id() {
+        Stdout.write_fmt(args).unwrap();
+        return;
+    }
+    let _guard = PRINT_LOCK.lock();
+    PRINTING_HART.store(hart_id(), Ordering::Relaxed);
     Stdout.write_fmt(args).unwrap();
+    PRINTING_HART.store(usize::MAX, Ordering::Relaxed);
 }
 
 
@@ -2,14 +2,36 @@ pub mod virtio_blk;
 
 pub use virtio_blk::VirtIOBlock;
 
+use crate::board::{VIRTIO_MMIO_BASE, VIRTIO_MMIO_SIZE, VIRTIO_MMIO_SLOTS};
+use alloc::vec::Vec;
+use virtio_blk::{is_virtio_blk, VIRTIO0};
+
 use crate::board::BlockDeviceImpl;
 use alloc::sync::Arc;
 use easy_fs::BlockDevice;
 use lazy_static::*;
+use log::info;
 // type BlockDeviceImpl = virtio_blk::VirtIOBlock;
 
 lazy_static! {
     pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    /// virtio-blk devices found besides `BLOCK_DEVICE`, paired with their IRQ numbers
+    pub static ref EXTRA_BLOCK_DEVICES: Vec<(usize, Arc<dyn BlockDevice>)> = probe_block_devices();
+}
+
+/// Scan every virtio-mmio slot for block devices other than the easy-fs one
+fn probe_block_devices() -> Vec<(usize, Arc<dyn BlockDevice>)> {
+    let mut devices: Vec<(usize, Arc<dyn BlockDevice>)> = Vec::new();
+    for slot in 0..VIRTIO_MMIO_SLOTS {
+        let addr = VIRTIO_MMIO_BASE + slot * VIRTIO_MMIO_SIZE;
+        if addr == VIRTIO0 || !is_virtio_blk(addr) {
+            continue;
+        }
+        info!("found virtio-blk at {:#x}", addr);
+        // slot i of the virt machine raises PLIC source i + 1
+        devices.push((slot + 1, Arc::new(VirtIOBlock::from_mmio(addr))));
+    }
+    devices
 }
 
 #[allow(unused)]
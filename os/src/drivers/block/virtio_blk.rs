@@ -5,7 +5,7 @@ use crate::mm::page_table::PageTable;
 use crate::mm::{
     frame_alloc, frame_dealloc, FrameTracker, PhysPageNum, VirtAddr, PhysAddr,
 };
-use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::sync::{Condvar, SpinNoIrqLock};
 use alloc::vec::Vec;
 use lazy_static::*;
 use virtio_drivers::{BlkResp, Hal, RespStatus, VirtIOBlk, VirtIOHeader};
@@ -17,21 +17,21 @@ use crate::task::schedule;
 // #[allow(unused)]
 // const VIRTIO0: usize = 0x10001000;
 #[allow(unused)]
-const VIRTIO0: usize = 0x10008000;
+pub const VIRTIO0: usize = 0x10008000;
 pub struct VirtIOBlock {
-    virtio_blk: UPIntrFreeCell<VirtIOBlk<'static, VirtioHal>>,
+    virtio_blk: SpinNoIrqLock<VirtIOBlk<'static, VirtioHal>>,
     condvars: BTreeMap<u16, Condvar>,
 }
 lazy_static! {
-    static ref QUEUE_FRAMES: UPIntrFreeCell<Vec<FrameTracker>> = unsafe { UPIntrFreeCell::new(Vec::new()) };
+    static ref QUEUE_FRAMES: SpinNoIrqLock<Vec<FrameTracker>> = SpinNoIrqLock::new(Vec::new());
 }
 
 impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+        let nb = *DEV_NON_BLOCKING_ACCESS.lock();
         if nb {
             let mut resp = BlkResp::default();
-            let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
+            let task_cx_ptr = self.virtio_blk.with_lock(|blk| {
                 let token = unsafe { blk.read_block_nb(block_id, buf, &mut resp).unwrap() };
                 self.condvars.get(&token).unwrap().wait_no_sched()
             });
@@ -43,16 +43,16 @@ impl BlockDevice for VirtIOBlock {
             );
         } else {
             self.virtio_blk
-                .exclusive_access()
+                .lock()
                 .read_block(block_id, buf)
                 .expect("Error when reading VirtIOBlk");
         }
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+        let nb = *DEV_NON_BLOCKING_ACCESS.lock();
         if nb {
             let mut resp = BlkResp::default();
-            let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
+            let task_cx_ptr = self.virtio_blk.with_lock(|blk| {
                 let token = unsafe { blk.write_block_nb(block_id, buf, &mut resp).unwrap() };
                 self.condvars.get(&token).unwrap().wait_no_sched()
             });
@@ -64,13 +64,13 @@ impl BlockDevice for VirtIOBlock {
             );
         } else {
             self.virtio_blk
-                .exclusive_access()
+                .lock()
                 .write_block(block_id, buf)
                 .expect("Error when writing VirtIOBlk");
         }
     }
     fn handle_irq(&self) {
-        self.virtio_blk.exclusive_session(|blk| {
+        self.virtio_blk.with_lock(|blk| {
             while let Ok(token) = blk.pop_used() {
                 self.condvars.get(&token).unwrap().signal();
             }
@@ -79,15 +79,32 @@ impl BlockDevice for VirtIOBlock {
 }
 
 
+/// virtio-mmio magic value, "virt" in little endian
+const VIRTIO_MAGIC: u32 = 0x7472_6976;
+/// virtio device id of block devices
+const VIRTIO_ID_BLOCK: u32 = 2;
+
+/// Whether a virtio-blk device sits at the virtio-mmio slot `addr`
+pub fn is_virtio_blk(addr: usize) -> bool {
+    unsafe {
+        let magic = (addr as *const u32).read_volatile();
+        let device_id = ((addr + 8) as *const u32).read_volatile();
+        magic == VIRTIO_MAGIC && device_id == VIRTIO_ID_BLOCK
+    }
+}
+
 impl VirtIOBlock {
     pub fn new() -> Self {
-        let virtio_blk = unsafe {
-            UPIntrFreeCell::new(
-                VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
-            )
-        };
+        Self::from_mmio(VIRTIO0)
+    }
+
+    /// Take over the virtio-blk device at MMIO address `addr`
+    pub fn from_mmio(addr: usize) -> Self {
+        let virtio_blk = SpinNoIrqLock::new(
+            VirtIOBlk::<VirtioHal>::new(&mut *(addr as *mut VirtIOHeader)).unwrap(),
+        );
         let mut condvars = BTreeMap::new();
-        let channels = virtio_blk.exclusive_access().virt_queue_size();
+        let channels = virtio_blk.lock().virt_queue_size();
         for i in 0..channels {
             let condvar = Condvar::new();
             condvars.insert(i, condvar);
@@ -110,7 +127,7 @@ impl VirtIOBlock {
 //                 ppn_base = frame.ppn;
 //             }
 //             assert_eq!(frame.ppn.0, ppn_base.0 + i);
-//             QUEUE_FRAMES.exclusive_access().push(frame);
+//             QUEUE_FRAMES.lock().push(frame);
 //         }
 //         let pa: PhysAddr = ppn_base.into();
 //         pa.0
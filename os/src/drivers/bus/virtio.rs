@@ -2,7 +2,7 @@ use crate::mm::{
      frame_dealloc,  FrameTracker, PageTable, PhysAddr, PhysPageNum,
     StepByOne, VirtAddr,
 };
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinNoIrqLock;
 use alloc::vec::Vec;
 use lazy_static::*;
 use virtio_drivers::Hal;
@@ -10,8 +10,8 @@ use crate::mm::frame_allocator::frame_alloc_more;
 use crate::mm::memory_set::kernel_token;
 
 lazy_static! {
-    static ref QUEUE_FRAMES: UPIntrFreeCell<Vec<FrameTracker>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
+    static ref QUEUE_FRAMES: SpinNoIrqLock<Vec<FrameTracker>> =
+        SpinNoIrqLock::new(Vec::new());
 }
 
 pub struct VirtioHal;
@@ -21,7 +21,7 @@ impl Hal for VirtioHal {
         let trakcers = frame_alloc_more(pages);
         let ppn_base = trakcers.as_ref().unwrap().last().unwrap().ppn;
         QUEUE_FRAMES
-            .exclusive_access()
+            .lock()
             .append(&mut trakcers.unwrap());
         let pa: PhysAddr = ppn_base.into();
         pa.0
@@ -2,9 +2,10 @@
 ///! Ref: ns16550a datasheet: https://datasheetspdf.com/pdf-file/605590/NationalSemiconductor/NS16550A/1
 ///! Ref: ns16450 datasheet: https://datasheetspdf.com/pdf-file/1311818/NationalSemiconductor/NS16450/1
 use super::CharDevice;
-use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::sync::{Condvar, PollQueue, SpinNoIrqLock};
 use crate::task::schedule;
 use alloc::collections::VecDeque;
+use alloc::sync::Arc;
 use bitflags::*;
 use volatile::{ReadOnly, Volatile, WriteOnly};
 
@@ -125,8 +126,9 @@ struct NS16550aInner {
 }
 
 pub struct NS16550a<const BASE_ADDR: usize> {
-    inner: UPIntrFreeCell<NS16550aInner>,
+    inner: SpinNoIrqLock<NS16550aInner>,
     condvar: Condvar,
+    poll_queue: Arc<PollQueue>,
 }
 
 impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR> {
@@ -137,26 +139,32 @@ impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR> {
         };
         //inner.ns16550a.init();
         Self {
-            inner: unsafe { UPIntrFreeCell::new(inner) },
+            inner: SpinNoIrqLock::new(inner),
             condvar: Condvar::new(),
+            poll_queue: Arc::new(PollQueue::new()),
         }
     }
 
     pub fn read_buffer_is_empty(&self) -> bool {
-        self.inner.exclusive_session(|inner| inner.read_buffer.is_empty())
+        self.inner.with_lock(|inner| inner.read_buffer.is_empty())
+    }
+
+    /// Tasks in `poll` waiting for input
+    pub fn poll_queue(&self) -> Arc<PollQueue> {
+        self.poll_queue.clone()
     }
 }
 
 impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
     fn init(&self){
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         inner.ns16550a.init();
         drop(inner);
     }
 
     fn read(&self) -> u8 {
         loop {
-            let mut inner = self.inner.exclusive_access();
+            let mut inner = self.inner.lock();
             if let Some(ch) = inner.read_buffer.pop_front() {
                 return ch;
             } else {
@@ -167,12 +175,12 @@ impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
         }
     }
     fn write(&self, ch: u8) {
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         inner.ns16550a.write(ch);
     }
     fn handle_irq(&self) {
         let mut count = 0;
-        self.inner.exclusive_session(|inner| {
+        self.inner.with_lock(|inner| {
             while let Some(ch) = inner.ns16550a.read() {
                 count += 1;
                 inner.read_buffer.push_back(ch);
@@ -180,6 +188,7 @@ impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
         });
         if count > 0 {
             self.condvar.signal();
+            self.poll_queue.wake_all();
         }
     }
 }
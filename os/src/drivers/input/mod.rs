@@ -1,5 +1,5 @@
 use crate::drivers::bus::virtio::VirtioHal;
-use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::sync::{Condvar, PollQueue, SpinNoIrqLock};
 use crate::task::schedule;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
@@ -16,14 +16,17 @@ struct VirtIOInputInner {
 }
 
 struct VirtIOInputWrapper {
-    inner: UPIntrFreeCell<VirtIOInputInner>,
+    inner: SpinNoIrqLock<VirtIOInputInner>,
     condvar: Condvar,
+    poll_queue: Arc<PollQueue>,
 }
 
 pub trait InputDevice: Send + Sync + Any {
     fn read_event(&self) -> u64;
     fn handle_irq(&self);
     fn is_empty(&self) -> bool;
+    /// Tasks in `poll` waiting for events
+    fn poll_queue(&self) -> Arc<PollQueue>;
 }
 
 lazy_static::lazy_static!(
@@ -40,20 +43,25 @@ impl VirtIOInputWrapper {
             events: VecDeque::new(),
         };
         Self {
-            inner: unsafe { UPIntrFreeCell::new(inner) },
+            inner: SpinNoIrqLock::new(inner),
             condvar: Condvar::new(),
+            poll_queue: Arc::new(PollQueue::new()),
         }
     }
 }
 
 impl InputDevice for VirtIOInputWrapper {
     fn is_empty(&self) -> bool {
-        self.inner.exclusive_access().events.is_empty()
+        self.inner.lock().events.is_empty()
+    }
+
+    fn poll_queue(&self) -> Arc<PollQueue> {
+        self.poll_queue.clone()
     }
 
     fn read_event(&self) -> u64 {
         loop {
-            let mut inner = self.inner.exclusive_access();
+            let mut inner = self.inner.lock();
             if let Some(event) = inner.events.pop_front() {
                 return event;
             } else {
@@ -68,7 +76,7 @@ impl InputDevice for VirtIOInputWrapper {
         let mut count = 0;
         let mut result = 0;
         let mut key = 0;
-        self.inner.exclusive_session(|inner| {
+        self.inner.with_lock(|inner| {
             inner.virtio_input.ack_interrupt();
             while let Some((token, event)) = inner.virtio_input.pop_pending_event() {
                 count += 1;
@@ -84,6 +92,7 @@ impl InputDevice for VirtIOInputWrapper {
         if count > 0 {
             //self.condvars.get(&key).unwrap().signal();
             self.condvar.signal();
+            self.poll_queue.wake_all();
         };
     }
 }
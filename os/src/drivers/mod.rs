@@ -7,7 +7,7 @@ pub mod net;
 pub mod plic;
 
 
-pub use block::BLOCK_DEVICE;
+pub use block::{BLOCK_DEVICE, EXTRA_BLOCK_DEVICES};
 pub use bus::*;
 pub use chardev::UART;
 pub use gpu::*;
@@ -1,46 +1,45 @@
+mod virtio_net;
+
 use core::any::Any;
 
-use crate::drivers::virtio::VirtioHal;
-use crate::sync::UPIntrFreeCell;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
-use virtio_drivers::{VirtIOHeader, VirtIONet};
+use virtio_net::VirtIONetDevice;
 
 const VIRTIO8: usize = 0x10004000;
 
 lazy_static! {
-    pub static ref NET_DEVICE: Arc<dyn NetDevice> = Arc::new(VirtIONetWrapper::new());
+    /// Without a virtio-net device only the loopback interface works
+    pub static ref NET_DEVICE: Arc<dyn NetDevice> = match VirtIONetDevice::from_mmio(VIRTIO8) {
+        Some(device) => Arc::new(device),
+        None => Arc::new(NullNetDevice),
+    };
 }
 
 pub trait NetDevice: Send + Sync + Any {
     fn transmit(&self, data: &[u8]);
-    fn receive(&self, data: &mut [u8]) -> usize;
+    /// Take the oldest received frame, never blocks
+    fn receive(&self) -> Option<Vec<u8>>;
+    fn handle_irq(&self);
+    /// The hardware address frames are sent from
+    fn mac_address(&self) -> [u8; 6];
 }
 
-pub struct VirtIONetWrapper(UPIntrFreeCell<VirtIONet<'static, VirtioHal>>);
+/// Stands in for a missing network card: drops what is sent, never
+/// receives and has the all-zero MAC
+struct NullNetDevice;
 
-impl NetDevice for VirtIONetWrapper {
-    fn transmit(&self, data: &[u8]) {
-        self.0
-            .exclusive_access()
-            .send(data)
-            .expect("can't send data")
-    }
+impl NetDevice for NullNetDevice {
+    fn transmit(&self, _data: &[u8]) {}
 
-    fn receive(&self, data: &mut [u8]) -> usize {
-        self.0
-            .exclusive_access()
-            .recv(data)
-            .expect("can't receive data")
+    fn receive(&self) -> Option<Vec<u8>> {
+        None
     }
-}
 
-impl VirtIONetWrapper {
-    pub fn new() -> Self {
-        unsafe {
-            let virtio = VirtIONet::<VirtioHal>::new(&mut *(VIRTIO8 as *mut VirtIOHeader))
-                .expect("can't create net device by virtio");
-            VirtIONetWrapper(UPIntrFreeCell::new(virtio))
-        }
+    fn handle_irq(&self) {}
+
+    fn mac_address(&self) -> [u8; 6] {
+        [0; 6]
     }
 }
@@ -0,0 +1,318 @@
+//! A virtio-net driver for the legacy virtio-mmio transport.
+//!
+//! `virtio_drivers::VirtIONet` posts one receive buffer per call and spins
+//! until the device fills it. This driver keeps the receive queue stocked
+//! instead, so frames land in memory on their own and the device raises an
+//! interrupt for them.
+use super::NetDevice;
+use crate::config::PAGE_SIZE;
+use crate::drivers::virtio::VirtioHal;
+use crate::sync::SpinNoIrqLock;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+use log::warn;
+use virtio_drivers::Hal;
+
+// legacy virtio-mmio registers
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_HOST_FEATURES: usize = 0x010;
+const REG_GUEST_FEATURES: usize = 0x020;
+const REG_GUEST_PAGE_SIZE: usize = 0x028;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03c;
+const REG_QUEUE_PFN: usize = 0x040;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+/// Device specific config space, `virtio_net_config` starts with the MAC
+const REG_CONFIG: usize = 0x100;
+
+const VIRTIO_MAGIC: u32 = 0x7472_6976;
+const VIRTIO_ID_NET: u32 = 1;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+
+/// The device has a MAC address in its config space
+const VIRTIO_NET_F_MAC: u32 = 1 << 5;
+/// Used when the device has no MAC of its own, what QEMU assigns by default
+const DEFAULT_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+const RX_QUEUE: u32 = 0;
+const TX_QUEUE: u32 = 1;
+/// Descriptors per queue, every buffer takes two: header and frame
+const QUEUE_SIZE: usize = 16;
+const BUFFERS: usize = QUEUE_SIZE / 2;
+/// Size of `virtio_net_hdr` without mergeable receive buffers
+const NET_HDR_SIZE: usize = 10;
+/// Room for one header and one frame
+const BUF_SIZE: usize = 2048;
+/// Received frames kept until the stack takes them, later ones are dropped
+const MAX_QUEUED: usize = 256;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// A split virtqueue in the legacy layout: the descriptor table and the
+/// available ring come first, the used ring starts on the next page.
+/// Kernel memory is identically mapped, so addresses serve for the device too.
+struct VirtQueue {
+    base: usize,
+    used_offset: usize,
+    avail_idx: u16,
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    fn new() -> Self {
+        let used_offset = align_up(16 * QUEUE_SIZE + 6 + 2 * QUEUE_SIZE);
+        let size = used_offset + align_up(6 + 8 * QUEUE_SIZE);
+        Self {
+            base: VirtioHal::dma_alloc(size / PAGE_SIZE),
+            used_offset,
+            avail_idx: 0,
+            last_used_idx: 0,
+        }
+    }
+
+    fn desc(&self, index: usize) -> *mut Descriptor {
+        (self.base + 16 * index) as *mut Descriptor
+    }
+
+    fn avail_flags(&self) -> *mut u16 {
+        (self.base + 16 * QUEUE_SIZE) as *mut u16
+    }
+
+    /// Describe buffer `slot` of `buffers` as a header descriptor chained to a frame descriptor
+    fn set_buffer(&self, slot: usize, buffers: usize, frame_len: usize, device_writes: bool) {
+        let flags = if device_writes { DESC_F_WRITE } else { 0 };
+        let addr = buffers + slot * BUF_SIZE;
+        unsafe {
+            self.desc(2 * slot).write_volatile(Descriptor {
+                addr: addr as u64,
+                len: NET_HDR_SIZE as u32,
+                flags: flags | DESC_F_NEXT,
+                next: (2 * slot + 1) as u16,
+            });
+            self.desc(2 * slot + 1).write_volatile(Descriptor {
+                addr: (addr + NET_HDR_SIZE) as u64,
+                len: frame_len as u32,
+                flags,
+                next: 0,
+            });
+        }
+    }
+
+    /// Hand the chain starting at `head` to the device
+    fn push_avail(&mut self, head: usize) {
+        let ring = (self.base + 16 * QUEUE_SIZE + 4) as *mut u16;
+        let idx = (self.base + 16 * QUEUE_SIZE + 2) as *mut u16;
+        unsafe {
+            ring.add(self.avail_idx as usize % QUEUE_SIZE)
+                .write_volatile(head as u16);
+            // the ring entry must be visible before the index moves
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            idx.write_volatile(self.avail_idx);
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    /// Take one chain the device is done with, as (head, bytes written)
+    fn pop_used(&mut self) -> Option<(usize, usize)> {
+        let used = self.base + self.used_offset;
+        fence(Ordering::SeqCst);
+        let used_idx = unsafe { ((used + 2) as *const u16).read_volatile() };
+        if used_idx == self.last_used_idx {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let elem = used + 4 + 8 * (self.last_used_idx as usize % QUEUE_SIZE);
+        let (head, len) = unsafe {
+            (
+                (elem as *const u32).read_volatile(),
+                ((elem + 4) as *const u32).read_volatile(),
+            )
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        Some((head as usize, len as usize))
+    }
+}
+
+fn align_up(size: usize) -> usize {
+    (size + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE
+}
+
+struct VirtIONetInner {
+    base: usize,
+    rx: VirtQueue,
+    tx: VirtQueue,
+    rx_buffers: usize,
+    tx_buffers: usize,
+    /// transmit buffers not owned by the device
+    tx_free: Vec<usize>,
+    /// frames taken off the receive queue, oldest first
+    received: VecDeque<Vec<u8>>,
+}
+
+pub struct VirtIONetDevice {
+    mac: [u8; 6],
+    inner: SpinNoIrqLock<VirtIONetInner>,
+}
+
+impl VirtIONetInner {
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Tell the device where queue `index` lives
+    fn setup_queue(&self, index: u32, queue: &VirtQueue) -> Option<()> {
+        self.write_reg(REG_QUEUE_SEL, index);
+        if (self.read_reg(REG_QUEUE_NUM_MAX) as usize) < QUEUE_SIZE {
+            return None;
+        }
+        self.write_reg(REG_QUEUE_NUM, QUEUE_SIZE as u32);
+        self.write_reg(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+        self.write_reg(REG_QUEUE_PFN, (queue.base / PAGE_SIZE) as u32);
+        Some(())
+    }
+
+    /// Move transmit buffers the device has sent back to the free list
+    fn reclaim_tx(&mut self) {
+        while let Some((head, _)) = self.tx.pop_used() {
+            self.tx_free.push(head / 2);
+        }
+    }
+}
+
+impl VirtIONetDevice {
+    /// Reset and start the device at `base`, returning None if it is not virtio-net
+    pub fn from_mmio(base: usize) -> Option<Self> {
+        let read_reg = |offset: usize| unsafe { ((base + offset) as *const u32).read_volatile() };
+        if read_reg(REG_MAGIC) != VIRTIO_MAGIC
+            || read_reg(REG_VERSION) != 1
+            || read_reg(REG_DEVICE_ID) != VIRTIO_ID_NET
+        {
+            return None;
+        }
+        let mut inner = VirtIONetInner {
+            base,
+            rx: VirtQueue::new(),
+            tx: VirtQueue::new(),
+            rx_buffers: VirtioHal::dma_alloc(BUFFERS * BUF_SIZE / PAGE_SIZE),
+            tx_buffers: VirtioHal::dma_alloc(BUFFERS * BUF_SIZE / PAGE_SIZE),
+            tx_free: (0..BUFFERS).collect(),
+            received: VecDeque::new(),
+        };
+        inner.write_reg(REG_STATUS, 0);
+        inner.write_reg(REG_STATUS, STATUS_ACKNOWLEDGE);
+        inner.write_reg(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let features = inner.read_reg(REG_HOST_FEATURES) & VIRTIO_NET_F_MAC;
+        inner.write_reg(REG_GUEST_FEATURES, features);
+        let mut mac = DEFAULT_MAC;
+        if features & VIRTIO_NET_F_MAC != 0 {
+            for (i, byte) in mac.iter_mut().enumerate() {
+                *byte = unsafe { ((base + REG_CONFIG + i) as *const u8).read_volatile() };
+            }
+        }
+        inner.write_reg(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        inner.setup_queue(RX_QUEUE, &inner.rx)?;
+        inner.setup_queue(TX_QUEUE, &inner.tx)?;
+        // sent frames are reclaimed lazily, no need to interrupt for them
+        unsafe {
+            inner.tx.avail_flags().write_volatile(AVAIL_F_NO_INTERRUPT);
+        }
+        for slot in 0..BUFFERS {
+            inner
+                .rx
+                .set_buffer(slot, inner.rx_buffers, BUF_SIZE - NET_HDR_SIZE, true);
+            inner.rx.push_avail(2 * slot);
+        }
+        inner.write_reg(
+            REG_STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+        inner.write_reg(REG_QUEUE_NOTIFY, RX_QUEUE);
+        Some(Self {
+            mac,
+            inner: SpinNoIrqLock::new(inner),
+        })
+    }
+}
+
+impl NetDevice for VirtIONetDevice {
+    /// Frames that don't fit a buffer are dropped, not cut short. So are
+    /// frames sent while every buffer is still with the device, as a full
+    /// NIC would, rather than waiting with the lock held
+    fn transmit(&self, data: &[u8]) {
+        if data.len() > BUF_SIZE - NET_HDR_SIZE {
+            warn!("virtio-net: dropped a {} byte frame, too large to send", data.len());
+            return;
+        }
+        let mut inner = self.inner.lock();
+        inner.reclaim_tx();
+        let slot = match inner.tx_free.pop() {
+            Some(slot) => slot,
+            None => return,
+        };
+        let len = data.len();
+        let buffer = unsafe {
+            core::slice::from_raw_parts_mut((inner.tx_buffers + slot * BUF_SIZE) as *mut u8, BUF_SIZE)
+        };
+        buffer[..NET_HDR_SIZE].fill(0);
+        buffer[NET_HDR_SIZE..NET_HDR_SIZE + len].copy_from_slice(&data[..len]);
+        inner.tx.set_buffer(slot, inner.tx_buffers, len, false);
+        inner.tx.push_avail(2 * slot);
+        inner.write_reg(REG_QUEUE_NOTIFY, TX_QUEUE);
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        self.inner.lock().received.pop_front()
+    }
+
+    /// Copy every filled receive buffer out and give it back to the device.
+    /// Frames are dropped while `MAX_QUEUED` are waiting to be taken
+    fn handle_irq(&self) {
+        let mut inner = self.inner.lock();
+        let status = inner.read_reg(REG_INTERRUPT_STATUS);
+        inner.write_reg(REG_INTERRUPT_ACK, status);
+        let mut refilled = false;
+        while let Some((head, len)) = inner.rx.pop_used() {
+            if inner.received.len() < MAX_QUEUED {
+                let frame = (inner.rx_buffers + head / 2 * BUF_SIZE + NET_HDR_SIZE) as *const u8;
+                let frame_len = len.saturating_sub(NET_HDR_SIZE);
+                let data = unsafe { core::slice::from_raw_parts(frame, frame_len) }.to_vec();
+                inner.received.push_back(data);
+            }
+            inner.rx.push_avail(head);
+            refilled = true;
+        }
+        if refilled {
+            inner.write_reg(REG_QUEUE_NOTIFY, RX_QUEUE);
+        }
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac
+    }
+}
@@ -1,12 +1,36 @@
     .section .text.entry
     .globl _start
 _start:
+    # a0: hart id, a1: device tree
+    # harts past MAX_HARTS (8 in config.rs) have no boot stack or processor
+    li t0, 8
+    bgeu a0, t0, park
+    mv tp, a0
+    # hart n runs on the n-th boot stack from the top
     la sp, boot_stack_top
+    slli t0, a0, 16
+    sub sp, sp, t0
     call rust_main
 
+    .globl _start_secondary
+_start_secondary:
+    # a0: hart id, started through SBI HSM by the boot hart
+    li t0, 8
+    bgeu a0, t0, park
+    mv tp, a0
+    la sp, boot_stack_top
+    slli t0, a0, 16
+    sub sp, sp, t0
+    call rust_main_secondary
+
+park:
+    wfi
+    j park
+
     .section .bss.stack
     .globl boot_stack
 boot_stack:
-    .space 4096 * 16
+    # 64 KiB for each of MAX_HARTS
+    .space 4096 * 16 * 8
     .globl boot_stack_top
-boot_stack_top:
\ No newline at end of file
+boot_stack_top:
@@ -0,0 +1,269 @@
+//! A tiny device filesystem mounted at `/dev`.
+//!
+//! Every node is a thin `File` wrapper around a kernel device so that user
+//! programs can reach devices with plain open/read/write.
+use super::inode::EFS;
+use super::{File, OpenFlags, PollEvents};
+use crate::drivers::chardev::CharDevice;
+use crate::drivers::{BLOCK_DEVICE, GPU_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE, UART};
+use crate::drivers::input::InputDevice;
+use crate::mm::page_table::UserBuffer;
+use crate::net::pcap::PcapCapture;
+use crate::sync::{PollQueue, SpinNoIrqLock};
+use alloc::sync::Arc;
+use easy_fs::{get_block_cache, BLOCK_SZ};
+
+/// Open a device node, `name` is the path relative to `/dev/`
+pub fn open_dev(name: &str, flags: OpenFlags) -> Option<Arc<dyn File + Send + Sync>> {
+    let (readable, writable) = flags.read_write();
+    let file: Arc<dyn File + Send + Sync> = match name {
+        "null" => Arc::new(DevNull),
+        "zero" => Arc::new(DevZero),
+        "tty" | "console" => Arc::new(DevTty),
+        "fb0" => Arc::new(DevFrameBuffer::new(readable, writable)),
+        "input/keyboard" => Arc::new(DevInput::new(KEYBOARD_DEVICE.clone())),
+        "input/mouse" => Arc::new(DevInput::new(MOUSE_DEVICE.clone())),
+        "block" => Arc::new(DevBlock::new(readable, writable)),
+        "pcap" => Arc::new(PcapCapture::start()?),
+        _ => return None,
+    };
+    Some(file)
+}
+
+/// `/dev/null`: reads hit EOF, writes are discarded
+pub struct DevNull;
+
+impl File for DevNull {
+    fn readable(&self) -> bool { true }
+    fn writable(&self) -> bool { true }
+    fn read(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        buf.len()
+    }
+}
+
+/// `/dev/zero`: reads return zero bytes, writes are discarded
+pub struct DevZero;
+
+impl File for DevZero {
+    fn readable(&self) -> bool { true }
+    fn writable(&self) -> bool { true }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        for slice in buf.buffers.iter_mut() {
+            slice.fill(0);
+        }
+        buf.len()
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        buf.len()
+    }
+}
+
+/// `/dev/tty`: the serial console backed by `UART`
+pub struct DevTty;
+
+impl File for DevTty {
+    fn readable(&self) -> bool { true }
+    fn writable(&self) -> bool { true }
+    /// Block until one byte arrives, like `Stdin`
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        if buf.len() == 0 {
+            return 0;
+        }
+        let ch = UART.read();
+        buf.buffers[0][0] = ch;
+        1
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        for slice in buf.buffers.iter() {
+            for &ch in slice.iter() {
+                UART.write(ch);
+            }
+        }
+        buf.len()
+    }
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let mut ready = PollEvents::OUT;
+        if !UART.read_buffer_is_empty() {
+            ready |= PollEvents::IN;
+        }
+        ready & events
+    }
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        Some(UART.poll_queue())
+    }
+}
+
+/// `/dev/fb0`: the framebuffer of `GPU_DEVICE`, flushed after each write
+pub struct DevFrameBuffer {
+    readable: bool,
+    writable: bool,
+    offset: SpinNoIrqLock<usize>,
+}
+
+impl DevFrameBuffer {
+    pub fn new(readable: bool, writable: bool) -> Self {
+        Self {
+            readable,
+            writable,
+            offset: SpinNoIrqLock::new(0),
+        }
+    }
+}
+
+impl File for DevFrameBuffer {
+    fn readable(&self) -> bool { self.readable }
+    fn writable(&self) -> bool { self.writable }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let fb = GPU_DEVICE.get_framebuffer();
+        let mut offset = self.offset.lock();
+        let mut total = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let len = slice.len().min(fb.len() - *offset);
+            if len == 0 {
+                break;
+            }
+            slice[..len].copy_from_slice(&fb[*offset..*offset + len]);
+            *offset += len;
+            total += len;
+        }
+        total
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        let fb = GPU_DEVICE.get_framebuffer();
+        let mut offset = self.offset.lock();
+        let mut total = 0usize;
+        for slice in buf.buffers.iter() {
+            let len = slice.len().min(fb.len() - *offset);
+            if len == 0 {
+                break;
+            }
+            fb[*offset..*offset + len].copy_from_slice(&slice[..len]);
+            *offset += len;
+            total += len;
+        }
+        drop(offset);
+        GPU_DEVICE.flush();
+        total
+    }
+}
+
+/// `/dev/input/*`: a stream of 8-byte input events
+/// encoded as `type << 48 | code << 32 | value`
+pub struct DevInput {
+    device: Arc<dyn InputDevice>,
+}
+
+impl DevInput {
+    pub fn new(device: Arc<dyn InputDevice>) -> Self {
+        Self { device }
+    }
+}
+
+impl File for DevInput {
+    fn readable(&self) -> bool { true }
+    fn writable(&self) -> bool { false }
+    /// Block for the first event, then drain what is already queued
+    fn read(&self, buf: UserBuffer) -> usize {
+        let event_size = core::mem::size_of::<u64>();
+        let count = buf.len() / event_size;
+        let mut total = 0usize;
+        let mut iter = buf.into_iter();
+        while total < count {
+            if total > 0 && self.device.is_empty() {
+                break;
+            }
+            let event = self.device.read_event();
+            for byte in event.to_le_bytes() {
+                unsafe {
+                    *iter.next().unwrap() = byte;
+                }
+            }
+            total += 1;
+        }
+        total * event_size
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        if self.device.is_empty() {
+            PollEvents::empty()
+        } else {
+            events & PollEvents::IN
+        }
+    }
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        Some(self.device.poll_queue())
+    }
+}
+
+/// `/dev/block`: the raw block device underneath easy-fs
+pub struct DevBlock {
+    readable: bool,
+    writable: bool,
+    offset: SpinNoIrqLock<usize>,
+}
+
+impl DevBlock {
+    pub fn new(readable: bool, writable: bool) -> Self {
+        Self {
+            readable,
+            writable,
+            offset: SpinNoIrqLock::new(0),
+        }
+    }
+}
+
+impl File for DevBlock {
+    fn readable(&self) -> bool { self.readable }
+    fn writable(&self) -> bool { self.writable }
+    /// Stops at the end of the device, so reads past it return 0
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let mut offset = self.offset.lock();
+        let capacity = EFS.lock().total_blocks() * BLOCK_SZ;
+        let mut total = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let mut done = 0usize;
+            while done < slice.len() && *offset < capacity {
+                let start = *offset % BLOCK_SZ;
+                let len = (BLOCK_SZ - start).min(slice.len() - done);
+                // go through easy-fs's cache so we see its unwritten blocks
+                get_block_cache(*offset / BLOCK_SZ, BLOCK_DEVICE.clone())
+                    .lock()
+                    .read(0, |block: &[u8; BLOCK_SZ]| {
+                        slice[done..done + len].copy_from_slice(&block[start..start + len]);
+                    });
+                done += len;
+                *offset += len;
+            }
+            total += done;
+        }
+        total
+    }
+    /// Writes land in easy-fs's block cache, so the filesystem never keeps a
+    /// stale copy of a block or overwrites the new bytes when it syncs
+    fn write(&self, buf: UserBuffer) -> usize {
+        let mut offset = self.offset.lock();
+        let capacity = EFS.lock().total_blocks() * BLOCK_SZ;
+        let mut total = 0usize;
+        for slice in buf.buffers.iter() {
+            let mut done = 0usize;
+            while done < slice.len() && *offset < capacity {
+                let start = *offset % BLOCK_SZ;
+                let len = (BLOCK_SZ - start).min(slice.len() - done);
+                get_block_cache(*offset / BLOCK_SZ, BLOCK_DEVICE.clone())
+                    .lock()
+                    .modify(0, |block: &mut [u8; BLOCK_SZ]| {
+                        block[start..start + len].copy_from_slice(&slice[done..done + len]);
+                    });
+                done += len;
+                *offset += len;
+            }
+            total += done;
+        }
+        total
+    }
+}
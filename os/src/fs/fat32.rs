@@ -0,0 +1,315 @@
+//! A read-only FAT32 filesystem mounted at `/mnt`.
+//!
+//! It reads the first extra virtio-blk device sector by sector, so a disk
+//! image made on the host with `mkfs.vfat` and `mcopy` can be handed to the
+//! kernel without going through `easy-fs-fuse`.
+use super::procfs::ProcFile;
+use super::{File, OpenFlags};
+use crate::drivers::EXTRA_BLOCK_DEVICES;
+use crate::mm::page_table::UserBuffer;
+use crate::sync::SpinNoIrqLock;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use easy_fs::BlockDevice;
+use lazy_static::*;
+
+const SECTOR_SIZE: usize = 512;
+const DIR_ENTRY_SIZE: usize = 32;
+/// Cluster numbers at or above this value end a chain
+const FAT32_EOC: u32 = 0x0FFF_FFF8;
+
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+lazy_static! {
+    /// FAT32 volume on the first extra block device, if there is one
+    pub static ref FAT_ROOT: Option<Arc<Fat32FileSystem>> = EXTRA_BLOCK_DEVICES
+        .first()
+        .and_then(|(_, device)| Fat32FileSystem::open(device.clone()));
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+/// Geometry of a mounted FAT32 volume
+pub struct Fat32FileSystem {
+    device: Arc<dyn BlockDevice>,
+    sectors_per_cluster: usize,
+    /// first sector of FAT #0
+    fat_start: usize,
+    /// first sector of cluster 2
+    data_start: usize,
+    root_cluster: u32,
+    /// number of data clusters, they are numbered from 2
+    cluster_count: usize,
+}
+
+/// A directory entry with its long name resolved
+struct Fat32DirEntry {
+    name: String,
+    is_dir: bool,
+    first_cluster: u32,
+    size: usize,
+}
+
+impl Fat32FileSystem {
+    /// Parse the boot sector, looking through an MBR partition table if needed
+    pub fn open(device: Arc<dyn BlockDevice>) -> Option<Arc<Self>> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        device.read_block(0, &mut sector);
+        if read_u16(&sector, 510) != 0xAA55 {
+            return None;
+        }
+        let mut volume_start = 0usize;
+        if read_u16(&sector, 11) as usize != SECTOR_SIZE {
+            // not a boot sector, take the first FAT32 partition of the MBR
+            volume_start = (0..4)
+                .map(|i| 446 + i * 16)
+                .find(|entry| matches!(sector[entry + 4], 0x0B | 0x0C))
+                .map(|entry| read_u32(&sector, entry + 8) as usize)?;
+            device.read_block(volume_start, &mut sector);
+            if read_u16(&sector, 510) != 0xAA55 || read_u16(&sector, 11) as usize != SECTOR_SIZE {
+                return None;
+            }
+        }
+        let sectors_per_cluster = sector[13] as usize;
+        let reserved_sectors = read_u16(&sector, 14) as usize;
+        let num_fats = sector[16] as usize;
+        let root_entries = read_u16(&sector, 17);
+        let total_sectors = match read_u16(&sector, 19) {
+            0 => read_u32(&sector, 32) as usize,
+            total_sectors => total_sectors as usize,
+        };
+        let fat_size = read_u32(&sector, 36) as usize;
+        // FAT12/16 volumes have root entries and a 16-bit FAT size
+        if sectors_per_cluster == 0 || root_entries != 0 || fat_size == 0 {
+            return None;
+        }
+        let data_sectors = total_sectors.checked_sub(reserved_sectors + num_fats * fat_size)?;
+        // the FAT may have room for fewer clusters than the data area
+        let cluster_count = (data_sectors / sectors_per_cluster).min(fat_size * SECTOR_SIZE / 4 - 2);
+        let fat_start = volume_start + reserved_sectors;
+        Some(Arc::new(Self {
+            device,
+            sectors_per_cluster,
+            fat_start,
+            data_start: fat_start + num_fats * fat_size,
+            root_cluster: read_u32(&sector, 44),
+            cluster_count,
+        }))
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster * SECTOR_SIZE
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> usize {
+        self.data_start + (cluster as usize - 2) * self.sectors_per_cluster
+    }
+
+    /// Follow the FAT from `first` and collect the whole cluster chain. A
+    /// corrupt chain, one that loops or leaves the volume, is cut where it
+    /// goes wrong
+    fn cluster_chain(&self, first: u32) -> Vec<u32> {
+        let mut chain = Vec::new();
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut cached_sector = usize::MAX;
+        let mut cluster = first;
+        while (2..FAT32_EOC).contains(&cluster) {
+            // a chain can't be longer than the volume without a loop
+            if cluster as usize >= self.cluster_count + 2 || chain.len() == self.cluster_count {
+                break;
+            }
+            chain.push(cluster);
+            let offset = cluster as usize * 4;
+            let sector_id = self.fat_start + offset / SECTOR_SIZE;
+            if sector_id != cached_sector {
+                self.device.read_block(sector_id, &mut sector);
+                cached_sector = sector_id;
+            }
+            cluster = read_u32(&sector, offset % SECTOR_SIZE) & 0x0FFF_FFFF;
+        }
+        chain
+    }
+
+    /// Read from a file made of `clusters` starting at byte `offset`
+    fn read_at(&self, clusters: &[u32], offset: usize, buf: &mut [u8]) -> usize {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut done = 0usize;
+        while done < buf.len() {
+            let pos = offset + done;
+            let cluster = match clusters.get(pos / self.cluster_size()) {
+                Some(cluster) => *cluster,
+                None => break,
+            };
+            let in_cluster = pos % self.cluster_size();
+            let sector_id = self.cluster_sector(cluster) + in_cluster / SECTOR_SIZE;
+            let start = in_cluster % SECTOR_SIZE;
+            let len = (SECTOR_SIZE - start).min(buf.len() - done);
+            self.device.read_block(sector_id, &mut sector);
+            buf[done..done + len].copy_from_slice(&sector[start..start + len]);
+            done += len;
+        }
+        done
+    }
+
+    /// Parse every live entry of the directory starting at `first_cluster`
+    fn read_dir(&self, first_cluster: u32) -> Vec<Fat32DirEntry> {
+        let clusters = self.cluster_chain(first_cluster);
+        let mut data = alloc::vec![0u8; clusters.len() * self.cluster_size()];
+        self.read_at(&clusters, 0, &mut data);
+        let mut entries = Vec::new();
+        let mut long_name: Vec<u16> = Vec::new();
+        for raw in data.chunks(DIR_ENTRY_SIZE) {
+            match raw[0] {
+                0x00 => break,
+                0xE5 => {
+                    long_name.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            let attr = raw[11];
+            if attr == ATTR_LONG_NAME {
+                // long name pieces are stored last piece first
+                let mut piece: Vec<u16> = Vec::new();
+                for offset in (1..11).step_by(2).chain((14..26).step_by(2)).chain((28..32).step_by(2)) {
+                    piece.push(read_u16(raw, offset));
+                }
+                if raw[0] & 0x40 != 0 {
+                    long_name.clear();
+                }
+                piece.extend_from_slice(&long_name);
+                long_name = piece;
+                continue;
+            }
+            if attr & ATTR_VOLUME_ID != 0 {
+                long_name.clear();
+                continue;
+            }
+            let name = if long_name.is_empty() {
+                short_name(raw)
+            } else {
+                let end = long_name
+                    .iter()
+                    .position(|c| *c == 0x0000 || *c == 0xFFFF)
+                    .unwrap_or(long_name.len());
+                char::decode_utf16(long_name[..end].iter().cloned())
+                    .map(|c| c.unwrap_or('?'))
+                    .collect()
+            };
+            long_name.clear();
+            if name == "." || name == ".." {
+                continue;
+            }
+            entries.push(Fat32DirEntry {
+                name,
+                is_dir: attr & ATTR_DIRECTORY != 0,
+                first_cluster: (read_u16(raw, 20) as u32) << 16 | read_u16(raw, 26) as u32,
+                size: read_u32(raw, 28) as usize,
+            });
+        }
+        entries
+    }
+
+    /// Walk `path` from the root, names are matched case-insensitively
+    fn lookup(&self, path: &str) -> Option<Fat32DirEntry> {
+        let mut current = Fat32DirEntry {
+            name: String::new(),
+            is_dir: true,
+            first_cluster: self.root_cluster,
+            size: 0,
+        };
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if !current.is_dir {
+                return None;
+            }
+            current = self
+                .read_dir(current.first_cluster)
+                .into_iter()
+                .find(|entry| entry.name.eq_ignore_ascii_case(component))?;
+        }
+        Some(current)
+    }
+}
+
+/// Turn an 8.3 entry into `name.ext`
+fn short_name(raw: &[u8]) -> String {
+    let base = core::str::from_utf8(&raw[0..8]).unwrap_or("").trim_end();
+    let ext = core::str::from_utf8(&raw[8..11]).unwrap_or("").trim_end();
+    let mut name = String::from(base);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(ext);
+    }
+    name.to_ascii_lowercase()
+}
+
+/// A regular file on the FAT32 volume
+pub struct Fat32File {
+    fs: Arc<Fat32FileSystem>,
+    clusters: Vec<u32>,
+    size: usize,
+    offset: SpinNoIrqLock<usize>,
+}
+
+impl File for Fat32File {
+    fn readable(&self) -> bool { true }
+    fn writable(&self) -> bool { false }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let mut offset = self.offset.lock();
+        let mut total_read_size = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let len = slice.len().min(self.size - *offset);
+            if len == 0 {
+                break;
+            }
+            let read_size = self.fs.read_at(&self.clusters, *offset, &mut slice[..len]);
+            *offset += read_size;
+            total_read_size += read_size;
+            if read_size < len {
+                break;
+            }
+        }
+        total_read_size
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+}
+
+/// Open a FAT32 file, `path` is relative to `/mnt`.
+/// Opening a directory yields its entries, one per line.
+pub fn open_fat(path: &str, flags: OpenFlags) -> Option<Arc<dyn File + Send + Sync>> {
+    let (_, writable) = flags.read_write();
+    if writable || flags.contains(OpenFlags::CREATE) {
+        return None;
+    }
+    let fs = FAT_ROOT.as_ref()?.clone();
+    let entry = fs.lookup(path)?;
+    if entry.is_dir {
+        let mut listing = String::new();
+        for child in fs.read_dir(entry.first_cluster) {
+            listing.push_str(&child.name);
+            if child.is_dir {
+                listing.push('/');
+            }
+            listing.push('\n');
+        }
+        return Some(Arc::new(ProcFile::new(listing)));
+    }
+    let clusters = fs.cluster_chain(entry.first_cluster);
+    Some(Arc::new(Fat32File {
+        fs,
+        clusters,
+        size: entry.size,
+        offset: SpinNoIrqLock::new(0),
+    }))
+}
@@ -0,0 +1,78 @@
+//! Named pipes stored as FIFO inodes in easy-fs.
+//!
+//! Every opener of the same inode shares one `PipeRingBuffer`. Opening only
+//! the read end blocks until a writer shows up and vice versa, opening with
+//! `RDWR` never blocks.
+use super::pipe::{Pipe, PipeRingBuffer};
+use super::OpenFlags;
+use crate::sync::SpinNoIrqLock;
+use crate::task::schedule;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use easy_fs::Inode;
+use lazy_static::*;
+
+type FifoBuffer = Arc<SpinNoIrqLock<PipeRingBuffer>>;
+
+lazy_static! {
+    /// Ring buffers of the FIFOs that are currently open, keyed by disk inode position
+    static ref FIFO_TABLE: SpinNoIrqLock<BTreeMap<(usize, usize), Weak<SpinNoIrqLock<PipeRingBuffer>>>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+}
+
+/// Get the buffer shared by all openers of `inode`, creating it for the first one
+fn fifo_buffer(inode: &Inode) -> FifoBuffer {
+    let mut table = FIFO_TABLE.lock();
+    let key = inode.disk_inode_pos();
+    if let Some(buffer) = table.get(&key).and_then(|buffer| buffer.upgrade()) {
+        return buffer;
+    }
+    // drop entries whose ends have all been closed
+    table.retain(|_, buffer| buffer.strong_count() > 0);
+    let buffer = Arc::new(SpinNoIrqLock::new(PipeRingBuffer::new()));
+    table.insert(key, Arc::downgrade(&buffer));
+    buffer
+}
+
+/// Open a FIFO inode, blocking until the other side is opened as well
+pub fn open_fifo(inode: Arc<Inode>, flags: OpenFlags) -> Arc<Pipe> {
+    let (readable, writable) = flags.read_write();
+    let buffer = fifo_buffer(&inode);
+    let pipe = Arc::new(Pipe::new(readable, writable, buffer.clone()));
+    let mut ring_buffer = buffer.lock();
+    if readable {
+        ring_buffer.reader_opens += 1;
+    }
+    if writable {
+        ring_buffer.writer_opens += 1;
+    }
+    ring_buffer.open_condvar.broadcast();
+    if readable && writable {
+        return pipe;
+    }
+    // wait for a peer; remembering the open count lets us notice
+    // a peer that opened and closed again before we were scheduled
+    let peer_opens = |ring_buffer: &PipeRingBuffer| {
+        if readable {
+            ring_buffer.writer_opens
+        } else {
+            ring_buffer.reader_opens
+        }
+    };
+    let peers = |ring_buffer: &PipeRingBuffer| {
+        if readable {
+            ring_buffer.writers
+        } else {
+            ring_buffer.readers
+        }
+    };
+    let opens_before = peer_opens(&ring_buffer);
+    while peers(&ring_buffer) == 0 && peer_opens(&ring_buffer) == opens_before {
+        let task_cx_ptr = ring_buffer.open_condvar.wait_no_sched();
+        drop(ring_buffer);
+        schedule(task_cx_ptr);
+        ring_buffer = buffer.lock();
+    }
+    drop(ring_buffer);
+    pipe
+}
@@ -1,9 +1,10 @@
 use crate::drivers::BLOCK_DEVICE;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinNoIrqLock;
 use alloc::sync::Arc;
 use lazy_static::*;
 use bitflags::*;
 use alloc::vec::Vec;
+use spin::Mutex;
 use super::File;
 use crate::fs::UserBuffer;
 use easy_fs::{
@@ -17,10 +18,10 @@ use easy_fs::{
 pub struct OSInode {
     readable: bool,
     writable: bool,
-    inner: UPIntrFreeCell<OSInodeInner>,
+    inner: SpinNoIrqLock<OSInodeInner>,
 }
 
-/// The OS inode inner in 'UPIntrFreeCell'
+/// The OS inode inner in 'SpinNoIrqLock'
 pub struct OSInodeInner {
     offset: usize,
     inode: Arc<Inode>,
@@ -36,18 +37,16 @@ impl OSInode {
         Self {
             readable,
             writable,
-            inner: unsafe {
-                UPIntrFreeCell::new(OSInodeInner {
-                    offset: 0,
-                    inode,
-                })
-            },
+            inner: SpinNoIrqLock::new(OSInodeInner {
+                offset: 0,
+                inode,
+            }),
         }
     }
 
     /// Read all data inside a inode into vector
     pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
@@ -62,11 +61,10 @@ impl OSInode {
     }
 }
 lazy_static! {
+    /// The easy-fs on the block device
+    pub static ref EFS: Arc<Mutex<EasyFileSystem>> = EasyFileSystem::open(BLOCK_DEVICE.clone());
     /// The root of all inodes, or '/' in short
-    pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-        Arc::new(EasyFileSystem::root_inode(&efs))
-    };
+    pub static ref ROOT_INODE: Arc<Inode> = Arc::new(EasyFileSystem::root_inode(&EFS));
 }
 
 /// List all files in the filesystems
@@ -86,6 +84,8 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        /// set `FdFlags::CLOEXEC` on the new descriptor
+        const CLOEXEC = 1 << 19;
     }
 }
 impl OpenFlags {
@@ -131,7 +131,7 @@ impl File for OSInode {
     fn readable(&self) -> bool { self.readable }
     fn writable(&self) -> bool { self.writable }
     fn read(&self, mut buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         let mut total_read_size = 0usize;
         for slice in buf.buffers.iter_mut() {
             let read_size = inner.inode.read_at(inner.offset, *slice);
@@ -144,7 +144,7 @@ impl File for OSInode {
         total_read_size
     }
     fn write(&self, buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         let mut total_write_size = 0usize;
         for slice in buf.buffers.iter() {
             let write_size = inner.inode.write_at(inner.offset, *slice);
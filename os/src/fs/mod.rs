@@ -1,8 +1,16 @@
 mod stdio;
 mod inode;
 mod pipe;
+mod fifo;
+mod devfs;
+mod procfs;
+mod tmpfs;
+mod fat32;
 
 use crate::mm::page_table::UserBuffer;
+use crate::net::socket::Socket;
+use crate::sync::PollQueue;
+use alloc::sync::Arc;
 
 /// The common abstraction of all IO resources
 pub trait File : Send + Sync {
@@ -10,6 +18,62 @@ pub trait File : Send + Sync {
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
+    /// Which of `events` would not block right now.
+    /// Files that never block are always ready in the directions they are open for.
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let mut ready = PollEvents::empty();
+        if self.readable() {
+            ready |= PollEvents::IN;
+        }
+        if self.writable() {
+            ready |= PollEvents::OUT;
+        }
+        ready & events
+    }
+    /// The queue `poll` sleeps on until this file may have become ready
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        None
+    }
+    /// The socket behind this file, for the socket syscalls
+    fn as_socket(&self) -> Option<&Socket> {
+        None
+    }
+}
+
+bitflags! {
+    /// Events of `poll`, the same bits as Linux `POLL*`
+    pub struct PollEvents: u16 {
+        /// there is data to read
+        const IN    = 0x001;
+        /// writing would not block
+        const OUT   = 0x004;
+        /// error condition, e.g. the read end of a pipe is gone
+        const ERR   = 0x008;
+        /// hang up, e.g. the write end of a pipe is gone
+        const HUP   = 0x010;
+        /// the fd is not open
+        const NVAL  = 0x020;
+    }
+}
+
+bitflags! {
+    /// Per-descriptor flags, kept beside the fd table of a process
+    pub struct FdFlags: u32 {
+        /// close the descriptor when the process calls exec
+        const CLOEXEC = 1;
+    }
+}
+
+/// One entry of the array passed to `poll`
+#[repr(C)]
+#[derive(Debug)]
+pub struct PollFd {
+    /// file descriptor, negative entries are skipped
+    pub fd: i32,
+    /// requested `PollEvents`
+    pub events: i16,
+    /// returned `PollEvents`
+    pub revents: i16,
 }
 
 /// The stat of a inode
@@ -37,10 +101,52 @@ bitflags! {
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// named pipe
+        const FIFO  = 0o010000;
     }
 }
 
 pub use stdio::{Stdin, Stdout};
-pub use inode::{OSInode, open_file, OpenFlags, list_apps};
+pub use inode::{OSInode, open_file, OpenFlags, list_apps, ROOT_INODE};
 pub use pipe::{Pipe, make_pipe};
 
+/// Open a file by absolute or relative path.
+/// Paths under a mount point are routed to that filesystem,
+/// everything else lives in the easy-fs root directory.
+pub fn open(path: &str, flags: OpenFlags) -> Option<Arc<dyn File + Send + Sync>> {
+    if let Some(name) = path.strip_prefix("/dev/") {
+        return devfs::open_dev(name, flags);
+    }
+    if let Some(name) = path.strip_prefix("/tmp/") {
+        return tmpfs::open_tmp(name, flags).map(|file| file as Arc<dyn File + Send + Sync>);
+    }
+    if path == "/mnt" || path.starts_with("/mnt/") {
+        return fat32::open_fat(&path["/mnt".len()..], flags);
+    }
+    if path == "/proc" || path.starts_with("/proc/") {
+        return procfs::open_proc(&path["/proc".len()..], flags);
+    }
+    let name = path.trim_start_matches('/');
+    if let Some(inode) = ROOT_INODE.find(name) {
+        if inode.is_fifo() {
+            return Some(fifo::open_fifo(inode, flags));
+        }
+    }
+    open_file(name, flags).map(|inode| inode as Arc<dyn File + Send + Sync>)
+}
+
+/// Create a named pipe, only easy-fs can persist one
+pub fn mkfifo(path: &str) -> bool {
+    if ["/dev/", "/proc", "/tmp/", "/mnt"].iter().any(|mount| path.starts_with(mount)) {
+        return false;
+    }
+    ROOT_INODE.create_fifo(path.trim_start_matches('/')).is_some()
+}
+
+/// Remove a file by path, only tmpfs supports it for now
+pub fn unlink(path: &str) -> bool {
+    match path.strip_prefix("/tmp/") {
+        Some(name) => tmpfs::unlink_tmp(name),
+        None => false,
+    }
+}
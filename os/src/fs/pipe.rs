@@ -1,32 +1,60 @@
-use super::File;
-use alloc::sync::{Arc, Weak};
+use super::{File, PollEvents};
+use alloc::sync::Arc;
 use crate::mm::page_table::UserBuffer;
-use crate::sync::UPIntrFreeCell;
-use crate::task::suspend_current_and_run_next;
+use crate::config::PIPE_BUFFER_SIZE;
+use crate::sync::{Condvar, PollQueue, SpinNoIrqLock};
+use crate::task::{current_add_signal, schedule, SignalFlags};
+use alloc::vec;
+use alloc::vec::Vec;
 
-
-const RING_BUFFER_SIZE: usize = 32;
+/// Returned by `write` when no read end is left, `sys_write` sees it as -EPIPE
+const EPIPE: isize = 32;
 
 pub struct Pipe {
     readable: bool,
     writable: bool,
-    buffer: Arc<UPIntrFreeCell<PipeRingBuffer>>,
+    buffer: Arc<SpinNoIrqLock<PipeRingBuffer>>,
 }
 
 impl Pipe {
-    pub fn read_end_with_buffer(buffer: Arc<UPIntrFreeCell<PipeRingBuffer>>) -> Self {
+    /// Open an end on `buffer`, counting it as a reader and/or writer
+    pub fn new(readable: bool, writable: bool, buffer: Arc<SpinNoIrqLock<PipeRingBuffer>>) -> Self {
+        let mut ring_buffer = buffer.lock();
+        if readable {
+            ring_buffer.readers += 1;
+        }
+        if writable {
+            ring_buffer.writers += 1;
+        }
+        drop(ring_buffer);
         Self {
-            readable: true,
-            writable: false,
+            readable,
+            writable,
             buffer,
         }
     }
-    pub fn write_end_with_buffer(buffer: Arc<UPIntrFreeCell<PipeRingBuffer>>) -> Self {
-        Self {
-            readable: false,
-            writable: true,
-            buffer,
+    pub fn read_end_with_buffer(buffer: Arc<SpinNoIrqLock<PipeRingBuffer>>) -> Self {
+        Self::new(true, false, buffer)
+    }
+    pub fn write_end_with_buffer(buffer: Arc<SpinNoIrqLock<PipeRingBuffer>>) -> Self {
+        Self::new(false, true, buffer)
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let mut ring_buffer = self.buffer.lock();
+        if self.readable {
+            ring_buffer.readers -= 1;
+            // blocked writers should notice the broken pipe
+            ring_buffer.write_condvar.broadcast();
         }
+        if self.writable {
+            ring_buffer.writers -= 1;
+            // blocked readers should notice EOF
+            ring_buffer.read_condvar.broadcast();
+        }
+        ring_buffer.poll_queue.wake_all();
     }
 }
 
@@ -38,31 +66,49 @@ enum RingBufferStatus {
 }
 
 pub struct PipeRingBuffer {
-    arr: [u8; RING_BUFFER_SIZE],
+    arr: Vec<u8>,
     head: usize,
     tail: usize,
     status: RingBufferStatus,
-    write_end: Option<Weak<Pipe>>,
+    /// number of open read ends
+    pub readers: usize,
+    /// number of open write ends
+    pub writers: usize,
+    /// how many times a read/write end has been opened, used by FIFO rendezvous
+    pub reader_opens: usize,
+    pub writer_opens: usize,
+    /// FIFO openers waiting for the other side
+    pub open_condvar: Condvar,
+    /// readers waiting for data
+    read_condvar: Condvar,
+    /// writers waiting for space
+    write_condvar: Condvar,
+    /// tasks in `poll` waiting for either end to become ready
+    poll_queue: Arc<PollQueue>,
 }
 
 impl PipeRingBuffer {
     pub fn new() -> Self {
         Self {
-            arr: [0; RING_BUFFER_SIZE],
+            arr: vec![0; PIPE_BUFFER_SIZE],
             head: 0,
             tail: 0,
             status: RingBufferStatus::EMPTY,
-            write_end: None,
+            readers: 0,
+            writers: 0,
+            reader_opens: 0,
+            writer_opens: 0,
+            open_condvar: Condvar::new(),
+            read_condvar: Condvar::new(),
+            write_condvar: Condvar::new(),
+            poll_queue: Arc::new(PollQueue::new()),
         }
     }
 
-    pub fn set_write_end(&mut self, write_end: &Arc<Pipe>) {
-        self.write_end = Some(Arc::downgrade(write_end));
-    }
     pub fn write_byte(&mut self, byte: u8) {
         self.status = RingBufferStatus::NORMAL;
         self.arr[self.tail] = byte;
-        self.tail = (self.tail + 1) % RING_BUFFER_SIZE;
+        self.tail = (self.tail + 1) % PIPE_BUFFER_SIZE;
         if self.tail == self.head {
             self.status = RingBufferStatus::FULL;
         }
@@ -70,7 +116,7 @@ impl PipeRingBuffer {
     pub fn read_byte(&mut self) -> u8 {
         self.status = RingBufferStatus::NORMAL;
         let c = self.arr[self.head];
-        self.head = (self.head + 1) % RING_BUFFER_SIZE;
+        self.head = (self.head + 1) % PIPE_BUFFER_SIZE;
         if self.head == self.tail {
             self.status = RingBufferStatus::EMPTY;
         }
@@ -86,7 +132,7 @@ impl PipeRingBuffer {
             if self.tail > self.head {
                 self.tail - self.head
             } else {
-                self.tail + RING_BUFFER_SIZE - self.head
+                self.tail + PIPE_BUFFER_SIZE - self.head
             }
         }
     }
@@ -95,25 +141,26 @@ impl PipeRingBuffer {
         if self.status == RingBufferStatus::FULL {
             0
         } else {
-            RING_BUFFER_SIZE - self.available_read()
+            PIPE_BUFFER_SIZE - self.available_read()
         }
     }
 
 
     /// Check if all write ends bounded to this buffer are closed
     pub fn all_write_ends_closed(&self) -> bool {
-        self.write_end.as_ref().unwrap().upgrade().is_none()
+        self.writers == 0
+    }
+    /// Check if all read ends bounded to this buffer are closed
+    pub fn all_read_ends_closed(&self) -> bool {
+        self.readers == 0
     }
 }
 
 /// Crate a pipe return (read_end, write_end)
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
-    let buffer = Arc::new(unsafe {
-        UPIntrFreeCell::new(PipeRingBuffer::new())
-    });
+    let buffer = Arc::new(SpinNoIrqLock::new(PipeRingBuffer::new()));
     let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
-    let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
-    buffer.exclusive_access().set_write_end(&write_end);
+    let write_end = Arc::new(Pipe::write_end_with_buffer(buffer));
     (read_end, write_end)
 }
 
@@ -121,19 +168,24 @@ pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
 impl File for Pipe {
     fn readable(&self) -> bool { self.readable }
     fn writable(&self) -> bool { self.writable }
+    /// Block until some data arrives, then return what is buffered.
+    /// Returns 0 once the buffer is empty and every write end is closed.
     fn read(&self, buf: UserBuffer) -> usize {
         assert_eq!(self.readable(), true);
+        let want_size = buf.len();
         let mut buf_iter = buf.into_iter();
         let mut read_size = 0usize;
-        loop {
-            let mut ring_buffer = self.buffer.exclusive_access();
+        let mut ring_buffer = self.buffer.lock();
+        while read_size < want_size {
             let loop_read = ring_buffer.available_read();
             if loop_read == 0 {
-                if ring_buffer.all_write_ends_closed() {
-                    return read_size;
+                if read_size > 0 || ring_buffer.all_write_ends_closed() {
+                    break;
                 }
+                let task_cx_ptr = ring_buffer.read_condvar.wait_no_sched();
                 drop(ring_buffer);
-                suspend_current_and_run_next();
+                schedule(task_cx_ptr);
+                ring_buffer = self.buffer.lock();
                 continue;
             }
             // read at most loop_read bytes
@@ -142,21 +194,34 @@ impl File for Pipe {
                     unsafe { *byte_ref = ring_buffer.read_byte(); }
                     read_size += 1;
                 } else {
-                    return read_size;
+                    break;
                 }
             }
+            ring_buffer.write_condvar.broadcast();
+            ring_buffer.poll_queue.wake_all();
         }
+        read_size
     }
+    /// Block until the whole buffer is written.
+    /// Writing with no read end left raises SIGPIPE and fails with EPIPE.
     fn write(&self, buf: UserBuffer) -> usize {
         assert_eq!(self.writable(), true);
+        let want_size = buf.len();
         let mut buf_iter = buf.into_iter();
         let mut write_size = 0usize;
-        loop {
-            let mut ring_buffer = self.buffer.exclusive_access();
+        let mut ring_buffer = self.buffer.lock();
+        while write_size < want_size {
+            if ring_buffer.all_read_ends_closed() {
+                drop(ring_buffer);
+                current_add_signal(SignalFlags::SIGPIPE);
+                return if write_size > 0 { write_size } else { (-EPIPE) as usize };
+            }
             let loop_write = ring_buffer.available_write();
             if loop_write == 0 {
+                let task_cx_ptr = ring_buffer.write_condvar.wait_no_sched();
                 drop(ring_buffer);
-                suspend_current_and_run_next();
+                schedule(task_cx_ptr);
+                ring_buffer = self.buffer.lock();
                 continue;
             }
             // write at most loop_write bytes
@@ -165,11 +230,36 @@ impl File for Pipe {
                     ring_buffer.write_byte(unsafe { *byte_ref });
                     write_size += 1;
                 } else {
-                    return write_size;
+                    break;
                 }
             }
+            ring_buffer.read_condvar.broadcast();
+            ring_buffer.poll_queue.wake_all();
         }
+        write_size
+    }
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let ring_buffer = self.buffer.lock();
+        let mut ready = PollEvents::empty();
+        if self.readable {
+            if ring_buffer.available_read() > 0 {
+                ready |= PollEvents::IN;
+            }
+            if ring_buffer.all_write_ends_closed() {
+                ready |= PollEvents::HUP;
+            }
+        }
+        if self.writable {
+            if ring_buffer.all_read_ends_closed() {
+                ready |= PollEvents::ERR;
+            } else if ring_buffer.available_write() > 0 {
+                ready |= PollEvents::OUT;
+            }
+        }
+        // errors and hang-ups are reported even when not asked for
+        ready & (events | PollEvents::ERR | PollEvents::HUP)
+    }
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        Some(self.buffer.lock().poll_queue.clone())
     }
 }
-
-
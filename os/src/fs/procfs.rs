@@ -0,0 +1,221 @@
+//! A process information filesystem mounted at `/proc`.
+//!
+//! Files are rendered as text when they are opened, so a reader always
+//! sees a consistent snapshot. Opening `/proc` itself lists the entries.
+use super::{File, OpenFlags};
+use crate::board::{irq_count, IRQ_NAMES};
+use crate::config::PAGE_SIZE;
+use crate::drivers::EXTRA_BLOCK_DEVICES;
+use crate::mm::page_table::UserBuffer;
+use crate::mm::{frame_stats, MapPermission, MapType};
+use crate::net::{arp, iface};
+use crate::smp::online_harts;
+use crate::sync::SpinNoIrqLock;
+use crate::task::manager::PID2PCB;
+use crate::task::process::ProcessControlBlock;
+use crate::task::processor::task_switches;
+use crate::task::{current_process, TaskStatus};
+use crate::timer::{get_time_ms, timer_interrupts};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// Global files under `/proc`
+const PROC_GLOBAL_FILES: [&str; 6] =
+    ["meminfo", "uptime", "interrupts", "cpuinfo", "net/iface", "net/arp"];
+
+/// Open a proc file, `name` is the path relative to `/proc`
+pub fn open_proc(name: &str, flags: OpenFlags) -> Option<Arc<dyn File + Send + Sync>> {
+    let (_, writable) = flags.read_write();
+    if writable || flags.contains(OpenFlags::CREATE) {
+        return None;
+    }
+    let name = name.trim_matches('/');
+    let content = match name {
+        "" => list_entries(),
+        "meminfo" => meminfo(),
+        "uptime" => uptime(),
+        "interrupts" => interrupts(),
+        "cpuinfo" => cpuinfo(),
+        "net/iface" => iface::describe(),
+        "net/arp" => arp::describe(),
+        _ => {
+            let (pid, file) = name.split_once('/')?;
+            let pid = if pid == "self" {
+                current_process().getpid()
+            } else {
+                pid.parse::<usize>().ok()?
+            };
+            let process = PID2PCB.lock().get(&pid).cloned()?;
+            match file {
+                "status" => process_status(&process),
+                _ => return None,
+            }
+        }
+    };
+    Some(Arc::new(ProcFile::new(content)))
+}
+
+/// A read-only file holding text generated at open time
+pub struct ProcFile {
+    content: String,
+    offset: SpinNoIrqLock<usize>,
+}
+
+impl ProcFile {
+    pub fn new(content: String) -> Self {
+        Self {
+            content,
+            offset: SpinNoIrqLock::new(0),
+        }
+    }
+}
+
+impl File for ProcFile {
+    fn readable(&self) -> bool { true }
+    fn writable(&self) -> bool { false }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let content = self.content.as_bytes();
+        let mut offset = self.offset.lock();
+        let mut total = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let len = slice.len().min(content.len() - *offset);
+            if len == 0 {
+                break;
+            }
+            slice[..len].copy_from_slice(&content[*offset..*offset + len]);
+            *offset += len;
+            total += len;
+        }
+        total
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+}
+
+/// One entry per line: the global files followed by every live pid
+fn list_entries() -> String {
+    let mut s = String::new();
+    for name in PROC_GLOBAL_FILES {
+        writeln!(s, "{}", name).unwrap();
+    }
+    for pid in PID2PCB.lock().keys() {
+        writeln!(s, "{}", pid).unwrap();
+    }
+    s
+}
+
+fn meminfo() -> String {
+    let (total, free) = frame_stats();
+    let kb = PAGE_SIZE / 1024;
+    let mut s = String::new();
+    writeln!(s, "MemTotal:\t{} kB", total * kb).unwrap();
+    writeln!(s, "MemFree:\t{} kB", free * kb).unwrap();
+    writeln!(s, "MemUsed:\t{} kB", (total - free) * kb).unwrap();
+    writeln!(s, "FramesTotal:\t{}", total).unwrap();
+    writeln!(s, "FramesFree:\t{}", free).unwrap();
+    s
+}
+
+fn uptime() -> String {
+    let ms = get_time_ms();
+    let mut s = String::new();
+    writeln!(s, "{}.{:02}", ms / 1000, ms % 1000 / 10).unwrap();
+    s
+}
+
+fn interrupts() -> String {
+    let mut s = String::new();
+    writeln!(s, "timer:\t{}", timer_interrupts()).unwrap();
+    for &(irq, name) in IRQ_NAMES {
+        writeln!(s, "{}:\t{}\t{}", irq, irq_count(irq), name).unwrap();
+    }
+    for (irq, _) in EXTRA_BLOCK_DEVICES.iter() {
+        writeln!(s, "{}:\t{}\tvirtio-blk", irq, irq_count(*irq)).unwrap();
+    }
+    s
+}
+
+fn cpuinfo() -> String {
+    let mut s = String::new();
+    for (processor, hart) in online_harts().enumerate() {
+        writeln!(s, "processor\t: {}", processor).unwrap();
+        writeln!(s, "hart\t\t: {}", hart).unwrap();
+        writeln!(s, "isa\t\t: rv64imafdc").unwrap();
+        writeln!(s, "mmu\t\t: sv39").unwrap();
+        writeln!(s, "switches\t: {}", task_switches(hart)).unwrap();
+        writeln!(s).unwrap();
+    }
+    s
+}
+
+fn process_status(process: &Arc<ProcessControlBlock>) -> String {
+    let inner = process.inner_exclusive_access();
+    let ppid = inner
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(0, |parent| parent.getpid());
+    let statuses: Vec<TaskStatus> = inner
+        .tasks
+        .iter()
+        .flatten()
+        .map(|task| task.inner_exclusive_access().task_status)
+        .collect();
+    let state = if inner.is_zombie {
+        "Z (zombie)"
+    } else if statuses.iter().any(|status| *status == TaskStatus::Running) {
+        "R (running)"
+    } else if statuses.iter().any(|status| *status == TaskStatus::Ready) {
+        "R (ready)"
+    } else {
+        "S (sleeping)"
+    };
+    let mut s = String::new();
+    writeln!(s, "Name:\t{}", inner.name).unwrap();
+    writeln!(s, "State:\t{}", state).unwrap();
+    writeln!(s, "Pid:\t{}", process.getpid()).unwrap();
+    writeln!(s, "PPid:\t{}", ppid).unwrap();
+    writeln!(s, "Threads:\t{}", statuses.len()).unwrap();
+    writeln!(s, "SigPnd:\t{:#010x}", inner.signals.bits()).unwrap();
+    writeln!(s, "SigBlk:\t{:#010x}", inner.signal_mask.bits()).unwrap();
+    write!(s, "Fds:\t").unwrap();
+    for (fd, file) in inner.fd_table.iter().enumerate() {
+        if let Some(file) = file {
+            let r = if file.readable() { "r" } else { "-" };
+            let w = if file.writable() { "w" } else { "-" };
+            write!(s, "{}:{}{} ", fd, r, w).unwrap();
+        }
+    }
+    writeln!(s).unwrap();
+    let areas = inner.memory_set.areas();
+    let frames: usize = areas.iter().map(|area| area.frame_count()).sum();
+    writeln!(s, "VmRSS:\t{} kB", frames * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "VmAreas:\t{}", areas.len()).unwrap();
+    for area in areas {
+        let (start, end) = area.va_range();
+        let perm = area.map_perm();
+        let flag = |bit: MapPermission, c: char| if perm.contains(bit) { c } else { '-' };
+        let map_type = match area.map_type() {
+            MapType::Identical => "identical",
+            MapType::Framed => "framed",
+            MapType::Linear(_) => "linear",
+            MapType::Noalloc => "device",
+        };
+        writeln!(
+            s,
+            "{:#010x}-{:#010x} {}{}{}{} {}",
+            start.0,
+            end.0,
+            flag(MapPermission::R, 'r'),
+            flag(MapPermission::W, 'w'),
+            flag(MapPermission::X, 'x'),
+            flag(MapPermission::U, 'u'),
+            map_type,
+        )
+        .unwrap();
+    }
+    s
+}
@@ -1,6 +1,8 @@
 use crate::drivers::chardev::CharDevice;
 use crate::drivers::UART;
-use super::File;
+use super::{File, PollEvents};
+use crate::sync::PollQueue;
+use alloc::sync::Arc;
 use crate::mm::page_table::UserBuffer;
 use crate::sbi::console_getchar;
 use crate::task::suspend_current_and_run_next;
@@ -42,6 +44,16 @@ impl File for Stdin {
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        if UART.read_buffer_is_empty() {
+            PollEvents::empty()
+        } else {
+            events & PollEvents::IN
+        }
+    }
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        Some(UART.poll_queue())
+    }
 }
 
 impl File for Stdout {
@@ -0,0 +1,232 @@
+//! A RAM-backed filesystem mounted at `/tmp`.
+//!
+//! Like easy-fs it has a single flat root directory. File data lives in
+//! physical frames taken from the frame allocator, and the whole filesystem
+//! may hold at most `1 / TMPFS_FRAME_SHARE` of all frames.
+use super::{File, OpenFlags};
+use crate::config::{PAGE_SIZE, TMPFS_FRAME_SHARE};
+use crate::mm::page_table::UserBuffer;
+use crate::mm::{frame_alloc, frame_stats, FrameTracker};
+use crate::sync::SpinNoIrqLock;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+
+/// Frames currently held by all tmpfs files
+static TMPFS_USED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Take one frame for file data if tmpfs is still under its limit
+fn tmpfs_frame_alloc() -> Option<FrameTracker> {
+    let (total, _) = frame_stats();
+    // reserve the slot first so harts racing here can't pass the limit together
+    TMPFS_USED_FRAMES
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            (used < total / TMPFS_FRAME_SHARE).then_some(used + 1)
+        })
+        .ok()?;
+    let frame = frame_alloc();
+    if frame.is_none() {
+        TMPFS_USED_FRAMES.fetch_sub(1, Ordering::Relaxed);
+    }
+    frame
+}
+
+/// The root directory of tmpfs
+pub struct TmpDir {
+    files: SpinNoIrqLock<BTreeMap<String, Arc<TmpInode>>>,
+}
+
+impl TmpDir {
+    pub fn new() -> Self {
+        Self {
+            files: SpinNoIrqLock::new(BTreeMap::new()),
+        }
+    }
+    /// Find inode under the root directory by name
+    pub fn find(&self, name: &str) -> Option<Arc<TmpInode>> {
+        self.files.lock().get(name).cloned()
+    }
+    /// Create inode under the root directory by name
+    pub fn create(&self, name: &str) -> Option<Arc<TmpInode>> {
+        let mut files = self.files.lock();
+        if name.is_empty() || name.contains('/') || files.contains_key(name) {
+            return None;
+        }
+        let inode = Arc::new(TmpInode::new());
+        files.insert(String::from(name), inode.clone());
+        Some(inode)
+    }
+    /// Remove a file, its frames are freed once the last opener closes it
+    pub fn unlink(&self, name: &str) -> bool {
+        self.files.lock().remove(name).is_some()
+    }
+    /// List inodes under the root directory
+    #[allow(unused)]
+    pub fn ls(&self) -> Vec<String> {
+        self.files.lock().keys().cloned().collect()
+    }
+}
+
+/// A regular tmpfs file backed by whole frames
+pub struct TmpInode {
+    inner: SpinNoIrqLock<TmpInodeInner>,
+}
+
+struct TmpInodeInner {
+    size: usize,
+    frames: Vec<FrameTracker>,
+}
+
+impl TmpInode {
+    fn new() -> Self {
+        Self {
+            inner: SpinNoIrqLock::new(TmpInodeInner {
+                size: 0,
+                frames: Vec::new(),
+            }),
+        }
+    }
+    /// Read data from current inode
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let inner = self.inner.lock();
+        let end = (offset + buf.len()).min(inner.size);
+        let mut pos = offset;
+        while pos < end {
+            let page = inner.frames[pos / PAGE_SIZE].ppn.get_bytes_array();
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            buf[pos - offset..pos - offset + len]
+                .copy_from_slice(&page[page_offset..page_offset + len]);
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+    /// Write data to current inode, growing it when needed.
+    /// Returns how many bytes fit before tmpfs ran out of space.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut inner = self.inner.lock();
+        let end = offset + buf.len();
+        while inner.frames.len() * PAGE_SIZE < end {
+            match tmpfs_frame_alloc() {
+                Some(frame) => inner.frames.push(frame),
+                None => break,
+            }
+        }
+        let end = end.min(inner.frames.len() * PAGE_SIZE);
+        let mut pos = offset;
+        while pos < end {
+            let page = inner.frames[pos / PAGE_SIZE].ppn.get_bytes_array();
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            page[page_offset..page_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        inner.size = inner.size.max(end);
+        end.saturating_sub(offset)
+    }
+    /// Clear the data in current inode
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        TMPFS_USED_FRAMES.fetch_sub(inner.frames.len(), Ordering::Relaxed);
+        inner.frames.clear();
+        inner.size = 0;
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+lazy_static! {
+    /// The root of tmpfs, or '/tmp' in short
+    pub static ref TMP_ROOT: TmpDir = TmpDir::new();
+}
+
+/// A wrapper around a tmpfs inode to implement File trait atop
+pub struct TmpFile {
+    readable: bool,
+    writable: bool,
+    inner: SpinNoIrqLock<TmpFileInner>,
+}
+
+struct TmpFileInner {
+    offset: usize,
+    inode: Arc<TmpInode>,
+}
+
+impl TmpFile {
+    pub fn new(readable: bool, writable: bool, inode: Arc<TmpInode>) -> Self {
+        Self {
+            readable,
+            writable,
+            inner: SpinNoIrqLock::new(TmpFileInner { offset: 0, inode }),
+        }
+    }
+}
+
+/// Remove a tmpfs file, `name` is the path relative to `/tmp/`
+pub fn unlink_tmp(name: &str) -> bool {
+    TMP_ROOT.unlink(name)
+}
+
+/// Open a tmpfs file, `name` is the path relative to `/tmp/`
+pub fn open_tmp(name: &str, flags: OpenFlags) -> Option<Arc<TmpFile>> {
+    let (readable, writable) = flags.read_write();
+    if flags.contains(OpenFlags::CREATE) {
+        if let Some(inode) = TMP_ROOT.find(name) {
+            // clear size
+            inode.clear();
+            Some(Arc::new(TmpFile::new(readable, writable, inode)))
+        } else {
+            // create file
+            TMP_ROOT
+                .create(name)
+                .map(|inode| Arc::new(TmpFile::new(readable, writable, inode)))
+        }
+    } else {
+        TMP_ROOT.find(name).map(|inode| {
+            if flags.contains(OpenFlags::TRUNC) {
+                inode.clear();
+            }
+            Arc::new(TmpFile::new(readable, writable, inode))
+        })
+    }
+}
+
+impl File for TmpFile {
+    fn readable(&self) -> bool { self.readable }
+    fn writable(&self) -> bool { self.writable }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let mut inner = self.inner.lock();
+        let mut total_read_size = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let read_size = inner.inode.read_at(inner.offset, *slice);
+            if read_size == 0 {
+                break;
+            }
+            inner.offset += read_size;
+            total_read_size += read_size;
+        }
+        total_read_size
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        let mut inner = self.inner.lock();
+        let mut total_write_size = 0usize;
+        for slice in buf.buffers.iter() {
+            let write_size = inner.inode.write_at(inner.offset, *slice);
+            inner.offset += write_size;
+            total_write_size += write_size;
+            if write_size < slice.len() {
+                // out of space
+                break;
+            }
+        }
+        total_write_size
+    }
+}
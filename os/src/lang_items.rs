@@ -1,4 +1,5 @@
 use core::panic::PanicInfo;
+use crate::backtrace::print_backtrace;
 use crate::sbi::shutdown;
 
 
@@ -14,5 +15,6 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("Panicked: {}", info.message().unwrap());
     }
+    print_backtrace();
     shutdown(true)
 }
\ No newline at end of file
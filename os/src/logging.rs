@@ -1,17 +1,100 @@
+//! The kernel log.
+//!
+//! Records of the `log` crate go into a fixed-size ring buffer that user
+//! space reads back through the `syslog` syscall, as `dmesg` does. Those at
+//! or above the console level are printed as well. The console level is
+//! `loglevel=` on the kernel command line, else `LOG` at build time, else
+//! warn; the ring buffer keeps everything down to info either way.
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
 
+use crate::cmdline;
+use crate::config::LOG_BUFFER_SIZE;
+use crate::mm::UserBuffer;
+use crate::sync::SpinNoIrqLock;
+use crate::timer::get_time_ms;
 
-use log::{self, Level, LevelFilter, Log, Metadata, Record};
+/// The log text, the oldest lines are dropped to make room for new ones
+struct LogBuffer {
+    data: [u8; LOG_BUFFER_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self {
+            data: [0; LOG_BUFFER_SIZE],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn pop_line(&mut self) {
+        while self.len > 0 {
+            let byte = self.data[self.start];
+            self.start = (self.start + 1) % LOG_BUFFER_SIZE;
+            self.len -= 1;
+            if byte == b'\n' {
+                break;
+            }
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == LOG_BUFFER_SIZE {
+            self.pop_line();
+        }
+        self.data[(self.start + self.len) % LOG_BUFFER_SIZE] = byte;
+        self.len += 1;
+    }
+
+    fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    /// The byte `offset` bytes after the oldest one
+    fn get(&self, offset: usize) -> u8 {
+        self.data[(self.start + offset) % LOG_BUFFER_SIZE]
+    }
+}
 
-struct SimpleLogger;
+impl Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.push(byte));
+        Ok(())
+    }
+}
+
+static LOG_BUFFER: SpinNoIrqLock<LogBuffer> = SpinNoIrqLock::new(LogBuffer::new());
+/// Records up to this `LevelFilter` are printed
+static CONSOLE_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Warn as usize);
 
-impl Log for SimpleLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
     }
     fn log(&self, record: &Record) {
         if !self.enabled(record.metadata()) {
             return;
         }
+        let ms = get_time_ms();
+        writeln!(
+            LOG_BUFFER.lock(),
+            "[{:>5}.{:03}] [{:>5}] {}",
+            ms / 1000,
+            ms % 1000,
+            record.level(),
+            record.args(),
+        )
+        .unwrap();
+        if record.level() as usize > CONSOLE_LEVEL.load(Ordering::Relaxed) {
+            return;
+        }
         let color = match record.level() {
             Level::Error => 31, // Red
             Level::Warn => 93,  // BrightYellow
@@ -29,15 +112,41 @@ impl Log for SimpleLogger {
     fn flush(&self) {}
 }
 
+/// Install the logger, after `cmdline::init`
 pub fn init() {
-    static LOGGER: SimpleLogger = SimpleLogger;
+    static LOGGER: KernelLogger = KernelLogger;
+    let console_level = cmdline::param("loglevel")
+        .or(option_env!("LOG"))
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(LevelFilter::Warn);
+    CONSOLE_LEVEL.store(console_level as usize, Ordering::Relaxed);
     log::set_logger(&LOGGER).unwrap();
-    log::set_max_level(match option_env!("LOG") {
-        Some("ERROR") => LevelFilter::Error,
-        Some("WARN") => LevelFilter::Warn,
-        Some("INFO") => LevelFilter::Info,
-        Some("DEBUG") => LevelFilter::Debug,
-        Some("TRACE") => LevelFilter::Trace,
-        _ => LevelFilter::Off,
-    });
+    log::set_max_level(console_level.max(LevelFilter::Info));
+}
+
+/// Copy the newest part of the log that fits in `buf`, then empty the log
+/// if `clear`. Returns the bytes copied
+pub fn read_log(buf: UserBuffer, clear: bool) -> usize {
+    let mut log = LOG_BUFFER.lock();
+    let len = buf.len().min(log.len);
+    let skip = log.len - len;
+    for (offset, byte) in buf.into_iter().take(len).enumerate() {
+        unsafe {
+            *byte = log.get(skip + offset);
+        }
+    }
+    if clear {
+        log.clear();
+    }
+    len
+}
+
+/// Empty the log
+pub fn clear_log() {
+    LOG_BUFFER.lock().clear();
+}
+
+/// Bytes in the log
+pub fn log_len() -> usize {
+    LOG_BUFFER.lock().len
 }
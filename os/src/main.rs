@@ -8,6 +8,8 @@ extern crate alloc;
 use log::*;
 #[path = "boards/qemu.rs"]
 mod board;
+mod backtrace;
+mod cmdline;
 #[macro_use]
 mod console;
 mod lang_items;
@@ -24,6 +26,7 @@ mod mm;
 mod fs;
 mod drivers;
 mod net;
+mod smp;
 // mod build;
 
 #[macro_use]
@@ -39,41 +42,64 @@ core::arch::global_asm!(include_str!("entry.asm"));
 // core::arch::global_asm!(include_str!("link_app.S"));
 
 use lazy_static::*;
-use sync::UPIntrFreeCell;
+use sync::SpinNoIrqLock;
 use crate::drivers::chardev::CharDevice;
+use crate::drivers::net::NET_DEVICE;
 use crate::drivers::{GPU_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE, UART};
 
 lazy_static! {
-    pub static ref DEV_NON_BLOCKING_ACCESS: UPIntrFreeCell<bool> =
-        unsafe { UPIntrFreeCell::new(false) };
+    pub static ref DEV_NON_BLOCKING_ACCESS: SpinNoIrqLock<bool> =
+        SpinNoIrqLock::new(false);
 }
 #[no_mangle]
-pub fn rust_main() -> ! {
+pub fn rust_main(hart_id: usize, dtb: usize) -> ! {
     clear_bss();
-    // logging::init();
+    cmdline::init(dtb);
+    logging::init();
     mm::init();
     UART.init();
     println!("[kernel] Hello, myos!");
-    println!("KERN: init gpu");
+    info!("init gpu");
     let _gpu = GPU_DEVICE.clone();
-    println!("KERN: init keyboard");
+    info!("init keyboard");
     let _keyboard = KEYBOARD_DEVICE.clone();
-    println!("KERN: init mouse");
+    info!("init mouse");
     let _mouse = MOUSE_DEVICE.clone();
-    println!("KERN: init trap");
+    info!("init net");
+    let _net = NET_DEVICE.clone();
+    info!("init trap");
     // mm::remap_test();
     // task::add_initproc();
     trap::init();
     trap::enable_timer_interrupt();
     timer::set_next_trigger();
     board::device_init();
+    board::hart_init(hart_id);
+    net::init();
     fs::list_apps();
     task::add_initproc();
-    *DEV_NON_BLOCKING_ACCESS.exclusive_access() = true;
+    *DEV_NON_BLOCKING_ACCESS.lock() = true;
+    smp::set_online();
+    smp::start_secondary_harts();
     task::run_tasks();
     panic!("Unreachable in rust_main!");
 }
 
+/// Entry of the harts started by `smp::start_secondary_harts`
+#[no_mangle]
+pub fn rust_main_secondary(hart_id: usize) -> ! {
+    mm::activate_kernel_space();
+    trap::init();
+    trap::enable_timer_interrupt();
+    timer::set_next_trigger();
+    board::hart_init(hart_id);
+    net::loopback::init();
+    info!("hart {} started", hart_id);
+    smp::set_online();
+    task::run_tasks();
+    panic!("Unreachable in rust_main_secondary!");
+}
+
 fn clear_bss() {
     extern "C" {
         fn sbss();
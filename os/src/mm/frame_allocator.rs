@@ -2,13 +2,15 @@ use alloc::vec::Vec;
 use core::fmt;
 use core::fmt::{Debug, Formatter};
 use lazy_static::lazy_static;
+use log::warn;
 use crate::config::MEMORY_END;
 use crate::mm::address::{PhysAddr, PhysPageNum};
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinNoIrqLock;
 
 /// an implementation for frame allocator
 /// 物理页号区间 [ current , end ) 此前均 从未 被分配出去过，而向量 recycled 以后入先出的方式保存了被回收的物理页号
 pub struct StackFrameAllocator {
+    start: usize,    //可分配内存的起始物理页号
     current: usize,  //空闲内存的起始物理页号
     end: usize,      //空闲内存的结束物理页号
     recycled: Vec<usize>,
@@ -16,14 +18,26 @@ pub struct StackFrameAllocator {
 
 impl StackFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
         self.current = l.0;
         self.end = r.0;
     }
+
+    /// Number of frames managed by the allocator
+    pub fn total_frames(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Number of frames that can still be allocated
+    pub fn free_frames(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
 }
 
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
         Self{
+            start: 0,
             current: 0,
             end: 0,
             recycled: Vec::new(),
@@ -38,7 +52,7 @@ impl FrameAllocator for StackFrameAllocator {
             self.current += 1;
             Some((self.current - 1).into())
         } else {
-            println!("Frame allocation failed! current: {:?} end : {:?} recycled:{:?}", self.current,self.end,self.recycled);
+            warn!("Frame allocation failed! current: {:?} end : {:?} recycled:{:?}", self.current,self.end,self.recycled);
             None
         }
     }
@@ -77,9 +91,8 @@ trait FrameAllocator {
 type FrameAllocatorImpl = StackFrameAllocator;
 
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: UPIntrFreeCell<FrameAllocatorImpl> = unsafe {
-        UPIntrFreeCell::new(FrameAllocatorImpl::new())
-    };
+    pub static ref FRAME_ALLOCATOR: SpinNoIrqLock<FrameAllocatorImpl> =
+        SpinNoIrqLock::new(FrameAllocatorImpl::new());
 }
 
 pub fn init_frame_allocator() {
@@ -87,26 +100,32 @@ pub fn init_frame_allocator() {
         fn ekernel();
     }
     FRAME_ALLOCATOR
-        .exclusive_access()
+        .lock()
         .init(PhysAddr::from(ekernel as usize).ceil(), PhysAddr::from(MEMORY_END).floor());
 }
 
 pub fn frame_alloc() -> Option<FrameTracker> {
     FRAME_ALLOCATOR
-        .exclusive_access()
+        .lock()
         .alloc()
         .map(|ppn| FrameTracker::new(ppn))
 }
 pub fn frame_alloc_more(num: usize) -> Option<Vec<FrameTracker>> {
     FRAME_ALLOCATOR
-        .exclusive_access()
+        .lock()
         .alloc_more(num)
         .map(|x| x.iter().map(|&t| FrameTracker::new(t)).collect())
 }
 
+/// Get (total, free) frame counts of the frame allocator
+pub fn frame_stats() -> (usize, usize) {
+    let allocator = FRAME_ALLOCATOR.lock();
+    (allocator.total_frames(), allocator.free_frames())
+}
+
 pub fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR
-        .exclusive_access()
+        .lock()
         .dealloc(ppn);
 }
 
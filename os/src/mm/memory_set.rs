@@ -1,16 +1,17 @@
 use alloc::collections::BTreeMap;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinNoIrqLock;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use lazy_static::lazy_static;
 use log::info;
 use riscv::register::satp;
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE};
+use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, USER_HEAP_MAX, USER_STACK_MAX};
 use crate::mm::address::{PhysAddr, PhysPageNum, PPNRange, StepByOne, VirtAddr, VirtPageNum, VPNRange};
 use crate::mm::frame_allocator::{frame_alloc, FrameTracker};
 use crate::mm::page_table::{PageTable, PTEFlags};
 use spin::Mutex;
 use crate::mm::PageTableEntry;
+use crate::smp::retire_frames;
 
 extern "C" {
     fn stext();
@@ -27,11 +28,42 @@ extern "C" {
 
 lazy_static! {
     /// a memory set instance through lazy_static! managing kernel space
-    pub static ref KERNEL_SPACE: Arc<UPIntrFreeCell<MemorySet>> =
-        Arc::new(unsafe { UPIntrFreeCell::new(MemorySet::new_kernel()) });
+    pub static ref KERNEL_SPACE: Arc<SpinNoIrqLock<MemorySet>> =
+        Arc::new(SpinNoIrqLock::new(MemorySet::new_kernel()));
 }
 
 
+/// `e_machine` of RISC-V
+const EM_RISCV: u16 = 243;
+/// Size of an ELF64 program header
+const PH_ENTRY_SIZE: usize = 56;
+/// Programs live in the lower half of the Sv39 address space
+const USER_SPACE_END: usize = 1 << 38;
+/// Where position-independent (ET_DYN) programs are loaded
+const PIE_BASE: usize = 0x10_0000_0000;
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const RELA_ENTRY_SIZE: u64 = 24;
+const R_RISCV_NONE: u64 = 0;
+const R_RISCV_RELATIVE: u64 = 3;
+
+/// Where a program was loaded, for its auxiliary vector
+pub struct ElfInfo {
+    /// what the addresses in the file were moved by, 0 unless it is a PIE
+    pub base: usize,
+    pub entry: usize,
+    /// address of the program headers, 0 if no segment loads them
+    pub phdr: usize,
+    pub phent: usize,
+    pub phnum: usize,
+    /// the program header table, as in the file
+    pub ph_table: Vec<u8>,
+    /// start of the heap, right after the segments
+    pub heap_bottom: usize,
+}
+
 // 一小块区域的虚拟地址映射物理地址关系
 pub struct MapArea {
     //一段虚拟页号的连续区间，表示该逻辑段在地址区间中的位置和长度。它是一个迭代器
@@ -39,6 +71,8 @@ pub struct MapArea {
     data_frames: BTreeMap<VirtPageNum, FrameTracker>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// for a stack, the lowest page it may grow down to
+    stack_limit: Option<VirtPageNum>,
 }
 
 impl MapArea {
@@ -50,13 +84,73 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            stack_limit: None,
+        }
+    }
+
+    /// The [start, end) virtual address range of the area
+    pub fn va_range(&self) -> (VirtAddr, VirtAddr) {
+        (self.vpn_range.get_start().into(), self.vpn_range.get_end().into())
+    }
+
+    pub fn map_type(&self) -> MapType {
+        self.map_type
+    }
+
+    pub fn map_perm(&self) -> MapPermission {
+        self.map_perm
+    }
+
+    /// Number of frames owned by the area
+    pub fn frame_count(&self) -> usize {
+        self.data_frames.len()
+    }
+
+    /// Map or unmap pages at the end so that the area ends at `new_end`.
+    /// Returns the frames of the unmapped pages. If the frames run out while
+    /// growing, the new pages are unmapped again and their frames are the
+    /// `Err`, with the area as it was
+    pub fn resize(
+        &mut self,
+        page_table: &mut PageTable,
+        new_end: VirtPageNum,
+    ) -> Result<Vec<FrameTracker>, Vec<FrameTracker>> {
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        let new_end = new_end.max(start);
+        let mut frames = Vec::new();
+        if new_end > end {
+            for vpn in VPNRange::new(end, new_end) {
+                if !self.map_one(page_table, vpn) {
+                    return Err(self.take_frames(page_table, VPNRange::new(end, vpn)));
+                }
+            }
+        } else {
+            frames = self.take_frames(page_table, VPNRange::new(new_end, end));
         }
+        self.vpn_range = VPNRange::new(start, new_end);
+        Ok(frames)
     }
 
-    pub fn map(&mut self, page_table: &mut PageTable) {
+    /// Unmap the pages of `vpn_range` and return their frames, which other
+    /// harts may still reach until their TLBs are flushed
+    fn take_frames(&mut self, page_table: &mut PageTable, vpn_range: VPNRange) -> Vec<FrameTracker> {
+        vpn_range
+            .into_iter()
+            .filter_map(|vpn| {
+                page_table.unmap(vpn);
+                self.data_frames.remove(&vpn)
+            })
+            .collect()
+    }
+
+    /// Returns false if the frames ran out, with the area partly mapped
+    pub fn map(&mut self, page_table: &mut PageTable) -> bool {
         for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+            if !self.map_one(page_table, vpn) {
+                return false;
+            }
         }
+        true
     }
 
     pub fn unmap(&mut self, page_table: &mut PageTable) {
@@ -78,16 +172,21 @@ impl MapArea {
     }
 
 
-    fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    /// Returns false if no frame is left for the page or the page table
+    fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
         let ppn: PhysPageNum;
+        let mut frame = None;
         match self.map_type {
             MapType::Identical => {
                 ppn = PhysPageNum::from(vpn.0);
             }
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                let data_frame = match frame_alloc() {
+                    Some(data_frame) => data_frame,
+                    None => return false,
+                };
+                ppn = data_frame.ppn;
+                frame = Some(data_frame);
             }
             MapType::Linear(pn_offset) => {
                 // check for sv39
@@ -99,7 +198,13 @@ impl MapArea {
             }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags)
+        if !page_table.try_map(vpn, ppn, pte_flags) {
+            return false;
+        }
+        if let Some(frame) = frame {
+            self.data_frames.insert(vpn, frame);
+        }
+        true
     }
 
     pub fn map_noalloc(&mut self, page_table: &mut PageTable,ppn_range:PPNRange) {
@@ -110,22 +215,21 @@ impl MapArea {
         }
     }
 
-    /// data: start-aligned but maybe with shorter length
+    /// data: starts `offset` bytes into the first page, maybe with shorter length
     /// assume that all frames were cleared before
-    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
+    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8], offset: usize) {
         assert_eq!(self.map_type, MapType::Framed);
         let mut start: usize = 0;
+        let mut page_offset = offset;
         let mut current_vpn = self.vpn_range.get_start();
-        let len = data.len();
-        loop {
-            let src = &data[start..len.min(start + PAGE_SIZE)];
+        while start < data.len() {
+            let len = (PAGE_SIZE - page_offset).min(data.len() - start);
             // 映射为实际物理地址
-            let dst = &mut page_table.translate(current_vpn).unwrap().ppn().get_bytes_array()[..src.len()];
-            dst.copy_from_slice(src);
-            start += PAGE_SIZE;
-            if start >= len {
-                break;
-            }
+            let dst = &mut page_table.translate(current_vpn).unwrap().ppn().get_bytes_array()
+                [page_offset..page_offset + len];
+            dst.copy_from_slice(&data[start..start + len]);
+            start += len;
+            page_offset = 0;
             current_vpn.step();
         }
     }
@@ -139,11 +243,24 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            stack_limit: another.stack_limit,
         }
     }
 }
 
 
+/// What a page fault below a stack turned out to be
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StackFault {
+    /// the stack grew down to the address
+    Grown,
+    /// the address is in the guard page below a stack at its limit, or
+    /// there are no frames left to grow the stack
+    Overflow,
+    /// the address isn't below any stack
+    NotStack,
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum MapType {
     Identical,
@@ -164,7 +281,7 @@ bitflags! {
 
 /// Get the token of the kernel memory space
 pub fn kernel_token() -> usize {
-    KERNEL_SPACE.exclusive_access().token()
+    KERNEL_SPACE.lock().token()
 }
 /// 地址空间 是一系列有关联的不一定连续的逻辑段，
 /// 这种关联一般是指这些逻辑段组成的虚拟内存空间与一个运行的程序（目前把一个运行的程序称为任务，后续会称为进程）绑定，
@@ -181,8 +298,17 @@ impl MemorySet {
         map_area.map_noalloc(&mut self.page_table, ppn_range);
         self.areas.push(map_area);
     }
+    pub fn areas(&self) -> &[MapArea] {
+        &self.areas
+    }
     pub fn recycle_data_pages(&mut self) {
+        let mut frames = Vec::new();
+        for area in self.areas.iter_mut() {
+            let vpn_range = area.vpn_range;
+            frames.append(&mut area.take_frames(&mut self.page_table, vpn_range));
+        }
         self.areas.clear();
+        retire_frames(frames);
     }
     pub fn from_existed_user(user_apace: &MemorySet) -> MemorySet {
         let mut memory_set = Self::new_bare();
@@ -208,26 +334,119 @@ impl MemorySet {
     }
 
     pub fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+        assert!(map_area.map(&mut self.page_table), "no frame left to map an area");
         if let Some(data) = data {
-            map_area.copy_data(&mut self.page_table, data);
+            map_area.copy_data(&mut self.page_table, data, 0);
+        }
+        self.areas.push(map_area);
+    }
+
+    /// Push an area whose data starts `offset` bytes into its first page.
+    /// Fails if the frames run out, leaving the memory set half built
+    pub fn push_with_offset(
+        &mut self,
+        mut map_area: MapArea,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), &'static str> {
+        if !map_area.map(&mut self.page_table) {
+            return Err("out of memory");
         }
+        map_area.copy_data(&mut self.page_table, data, offset);
         self.areas.push(map_area);
+        Ok(())
     }
 
     pub fn insert_framed_area(&mut self, start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission) {
         self.push(MapArea::new(start_va, end_va, MapType::Framed, permission), None);
     }
 
+    /// Map a stack of `[bottom, top)` that page faults grow down to `limit`.
+    /// The page below `limit` is left unmapped as a guard
+    pub fn insert_stack_area(&mut self, limit: VirtAddr, bottom: VirtAddr, top: VirtAddr) {
+        let mut map_area = MapArea::new(
+            bottom,
+            top,
+            MapType::Framed,
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        );
+        map_area.stack_limit = Some(limit.floor());
+        self.push(map_area, None);
+    }
+
+    /// Grow the stack just above a page fault at `va` down to it. Nothing is
+    /// mapped if the frames run out on the way
+    pub fn grow_stack(&mut self, va: usize) -> StackFault {
+        if va >= USER_SPACE_END {
+            return StackFault::NotStack;
+        }
+        let vpn = VirtAddr::from(va).floor();
+        for area in self.areas.iter_mut() {
+            let limit = match area.stack_limit {
+                Some(limit) => limit,
+                None => continue,
+            };
+            let (start, end) = (area.vpn_range.get_start(), area.vpn_range.get_end());
+            if limit <= vpn && vpn < start {
+                for page in VPNRange::new(vpn, start) {
+                    if !area.map_one(&mut self.page_table, page) {
+                        let mapped = VPNRange::new(vpn, page);
+                        retire_frames(area.take_frames(&mut self.page_table, mapped));
+                        return StackFault::Overflow;
+                    }
+                }
+                area.vpn_range = VPNRange::new(vpn, end);
+                return StackFault::Grown;
+            }
+            if vpn.0 + 1 == limit.0 {
+                return StackFault::Overflow;
+            }
+        }
+        StackFault::NotStack
+    }
+
+    /// Unmap the area ending at `end_vpn`, for areas like stacks whose start moves
+    pub fn remove_area_with_end_vpn(&mut self, end_vpn: VirtPageNum) {
+        if let Some(idx) = self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.get_end() == end_vpn)
+        {
+            let mut area = self.areas.remove(idx);
+            let vpn_range = area.vpn_range;
+            retire_frames(area.take_frames(&mut self.page_table, vpn_range));
+        }
+    }
+
+    /// Resize the area starting at `start` to end at `new_end`. Fails,
+    /// leaving the area as it was, if there is no such area or not enough
+    /// frames to grow it
+    pub fn resize_area(&mut self, start: VirtAddr, new_end: VirtAddr) -> bool {
+        let start_vpn = start.floor();
+        match self.areas.iter_mut().find(|area| area.vpn_range.get_start() == start_vpn) {
+            Some(area) => match area.resize(&mut self.page_table, new_end.ceil()) {
+                Ok(frames) => {
+                    retire_frames(frames);
+                    true
+                }
+                Err(frames) => {
+                    retire_frames(frames);
+                    false
+                }
+            },
+            None => false,
+        }
+    }
+
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
-        if let Some((idx, area)) = self
+        if let Some(idx) = self
             .areas
-            .iter_mut()
-            .enumerate()
-            .find(|(_, area)| area.vpn_range.get_start() == start_vpn)
+            .iter()
+            .position(|area| area.vpn_range.get_start() == start_vpn)
         {
-            area.unmap(&mut self.page_table);
-            self.areas.remove(idx);
+            let mut area = self.areas.remove(idx);
+            let vpn_range = area.vpn_range;
+            retire_frames(area.take_frames(&mut self.page_table, vpn_range));
         }
     }
 
@@ -314,70 +533,228 @@ impl MemorySet {
     }
 
     /// Include sections in elf and trampoline and TrapContext and user stack,
-    /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
+    /// also returns user_sp and entry point. Fails on files that aren't a
+    /// RISC-V executable we can load. PIEs are loaded at `PIE_BASE` and
+    /// relocated.
+    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, usize, ElfInfo), &'static str> {
+        let elf = xmas_elf::ElfFile::new(elf_data)?;
+        let elf_header = elf.header;
+        if elf_header.pt1.magic != [0x7f, 0x45, 0x4c, 0x46] {
+            return Err("not an ELF file");
+        }
+        if elf_header.pt1.class() != xmas_elf::header::Class::SixtyFour
+            || elf_header.pt1.data() != xmas_elf::header::Data::LittleEndian
+        {
+            return Err("not a 64-bit little-endian ELF");
+        }
+        // e_machine, the header is in the file as it parsed
+        if u16::from_le_bytes([elf_data[18], elf_data[19]]) != EM_RISCV {
+            return Err("not a RISC-V ELF");
+        }
+        let base = match elf_header.pt2.type_().as_type() {
+            xmas_elf::header::Type::Executable => 0,
+            xmas_elf::header::Type::SharedObject => PIE_BASE,
+            _ => return Err("not an executable"),
+        };
+        let ph_offset = elf_header.pt2.ph_offset();
+        let ph_count = elf_header.pt2.ph_count();
+        let ph_entry_size = elf_header.pt2.ph_entry_size();
+        let ph_end = (ph_count as u64)
+            .checked_mul(ph_entry_size as u64)
+            .and_then(|size| ph_offset.checked_add(size));
+        match ph_end {
+            Some(ph_end)
+                if ph_entry_size as usize == PH_ENTRY_SIZE && ph_end <= elf_data.len() as u64 => {}
+            _ => return Err("bad program header table"),
+        }
+        let entry_point = base
+            .checked_add(elf_header.pt2.entry_point() as usize)
+            .ok_or("entry point out of range")?;
 
+        let mut memory_set = Self::new_bare();
         // map trampoline
         memory_set.map_trampoline();
 
         // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
-        let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
-        let ph_count = elf_header.pt2.ph_count();
+        let mut phdr = 0;
+        let mut entry_mapped = false;
         let mut max_end_vpn = VirtPageNum(0);
+        let mut dynamic = None;
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
-                let mut map_perm = MapPermission::U;
-                let ph_flags = ph.flags();
-                if ph_flags.is_read() {
-                    map_perm |= MapPermission::R
+            let ph = elf.program_header(i)?;
+            match ph.get_type()? {
+                xmas_elf::program::Type::Load if ph.mem_size() != 0 => {}
+                xmas_elf::program::Type::Dynamic => {
+                    dynamic = Some(ph);
+                    continue;
                 }
-                if ph_flags.is_write() {
-                    map_perm |= MapPermission::W
+                xmas_elf::program::Type::Interp => {
+                    return Err("dynamically linked programs aren't supported");
                 }
-                if ph_flags.is_execute() {
-                    map_perm |= MapPermission::X;
+                _ => continue,
+            }
+            let file_end = ph.offset().checked_add(ph.file_size());
+            let mem_start = (base as u64).checked_add(ph.virtual_addr());
+            let mem_end = mem_start.and_then(|mem_start| mem_start.checked_add(ph.mem_size()));
+            let (mem_start, mem_end) = match (file_end, mem_start, mem_end) {
+                (Some(file_end), Some(mem_start), Some(mem_end))
+                    if file_end <= elf_data.len() as u64 && mem_end <= USER_SPACE_END as u64 =>
+                {
+                    (mem_start as usize, mem_end as usize)
                 }
-                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
-                max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(map_area, Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]));
+                _ => return Err("segment out of range"),
+            };
+            if ph.file_size() > ph.mem_size() {
+                return Err("segment larger in the file than in memory");
+            }
+            // the file offset and the address agree modulo the alignment
+            let align = ph.align().max(1);
+            if !align.is_power_of_two() || ph.virtual_addr() % align != ph.offset() % align {
+                return Err("misaligned segment");
+            }
+            let start_va: VirtAddr = mem_start.into();
+            let end_va: VirtAddr = mem_end.into();
+            // segments can't share pages, they are mapped with their own permissions
+            if memory_set.areas.iter().any(|area| {
+                area.vpn_range.get_start() < end_va.ceil() && start_va.floor() < area.vpn_range.get_end()
+            }) {
+                return Err("overlapping segments");
+            }
+            let mut map_perm = MapPermission::U;
+            let ph_flags = ph.flags();
+            if ph_flags.is_read() {
+                map_perm |= MapPermission::R
+            }
+            if ph_flags.is_write() {
+                map_perm |= MapPermission::W
+            }
+            if ph_flags.is_execute() {
+                map_perm |= MapPermission::X;
+                entry_mapped |= (start_va.0..end_va.0).contains(&entry_point);
+            }
+            // the program headers are in memory if a segment loads them
+            if (ph.offset()..ph.offset() + ph.file_size()).contains(&ph_offset) {
+                phdr = mem_start + (ph_offset - ph.offset()) as usize;
+            }
+            let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+            max_end_vpn = max_end_vpn.max(map_area.vpn_range.get_end());
+            // the rest of the segment, up to p_memsz, is left zeroed
+            memory_set.push_with_offset(
+                map_area,
+                start_va.page_offset(),
+                &elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize],
+            )?;
+        }
+        if !entry_mapped {
+            return Err("entry point outside the executable segments");
+        }
+        if let Some(dynamic) = dynamic {
+            if base != 0 {
+                memory_set.relocate(dynamic, base)?;
             }
         }
 
+        // an empty heap after the segments, brk grows it
+        let heap_bottom: VirtAddr = max_end_vpn.into();
+        memory_set.push(
+            MapArea::new(
+                heap_bottom,
+                heap_bottom,
+                MapType::Framed,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None,
+        );
         // map user stack with U flags
-        let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_bottom: usize = max_end_va.into();
+        let mut user_stack_bottom: usize = heap_bottom.0 + USER_HEAP_MAX;
         // guard page
         user_stack_bottom += PAGE_SIZE;
-        // let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
-
-        // memory_set.push(MapArea::new(user_stack_bottom.into(),
-        //                              user_stack_top.into(),
-        //                              MapType::Framed,
-        //                              MapPermission::R | MapPermission::W | MapPermission::U),
-        //                 None,
-        // );
-        // // map TrapContext
-        // memory_set.push(
-        //     MapArea::new(
-        //         TRAP_CONTEXT_BASE.into(),
-        //         TRAMPOLINE.into(),
-        //         MapType::Framed,
-        //         MapPermission::R | MapPermission::W,
-        //     ),
-        //     None,
-        // );
-        (
+        // the main thread's stack must stay in the lower half too, the trap
+        // contexts are at the top of the address space
+        if user_stack_bottom + USER_STACK_MAX > USER_SPACE_END {
+            return Err("no room for the user stack");
+        }
+        Ok((
             memory_set,
             user_stack_bottom,
-            elf.header.pt2.entry_point() as usize,
-        )
+            ElfInfo {
+                base,
+                entry: entry_point,
+                phdr,
+                phent: ph_entry_size as usize,
+                phnum: ph_count as usize,
+                ph_table: elf_data[ph_offset as usize..][..ph_count as usize * PH_ENTRY_SIZE].to_vec(),
+                heap_bottom: heap_bottom.0,
+            },
+        ))
+    }
+
+    /// Apply the R_RISCV_RELATIVE relocations the dynamic section of a PIE
+    /// loaded at `base` lists, reading the tables from the loaded image
+    fn relocate(
+        &mut self,
+        dynamic: xmas_elf::program::ProgramHeader,
+        base: usize,
+    ) -> Result<(), &'static str> {
+        let (mut rela, mut rela_size, mut rela_entry) = (0, 0, RELA_ENTRY_SIZE);
+        let dynamic_va = base as u64 + dynamic.virtual_addr();
+        for i in 0..dynamic.mem_size() / 16 {
+            let tag = self.read_u64(dynamic_va + i * 16)?;
+            let value = self.read_u64(dynamic_va + i * 16 + 8)?;
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela = value,
+                DT_RELASZ => rela_size = value,
+                DT_RELAENT => rela_entry = value,
+                _ => {}
+            }
+        }
+        if rela_entry != RELA_ENTRY_SIZE {
+            return Err("bad relocation entry size");
+        }
+        for i in 0..rela_size / RELA_ENTRY_SIZE {
+            let entry = base as u64 + rela + i * RELA_ENTRY_SIZE;
+            let offset = self.read_u64(entry)?;
+            let kind = self.read_u64(entry + 8)? & 0xffff_ffff;
+            let addend = self.read_u64(entry + 16)?;
+            match kind {
+                R_RISCV_NONE => {}
+                R_RISCV_RELATIVE => {
+                    let value = (base as u64).wrapping_add(addend);
+                    self.write_bytes(base as u64 + offset, &value.to_le_bytes())?;
+                }
+                _ => return Err("unsupported relocation"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a word of the program being loaded
+    fn read_u64(&self, va: u64) -> Result<u64, &'static str> {
+        let mut bytes = [0u8; 8];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = *self.loaded_byte(va + i as u64)?;
+        }
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Write to the program being loaded, whatever the segment permissions
+    fn write_bytes(&mut self, va: u64, bytes: &[u8]) -> Result<(), &'static str> {
+        for (i, byte) in bytes.iter().enumerate() {
+            *self.loaded_byte(va + i as u64)? = *byte;
+        }
+        Ok(())
+    }
+
+    fn loaded_byte(&self, va: u64) -> Result<&'static mut u8, &'static str> {
+        if va >= USER_SPACE_END as u64 {
+            return Err("relocation outside the segments");
+        }
+        let va = VirtAddr::from(va as usize);
+        match self.page_table.translate(va.floor()) {
+            Some(pte) if pte.is_valid() => Ok(&mut pte.ppn().get_bytes_array()[va.page_offset()]),
+            _ => Err("relocation outside the segments"),
+        }
     }
 
     pub fn activate(&self) {
@@ -396,7 +773,7 @@ impl MemorySet {
 
 // #[allow(unused)]
 // pub fn remap_test() {
-//     let mut kernel_space = KERNEL_SPACE.exclusive_access();
+//     let mut kernel_space = KERNEL_SPACE.lock();
 //     let mid_text: VirtAddr = ((stext as usize + etext as usize) / 2).into();
 //     let mid_rodata: VirtAddr = ((srodata as usize + erodata as usize) / 2).into();
 //     let mid_data: VirtAddr = ((sdata as usize + edata as usize) / 2).into();
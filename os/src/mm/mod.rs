@@ -6,9 +6,9 @@ pub mod memory_set;
 
 pub use address::{VPNRange, PPNRange};
 pub use address::{PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
-pub use frame_allocator::{frame_alloc, frame_dealloc, FrameTracker};
+pub use frame_allocator::{frame_alloc, frame_dealloc, frame_stats, FrameTracker};
 // pub use memory_set::remap_test;
-pub use memory_set::{kernel_token, MapPermission, MemorySet, MapArea, MapType, KERNEL_SPACE};
+pub use memory_set::{kernel_token, ElfInfo, MapPermission, MemorySet, MapArea, MapType, StackFault, KERNEL_SPACE};
 use page_table::PTEFlags;
 pub use page_table::{
     translated_byte_buffer, translated_ref, translated_refmut, translated_str, PageTable,
@@ -19,5 +19,10 @@ pub use page_table::{
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
-    KERNEL_SPACE.exclusive_access().activate();
+    KERNEL_SPACE.lock().activate();
+}
+
+/// switch a secondary hart to the kernel space set up by `init`
+pub fn activate_kernel_space() {
+    KERNEL_SPACE.lock().activate();
 }
\ No newline at end of file
@@ -0,0 +1,209 @@
+//! The ARP cache.
+//!
+//! Frames for a neighbour whose hardware address is unknown wait on its
+//! entry while requests for it go out once a second. When a reply arrives
+//! they are sent; after `MAX_REQUESTS` unanswered ones they are dropped.
+//! Resolved entries age out after `REACHABLE_MS` and are resolved again on
+//! the next frame. The cache is listed in `/proc/net/arp`.
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use lazy_static::lazy_static;
+
+use super::iface::{local_ip, local_mac};
+use super::packet::{ArpPacket, Ipv4Addr, MacAddr, ARP_REPLY, ARP_REQUEST};
+use super::transmit;
+use crate::sync::SpinNoIrqLock;
+use crate::timer::get_time_ms;
+
+/// How long a resolved address is trusted
+const REACHABLE_MS: usize = 60_000;
+/// Interval between requests for an unresolved address
+const RETRANSMIT_MS: usize = 1000;
+/// Requests sent before giving up on an address
+const MAX_REQUESTS: usize = 3;
+/// Frames waiting on one address, the oldest is dropped beyond that
+const MAX_PENDING: usize = 16;
+const MAX_ENTRIES: usize = 64;
+
+enum Neighbour {
+    /// requests are out, `pending` frames wait for the answer
+    Incomplete {
+        requests: usize,
+        retry_at: usize,
+        pending: VecDeque<Vec<u8>>,
+    },
+    Reachable {
+        mac: MacAddr,
+        expire_at: usize,
+    },
+}
+
+lazy_static! {
+    static ref ARP_CACHE: SpinNoIrqLock<BTreeMap<Ipv4Addr, Neighbour>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+}
+
+fn send_request(target_ip: Ipv4Addr) {
+    let request = ArpPacket {
+        op: ARP_REQUEST,
+        sender_mac: local_mac(),
+        sender_ip: local_ip(),
+        target_mac: MacAddr::default(),
+        target_ip,
+    };
+    transmit(&request.build(MacAddr::BROADCAST));
+}
+
+/// Send `frames` to `mac`, their destination is still unset
+fn transmit_to(mac: MacAddr, frames: impl IntoIterator<Item = Vec<u8>>) {
+    for mut frame in frames {
+        frame[0..6].copy_from_slice(&mac.0);
+        transmit(&frame);
+    }
+}
+
+/// Make room for a new entry by evicting the resolved one closest to
+/// expiring, false if every entry is still being resolved
+fn make_room(cache: &mut BTreeMap<Ipv4Addr, Neighbour>) -> bool {
+    if cache.len() < MAX_ENTRIES {
+        return true;
+    }
+    let oldest = cache
+        .iter()
+        .filter_map(|(ip, neighbour)| match neighbour {
+            Neighbour::Reachable { expire_at, .. } => Some((*expire_at, *ip)),
+            Neighbour::Incomplete { .. } => None,
+        })
+        .min();
+    match oldest {
+        Some((_, ip)) => {
+            cache.remove(&ip);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Send the Ethernet `frame` to the neighbour at `next_hop`, resolving its
+/// hardware address first if it is not cached
+pub fn output(next_hop: Ipv4Addr, frame: Vec<u8>) {
+    let now = get_time_ms();
+    let mut cache = ARP_CACHE.lock();
+    match cache.get_mut(&next_hop) {
+        Some(Neighbour::Reachable { mac, .. }) => {
+            let mac = *mac;
+            drop(cache);
+            transmit_to(mac, [frame]);
+        }
+        Some(Neighbour::Incomplete { pending, .. }) => {
+            if pending.len() == MAX_PENDING {
+                pending.pop_front();
+            }
+            pending.push_back(frame);
+        }
+        None => {
+            if !make_room(&mut cache) {
+                return;
+            }
+            let mut pending = VecDeque::new();
+            pending.push_back(frame);
+            let neighbour = Neighbour::Incomplete {
+                requests: 1,
+                retry_at: now + RETRANSMIT_MS,
+                pending,
+            };
+            cache.insert(next_hop, neighbour);
+            drop(cache);
+            send_request(next_hop);
+        }
+    }
+}
+
+/// Learn from an ARP packet and answer requests for our address
+pub fn handle_arp(arp: &ArpPacket) {
+    let local_ip = local_ip();
+    if local_ip == Ipv4Addr::UNSPECIFIED || arp.sender_ip == Ipv4Addr::UNSPECIFIED {
+        return;
+    }
+    let for_us = arp.target_ip == local_ip;
+    // as RFC 826 has it: refresh an entry we have, add one if asked by its owner
+    let mut cache = ARP_CACHE.lock();
+    let pending = if cache.contains_key(&arp.sender_ip) || (for_us && make_room(&mut cache)) {
+        let neighbour = Neighbour::Reachable {
+            mac: arp.sender_mac,
+            expire_at: get_time_ms() + REACHABLE_MS,
+        };
+        match cache.insert(arp.sender_ip, neighbour) {
+            Some(Neighbour::Incomplete { pending, .. }) => pending,
+            _ => VecDeque::new(),
+        }
+    } else {
+        VecDeque::new()
+    };
+    drop(cache);
+    transmit_to(arp.sender_mac, pending);
+    if arp.op == ARP_REQUEST && for_us {
+        let reply = ArpPacket {
+            op: ARP_REPLY,
+            sender_mac: local_mac(),
+            sender_ip: local_ip,
+            target_mac: arp.sender_mac,
+            target_ip: arp.sender_ip,
+        };
+        transmit(&reply.build(arp.sender_mac));
+    }
+}
+
+/// Age out resolved entries and retransmit or give up on unresolved ones,
+/// called on every timer tick
+pub fn arp_timer_tick() {
+    let now = get_time_ms();
+    let mut retransmit = Vec::new();
+    let mut cache = ARP_CACHE.lock();
+    cache.retain(|ip, neighbour| match neighbour {
+        Neighbour::Reachable { expire_at, .. } => now < *expire_at,
+        Neighbour::Incomplete {
+            requests, retry_at, ..
+        } => {
+            if now < *retry_at {
+                true
+            } else if *requests < MAX_REQUESTS {
+                *requests += 1;
+                *retry_at = now + RETRANSMIT_MS;
+                retransmit.push(*ip);
+                true
+            } else {
+                false
+            }
+        }
+    });
+    drop(cache);
+    for ip in retransmit {
+        send_request(ip);
+    }
+}
+
+/// Forget every neighbour, as when the interface address changes
+pub fn flush() {
+    ARP_CACHE.lock().clear();
+}
+
+/// Text for `/proc/net/arp`, in the layout Linux uses
+pub fn describe() -> String {
+    let mut s = String::new();
+    writeln!(s, "IP address       HW type     Flags       HW address            Mask     Device")
+        .unwrap();
+    for (ip, neighbour) in ARP_CACHE.lock().iter() {
+        // ATF_COM marks a resolved entry
+        let (flags, mac) = match neighbour {
+            Neighbour::Reachable { mac, .. } => (0x2, *mac),
+            Neighbour::Incomplete { .. } => (0x0, MacAddr::default()),
+        };
+        let ip = format!("{}", ip);
+        writeln!(s, "{:<16} 0x1         {:<#11x} {}     *        eth0", ip, flags, mac).unwrap();
+    }
+    s
+}
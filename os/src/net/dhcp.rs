@@ -0,0 +1,338 @@
+//! A DHCP client for the interface address.
+//!
+//! Discover, offer, request and ack as in RFC 2131, driven by the network
+//! timer and by replies on port 68. Every message is broadcast with the
+//! broadcast flag set, so the client works before it has an address and
+//! without knowing the server's MAC. If no server answers, the static
+//! configuration from the kernel command line is used instead.
+use alloc::vec;
+use lazy_static::lazy_static;
+use log::{info, warn};
+
+use super::iface::{clear_config, local_ip, local_mac, set_config, IfConfig, CONFIG_DHCP};
+use super::packet::{Ipv4Addr, Ipv4Packet, MacAddr, UdpDatagram, IP_PROTO_UDP};
+use super::send_ipv4;
+use crate::sync::SpinNoIrqLock;
+use crate::timer::{get_time, get_time_ms};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// The fixed part of a message, options follow the magic cookie
+const HEADER_LEN: usize = 240;
+/// BOOTP relays expect at least this much
+const MIN_MESSAGE_LEN: usize = 300;
+/// Ask the server to broadcast its reply, we can't receive unicast yet
+const FLAG_BROADCAST: u16 = 0x8000;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETERS: u8 = 55;
+const OPT_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+/// Time to wait for a reply before sending again
+const RETRANSMIT_MS: usize = 2000;
+/// Unanswered discovers before falling back to the static configuration
+const MAX_DISCOVERS: usize = 4;
+/// Unanswered requests before starting over with a discover
+const MAX_REQUESTS: usize = 4;
+
+enum DhcpState {
+    /// not running, the configuration is static
+    Stopped,
+    /// broadcasting discovers, waiting for an offer
+    Selecting,
+    /// requesting the offered address, waiting for the ack
+    Requesting { offered: Ipv4Addr, server: Ipv4Addr },
+    /// holding a lease, renewed at `renew_at` and lost at `expire_at` (in ms)
+    Bound { renew_at: usize, expire_at: usize },
+    /// asking to extend the lease, which is still used until `expire_at`
+    Renewing { expire_at: usize },
+}
+
+struct DhcpClient {
+    state: DhcpState,
+    xid: u32,
+    /// messages sent in the current state without an answer
+    sent: usize,
+    /// when to send the next message, in ms
+    retransmit_at: usize,
+}
+
+lazy_static! {
+    static ref DHCP_CLIENT: SpinNoIrqLock<DhcpClient> = SpinNoIrqLock::new(DhcpClient {
+        state: DhcpState::Stopped,
+        xid: 0,
+        sent: 0,
+        retransmit_at: 0,
+    });
+}
+
+/// The parts of a server reply the client looks at
+struct DhcpReply {
+    msg_type: u8,
+    yiaddr: Ipv4Addr,
+    server: Option<Ipv4Addr>,
+    subnet_mask: Option<Ipv4Addr>,
+    router: Option<Ipv4Addr>,
+    dns: Option<Ipv4Addr>,
+    /// in seconds
+    lease_time: Option<u32>,
+}
+
+impl DhcpReply {
+    /// Parse a BOOTREPLY for transaction `xid` addressed to `mac`
+    fn parse(data: &[u8], xid: u32, mac: MacAddr) -> Option<Self> {
+        if data.len() < HEADER_LEN
+            || data[0] != BOOTREPLY
+            || data[4..8] != xid.to_be_bytes()
+            || data[28..34] != mac.0
+            || data[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let addr = |value: &[u8]| match value {
+            [a, b, c, d, ..] => Some(Ipv4Addr([*a, *b, *c, *d])),
+            _ => None,
+        };
+        let mut reply = Self {
+            msg_type: 0,
+            yiaddr: addr(&data[16..20])?,
+            server: None,
+            subnet_mask: None,
+            router: None,
+            dns: None,
+            lease_time: None,
+        };
+        let mut options = &data[HEADER_LEN..];
+        while let Some(&code) = options.first() {
+            match code {
+                OPT_END => break,
+                OPT_PAD => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    let value = options.get(2..2 + len)?;
+                    match code {
+                        OPT_MESSAGE_TYPE => reply.msg_type = *value.first()?,
+                        OPT_SERVER_ID => reply.server = addr(value),
+                        OPT_SUBNET_MASK => reply.subnet_mask = addr(value),
+                        // several routers and servers may be listed, the first is preferred
+                        OPT_ROUTER => reply.router = addr(value),
+                        OPT_DNS => reply.dns = addr(value),
+                        OPT_LEASE_TIME => {
+                            reply.lease_time = value
+                                .get(..4)
+                                .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+                        }
+                        _ => {}
+                    }
+                    options = &options[2 + len..];
+                }
+            }
+        }
+        Some(reply)
+    }
+}
+
+impl DhcpClient {
+    /// Broadcast a message of `msg_type`, `ciaddr` is our address while renewing
+    fn send(&self, msg_type: u8, ciaddr: Ipv4Addr, requested: Option<(Ipv4Addr, Ipv4Addr)>) {
+        let mut msg = vec![0u8; HEADER_LEN];
+        msg[0] = BOOTREQUEST;
+        // Ethernet, 6 byte hardware addresses
+        msg[1] = 1;
+        msg[2] = 6;
+        msg[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        msg[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+        msg[12..16].copy_from_slice(&ciaddr.0);
+        msg[28..34].copy_from_slice(&local_mac().0);
+        msg[236..240].copy_from_slice(&MAGIC_COOKIE);
+        msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, msg_type]);
+        if let Some((offered, server)) = requested {
+            msg.extend_from_slice(&[OPT_REQUESTED_IP, 4]);
+            msg.extend_from_slice(&offered.0);
+            msg.extend_from_slice(&[OPT_SERVER_ID, 4]);
+            msg.extend_from_slice(&server.0);
+        }
+        msg.extend_from_slice(&[
+            OPT_PARAMETERS,
+            4,
+            OPT_SUBNET_MASK,
+            OPT_ROUTER,
+            OPT_DNS,
+            OPT_LEASE_TIME,
+            OPT_END,
+        ]);
+        if msg.len() < MIN_MESSAGE_LEN {
+            msg.resize(MIN_MESSAGE_LEN, OPT_PAD);
+        }
+        let datagram = UdpDatagram {
+            src_port: CLIENT_PORT,
+            dst_port: SERVER_PORT,
+            payload: &msg,
+        };
+        send_ipv4(
+            Ipv4Addr::BROADCAST,
+            IP_PROTO_UDP,
+            &datagram.build(local_ip(), Ipv4Addr::BROADCAST),
+        );
+    }
+
+    /// Send the message of the current state and schedule its retransmission
+    fn transmit(&mut self, now: usize) {
+        match self.state {
+            DhcpState::Selecting => self.send(DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, None),
+            DhcpState::Requesting { offered, server } => {
+                self.send(DHCPREQUEST, Ipv4Addr::UNSPECIFIED, Some((offered, server)))
+            }
+            DhcpState::Renewing { .. } => self.send(DHCPREQUEST, local_ip(), None),
+            DhcpState::Stopped | DhcpState::Bound { .. } => return,
+        }
+        self.sent += 1;
+        self.retransmit_at = now + RETRANSMIT_MS;
+    }
+
+    fn enter(&mut self, state: DhcpState, now: usize) {
+        self.state = state;
+        self.sent = 0;
+        self.transmit(now);
+    }
+
+    /// Start over with a new transaction
+    fn restart(&mut self, now: usize) {
+        self.xid = new_xid();
+        self.enter(DhcpState::Selecting, now);
+    }
+
+    fn on_timer(&mut self, now: usize) {
+        match self.state {
+            DhcpState::Bound { renew_at, expire_at } if now >= renew_at => {
+                self.enter(DhcpState::Renewing { expire_at }, now)
+            }
+            DhcpState::Renewing { expire_at } if now >= expire_at => {
+                warn!("dhcp: lease of {} expired", local_ip());
+                clear_config();
+                self.restart(now);
+            }
+            DhcpState::Selecting if now >= self.retransmit_at && self.sent >= MAX_DISCOVERS => {
+                self.state = DhcpState::Stopped;
+                match IfConfig::from_cmdline() {
+                    Some(config) => {
+                        warn!(
+                            "dhcp: no server, using {}/{} from the command line",
+                            config.ip, config.prefix_len
+                        );
+                        set_config(config);
+                    }
+                    None => warn!("dhcp: no server and no static address, interface unconfigured"),
+                }
+            }
+            DhcpState::Requesting { .. } if now >= self.retransmit_at && self.sent >= MAX_REQUESTS => {
+                self.restart(now)
+            }
+            DhcpState::Selecting | DhcpState::Requesting { .. } | DhcpState::Renewing { .. }
+                if now >= self.retransmit_at =>
+            {
+                self.transmit(now)
+            }
+            _ => {}
+        }
+    }
+
+    fn on_reply(&mut self, reply: DhcpReply, now: usize) {
+        match (&self.state, reply.msg_type) {
+            (DhcpState::Selecting, DHCPOFFER) => {
+                // an offer without a server identifier can't be requested
+                if let Some(server) = reply.server {
+                    let offered = reply.yiaddr;
+                    self.enter(DhcpState::Requesting { offered, server }, now);
+                }
+            }
+            (DhcpState::Requesting { .. } | DhcpState::Renewing { .. }, DHCPACK) => {
+                let bound = matches!(self.state, DhcpState::Requesting { .. });
+                let config = IfConfig {
+                    mac: local_mac(),
+                    prefix_len: reply
+                        .subnet_mask
+                        .map_or(24, |mask| mask.to_u32().count_ones() as u8),
+                    source: CONFIG_DHCP,
+                    ip: reply.yiaddr,
+                    gateway: reply.router.unwrap_or(Ipv4Addr::UNSPECIFIED),
+                    dns: reply.dns.unwrap_or(Ipv4Addr::UNSPECIFIED),
+                };
+                set_config(config);
+                if bound {
+                    info!(
+                        "dhcp: leased {}/{} gateway {}",
+                        config.ip, config.prefix_len, config.gateway
+                    );
+                }
+                // a lease of all ones is infinite
+                self.state = match reply.lease_time {
+                    Some(lease) if lease != u32::MAX => DhcpState::Bound {
+                        renew_at: now + lease as usize * 500,
+                        expire_at: now + lease as usize * 1000,
+                    },
+                    _ => DhcpState::Bound {
+                        renew_at: usize::MAX,
+                        expire_at: usize::MAX,
+                    },
+                };
+            }
+            (DhcpState::Requesting { .. } | DhcpState::Renewing { .. }, DHCPNAK) => {
+                clear_config();
+                self.restart(now);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A transaction id that differs between boots and between machines
+fn new_xid() -> u32 {
+    let mac = local_mac().0;
+    get_time() as u32 ^ u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]])
+}
+
+/// Start leasing an address, dropping any lease held
+pub fn start() {
+    DHCP_CLIENT.lock().restart(get_time_ms());
+}
+
+/// Stop the client, the address it leased is kept
+pub fn stop() {
+    DHCP_CLIENT.lock().state = DhcpState::Stopped;
+}
+
+/// Retransmit, renew or give up, called on every timer tick
+pub fn dhcp_timer_tick() {
+    DHCP_CLIENT.lock().on_timer(get_time_ms());
+}
+
+/// Take a datagram for the DHCP client port, false if `ip` is not one
+pub fn handle_reply(ip: &Ipv4Packet) -> bool {
+    let datagram = match UdpDatagram::parse(ip) {
+        Some(datagram) if datagram.dst_port == CLIENT_PORT => datagram,
+        _ => return false,
+    };
+    let mut client = DHCP_CLIENT.lock();
+    if let Some(reply) = DhcpReply::parse(datagram.payload, client.xid, local_mac()) {
+        client.on_reply(reply, get_time_ms());
+    }
+    true
+}
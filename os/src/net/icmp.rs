@@ -0,0 +1,250 @@
+//! ICMP: echo replies and ping sockets.
+//!
+//! The stack answers echo requests itself. A ping socket sends echo
+//! requests and receives the replies to them, like Linux's
+//! `socket(AF_INET, SOCK_DGRAM, IPPROTO_ICMP)`: the identifier of every
+//! request is replaced with the socket's own and the checksum is filled in,
+//! so only the replies carrying that identifier come back to it.
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+use super::packet::{
+    IcmpPacket, Ipv4Addr, Ipv4Packet, ICMP_ECHO_REPLY, ICMP_ECHO_REQUEST, ICMP_HEADER_LEN,
+    IPV4_HEADER_LEN, IP_MTU, IP_PROTO_ICMP,
+};
+use super::send_ipv4;
+use crate::fs::{File, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::{Condvar, PollQueue, SpinNoIrqLock};
+use crate::task::schedule;
+
+/// Replies queued on one socket before new ones are dropped
+const MAX_QUEUED: usize = 64;
+/// Largest echo request sent, header included, it has to fit the MTU
+pub const MAX_MESSAGE_LEN: usize = IP_MTU - IPV4_HEADER_LEN;
+
+/// An open ping socket
+struct IcmpEndpoint {
+    /// identifier of the echo requests sent from this socket
+    ident: u16,
+    /// set by `connect`, only replies from there are received
+    peer: Option<Ipv4Addr>,
+    /// received replies with their source, header included
+    messages: VecDeque<(Ipv4Addr, Vec<u8>)>,
+    condvar: Arc<Condvar>,       // readers waiting for a reply
+    poll_queue: Arc<PollQueue>,  // tasks in `poll` waiting for a reply
+}
+
+lazy_static! {
+    static ref ICMP_TABLE: SpinNoIrqLock<Vec<Option<IcmpEndpoint>>> =
+        SpinNoIrqLock::new(Vec::new());
+}
+
+/// whether a socket uses `ident`
+pub fn ident_in_use(ident: u16) -> bool {
+    ICMP_TABLE
+        .lock()
+        .iter()
+        .flatten()
+        .any(|endpoint| endpoint.ident == ident)
+}
+
+/// Answer an echo request to us, or queue an echo reply on its socket
+pub fn handle_icmp(ip: &Ipv4Packet) {
+    let packet = match IcmpPacket::parse(ip) {
+        Some(packet) => packet,
+        None => return,
+    };
+    match packet.icmp_type {
+        ICMP_ECHO_REQUEST => {
+            let reply = IcmpPacket {
+                icmp_type: ICMP_ECHO_REPLY,
+                code: 0,
+                id: packet.id,
+                seq: packet.seq,
+                payload: packet.payload,
+            };
+            send_ipv4(ip.src, IP_PROTO_ICMP, &reply.build());
+        }
+        ICMP_ECHO_REPLY => {
+            let mut icmp_table = ICMP_TABLE.lock();
+            let endpoint = icmp_table.iter_mut().flatten().find(|endpoint| {
+                endpoint.ident == packet.id && endpoint.peer.map_or(true, |peer| peer == ip.src)
+            });
+            if let Some(endpoint) = endpoint {
+                if endpoint.messages.len() < MAX_QUEUED {
+                    endpoint.messages.push_back((ip.src, ip.payload.to_vec()));
+                    endpoint.condvar.signal();
+                    endpoint.poll_queue.wake_all();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+pub struct ICMP {
+    pub socket_index: usize,
+}
+
+impl ICMP {
+    /// A ping socket sending with `ident`, None if another socket has it
+    pub fn new(ident: u16) -> Option<Self> {
+        if ident_in_use(ident) {
+            return None;
+        }
+        let endpoint = IcmpEndpoint {
+            ident,
+            peer: None,
+            messages: VecDeque::new(),
+            condvar: Arc::new(Condvar::new()),
+            poll_queue: Arc::new(PollQueue::new()),
+        };
+        let mut icmp_table = ICMP_TABLE.lock();
+        let index = match icmp_table.iter().position(|x| x.is_none()) {
+            Some(index) => {
+                icmp_table[index] = Some(endpoint);
+                index
+            }
+            None => {
+                icmp_table.push(Some(endpoint));
+                icmp_table.len() - 1
+            }
+        };
+        Some(Self {
+            socket_index: index,
+        })
+    }
+
+    pub fn ident(&self) -> u16 {
+        ICMP_TABLE.lock()[self.socket_index]
+            .as_ref()
+            .unwrap()
+            .ident
+    }
+
+    pub fn peer(&self) -> Option<Ipv4Addr> {
+        ICMP_TABLE.lock()[self.socket_index]
+            .as_ref()
+            .unwrap()
+            .peer
+    }
+
+    /// Send with `ident` from now on, false if another socket has it
+    pub fn rebind(&self, ident: u16) -> bool {
+        if ident != self.ident() && ident_in_use(ident) {
+            return false;
+        }
+        ICMP_TABLE.lock()[self.socket_index]
+            .as_mut()
+            .unwrap()
+            .ident = ident;
+        true
+    }
+
+    /// Set the default destination and receive only from there
+    pub fn connect(&self, peer: Ipv4Addr) {
+        let mut icmp_table = ICMP_TABLE.lock();
+        let endpoint = icmp_table[self.socket_index].as_mut().unwrap();
+        endpoint.peer = Some(peer);
+        endpoint.messages.retain(|(addr, _)| *addr == peer);
+    }
+
+    /// Send the echo request in `data`, header included. Fails with
+    /// usize::MAX if it is not one or doesn't fit the MTU.
+    pub fn send_to(&self, data: &[u8], addr: Ipv4Addr) -> usize {
+        if data.len() < ICMP_HEADER_LEN
+            || data.len() > MAX_MESSAGE_LEN
+            || data[0] != ICMP_ECHO_REQUEST
+            || data[1] != 0
+        {
+            return usize::MAX;
+        }
+        let request = IcmpPacket {
+            icmp_type: ICMP_ECHO_REQUEST,
+            code: 0,
+            id: self.ident(),
+            seq: u16::from_be_bytes([data[6], data[7]]),
+            payload: &data[ICMP_HEADER_LEN..],
+        };
+        send_ipv4(addr, IP_PROTO_ICMP, &request.build());
+        data.len()
+    }
+
+    /// Block until a reply arrives, copy as much of it as fits and return its source
+    pub fn recv_from(&self, mut buf: UserBuffer) -> (usize, Ipv4Addr) {
+        let (addr, data) = loop {
+            let mut icmp_table = ICMP_TABLE.lock();
+            let endpoint = icmp_table[self.socket_index].as_mut().unwrap();
+            if let Some(message) = endpoint.messages.pop_front() {
+                break message;
+            }
+            let task_cx_ptr = endpoint.condvar.clone().wait_no_sched();
+            drop(icmp_table);
+            schedule(task_cx_ptr);
+        };
+        let mut copied = 0;
+        for slice in buf.buffers.iter_mut() {
+            let len = slice.len().min(data.len() - copied);
+            slice[..len].copy_from_slice(&data[copied..copied + len]);
+            copied += len;
+            if copied == data.len() {
+                break;
+            }
+        }
+        (copied, addr)
+    }
+}
+
+impl File for ICMP {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    /// Block until a reply arrives, then copy as much of it as fits
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.recv_from(buf).0
+    }
+
+    /// Send to the connected peer, fails on a socket that is not connected
+    fn write(&self, buf: UserBuffer) -> usize {
+        let peer = match self.peer() {
+            Some(peer) => peer,
+            None => return usize::MAX,
+        };
+        if buf.len() > MAX_MESSAGE_LEN {
+            return usize::MAX;
+        }
+        let data: Vec<u8> = buf.buffers.iter().flat_map(|slice| slice.iter().copied()).collect();
+        self.send_to(&data, peer)
+    }
+
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let icmp_table = ICMP_TABLE.lock();
+        let endpoint = icmp_table[self.socket_index].as_ref().unwrap();
+        let mut ready = PollEvents::OUT;
+        if !endpoint.messages.is_empty() {
+            ready |= PollEvents::IN;
+        }
+        ready & events
+    }
+
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        let icmp_table = ICMP_TABLE.lock();
+        icmp_table[self.socket_index]
+            .as_ref()
+            .map(|endpoint| endpoint.poll_queue.clone())
+    }
+}
+
+impl Drop for ICMP {
+    fn drop(&mut self) {
+        ICMP_TABLE.lock()[self.socket_index] = None;
+    }
+}
@@ -0,0 +1,120 @@
+//! Configuration of the only network interface.
+//!
+//! The hardware address comes from the device. The IPv4 configuration is
+//! leased over DHCP, or set statically from the kernel command line or the
+//! `ifconfig` syscalls. Until then the address is 0.0.0.0.
+use alloc::string::String;
+use core::fmt::Write;
+use lazy_static::lazy_static;
+
+use super::arp;
+use super::packet::{Ipv4Addr, MacAddr};
+use crate::cmdline::param;
+use crate::drivers::net::NET_DEVICE;
+use crate::sync::SpinNoIrqLock;
+
+/// Where the current configuration came from
+pub const CONFIG_NONE: u8 = 0;
+pub const CONFIG_STATIC: u8 = 1;
+pub const CONFIG_DHCP: u8 = 2;
+
+/// The interface configuration, also the layout the `ifconfig` syscalls use
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct IfConfig {
+    pub mac: MacAddr,
+    /// length of the subnet prefix, 24 for a 255.255.255.0 netmask
+    pub prefix_len: u8,
+    /// one of the `CONFIG_*` values
+    pub source: u8,
+    pub ip: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub dns: Ipv4Addr,
+}
+
+impl IfConfig {
+    const fn unconfigured() -> Self {
+        Self {
+            mac: MacAddr([0; 6]),
+            prefix_len: 0,
+            source: CONFIG_NONE,
+            ip: Ipv4Addr::UNSPECIFIED,
+            gateway: Ipv4Addr::UNSPECIFIED,
+            dns: Ipv4Addr::UNSPECIFIED,
+        }
+    }
+
+    /// The static configuration from `ip=A.B.C.D/N gw=A.B.C.D dns=A.B.C.D`
+    /// on the kernel command line, None without `ip=`
+    pub fn from_cmdline() -> Option<Self> {
+        let arg = param("ip")?;
+        let (ip, prefix_len) = match arg.split_once('/') {
+            Some((ip, prefix_len)) => (ip, prefix_len.parse().ok().filter(|len| *len <= 32)?),
+            None => (arg, 24),
+        };
+        let addr = |key| param(key).and_then(Ipv4Addr::parse);
+        Some(Self {
+            mac: local_mac(),
+            prefix_len,
+            source: CONFIG_STATIC,
+            ip: Ipv4Addr::parse(ip)?,
+            gateway: addr("gw").unwrap_or(Ipv4Addr::UNSPECIFIED),
+            dns: addr("dns").unwrap_or(Ipv4Addr::UNSPECIFIED),
+        })
+    }
+}
+
+lazy_static! {
+    static ref IFACE: SpinNoIrqLock<IfConfig> =
+        SpinNoIrqLock::new(IfConfig::unconfigured());
+}
+
+pub fn local_mac() -> MacAddr {
+    MacAddr(NET_DEVICE.mac_address())
+}
+
+/// Our address, 0.0.0.0 while the interface is not configured
+pub fn local_ip() -> Ipv4Addr {
+    IFACE.lock().ip
+}
+
+pub fn config() -> IfConfig {
+    let mut config = *IFACE.lock();
+    config.mac = local_mac();
+    config
+}
+
+/// Replace the IPv4 configuration, the MAC can't be changed. The ARP
+/// cache is flushed if the address changes.
+pub fn set_config(config: IfConfig) {
+    let mut iface = IFACE.lock();
+    if iface.ip != config.ip {
+        arp::flush();
+    }
+    *iface = config;
+    iface.mac = local_mac();
+}
+
+/// Drop the IPv4 configuration, as when a lease runs out
+pub fn clear_config() {
+    *IFACE.lock() = IfConfig::unconfigured();
+    arp::flush();
+}
+
+/// Text for `/proc/net/iface`
+pub fn describe() -> String {
+    let config = config();
+    let source = match config.source {
+        CONFIG_STATIC => "static",
+        CONFIG_DHCP => "dhcp",
+        _ => "none",
+    };
+    let mut s = String::new();
+    writeln!(s, "HWaddr:\t{}", config.mac).unwrap();
+    writeln!(s, "Addr:\t{}/{}", config.ip, config.prefix_len).unwrap();
+    writeln!(s, "Netmask:\t{}", Ipv4Addr::netmask(config.prefix_len)).unwrap();
+    writeln!(s, "Gateway:\t{}", config.gateway).unwrap();
+    writeln!(s, "DNS:\t{}", config.dns).unwrap();
+    writeln!(s, "Source:\t{}", source).unwrap();
+    s
+}
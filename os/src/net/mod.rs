@@ -1,103 +1,157 @@
+pub mod arp;
+pub mod dhcp;
+pub mod icmp;
+pub mod iface;
+pub mod loopback;
+pub mod packet;
+pub mod pcap;
 pub mod port_table;
 pub mod socket;
 pub mod tcp;
 pub mod udp;
 
-pub use lose_net_stack::IPv4;
+use core::sync::atomic::{AtomicU16, Ordering};
+use log::{info, warn};
 
-use alloc::{sync::Arc, vec};
-use lose_net_stack::{results::Packet, LoseStack, MacAddress, TcpFlags};
+use crate::cmdline::param;
+use crate::drivers::net::NET_DEVICE;
 
-use crate::{
-    net::socket::{get_socket, push_data},
-    sync::UPIntrFreeCell,
+use self::iface::{config, local_ip, local_mac, set_config, IfConfig};
+use self::packet::{
+    build_ipv4, ArpPacket, Ipv4Addr, Ipv4Packet, MacAddr, IP_PROTO_ICMP, IP_PROTO_TCP,
+    IP_PROTO_UDP,
 };
-use crate::drivers::net::NET_DEVICE;
 
-use self::{port_table::check_accept, socket::set_s_a_by_index};
+/// Start of the dynamic port range sockets get when they don't bind one
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// Configure the interface: lease an address over DHCP, or with `dhcp=off`
+/// on the command line take the static one given there. Without a network
+/// card there is nothing to configure.
+pub fn init() {
+    loopback::init();
+    if local_mac() == MacAddr::default() {
+        info!("net: no network card, loopback only");
+        return;
+    }
+    info!("net: hwaddr {}", local_mac());
+    if param("dhcp") == Some("off") {
+        match IfConfig::from_cmdline() {
+            Some(config) => set_config(config),
+            None => warn!("net: dhcp=off without ip=, interface unconfigured"),
+        }
+    } else {
+        dhcp::start();
+    }
+}
+
+/// Handle the virtio-net interrupt: hand every received frame to the stack
+pub fn net_interrupt_handler() {
+    NET_DEVICE.handle_irq();
+    while let Some(frame) = NET_DEVICE.receive() {
+        pcap::capture(&frame);
+        handle_packet(&frame);
+    }
+}
 
-pub struct NetStack(UPIntrFreeCell<LoseStack>);
+/// Send an Ethernet frame through the network device
+pub fn transmit(frame: &[u8]) {
+    pcap::capture(frame);
+    NET_DEVICE.transmit(frame);
+}
 
-impl NetStack {
-    pub fn new() -> Self {
-        unsafe {
-            NetStack(UPIntrFreeCell::new(LoseStack::new(
-                IPv4::new(10, 0, 2, 15),
-                MacAddress::new([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]),
-            )))
-        }
+/// Handle the software interrupt raised by the loopback interface
+pub fn loopback_interrupt_handler() {
+    while let Some(frame) = loopback::receive() {
+        handle_packet(&frame);
     }
 }
 
-lazy_static::lazy_static! {
-    static ref LOSE_NET_STACK: Arc<NetStack> = Arc::new(NetStack::new());
+/// Send an IPv4 datagram from `source_ip(dst)`: through the loopback
+/// interface if it is for this host, broadcast, or to the next hop once ARP
+/// has resolved it. Dropped while the interface has no address.
+pub fn send_ipv4(dst: Ipv4Addr, protocol: u8, payload: &[u8]) {
+    let src = source_ip(dst);
+    let frame = build_ipv4(local_mac(), MacAddr::BROADCAST, src, dst, protocol, payload);
+    if loopback::is_local(dst) {
+        loopback::transmit(frame);
+        return;
+    }
+    let config = config();
+    if is_broadcast(&config, dst) {
+        transmit(&frame);
+    } else if config.ip != Ipv4Addr::UNSPECIFIED {
+        arp::output(next_hop(&config, dst), frame);
+    }
 }
 
-pub fn net_interrupt_handler() {
-    let mut recv_buf = vec![0u8; 1024];
-
-    let len = NET_DEVICE.receive(&mut recv_buf);
-
-    let packet = LOSE_NET_STACK
-        .0
-        .exclusive_access()
-        .analysis(&recv_buf[..len]);
-
-    // println!("[kernel] receive a packet");
-    // hexdump(&recv_buf[..len]);
-
-    match packet {
-        Packet::ARP(arp_packet) => {
-            let lose_stack = LOSE_NET_STACK.0.exclusive_access();
-            let reply_packet = arp_packet
-                .reply_packet(lose_stack.ip, lose_stack.mac)
-                .expect("can't build reply");
-            let reply_data = reply_packet.build_data();
-            NET_DEVICE.transmit(&reply_data)
-        }
+/// The address datagrams to `dst` are sent from. A loopback address
+/// answers from itself so that replies come back to the same one.
+pub fn source_ip(dst: Ipv4Addr) -> Ipv4Addr {
+    if dst.is_loopback() {
+        dst
+    } else {
+        local_ip()
+    }
+}
 
-        Packet::UDP(udp_packet) => {
-            let target = udp_packet.source_ip;
-            let lport = udp_packet.dest_port;
-            let rport = udp_packet.source_port;
+/// Whether `dst` is the limited broadcast or that of our subnet
+fn is_broadcast(config: &IfConfig, dst: Ipv4Addr) -> bool {
+    let host_bits = !Ipv4Addr::netmask(config.prefix_len).to_u32();
+    dst == Ipv4Addr::BROADCAST
+        || (config.prefix_len < 31 && dst.to_u32() == config.ip.to_u32() | host_bits)
+}
 
-            if let Some(socket_index) = get_socket(target, lport, rport) {
-                push_data(socket_index, udp_packet.data.to_vec());
-            }
+/// The neighbour datagrams for `dst` go to: the host itself on our
+/// subnet, the gateway beyond it
+fn next_hop(config: &IfConfig, dst: Ipv4Addr) -> Ipv4Addr {
+    if config.gateway == Ipv4Addr::UNSPECIFIED || dst.same_subnet(config.ip, config.prefix_len) {
+        dst
+    } else {
+        config.gateway
+    }
+}
+
+/// Pick a port from the dynamic range that `in_use` says is free
+pub fn ephemeral_port(in_use: impl Fn(u16) -> bool) -> u16 {
+    static NEXT: AtomicU16 = AtomicU16::new(EPHEMERAL_PORT_START);
+    loop {
+        let port = NEXT.fetch_add(1, Ordering::Relaxed);
+        if port == u16::MAX {
+            NEXT.store(EPHEMERAL_PORT_START, Ordering::Relaxed);
+        }
+        if port >= EPHEMERAL_PORT_START && !in_use(port) {
+            return port;
         }
+    }
+}
 
-        Packet::TCP(tcp_packet) => {
-            let target = tcp_packet.source_ip;
-            let lport = tcp_packet.dest_port;
-            let rport = tcp_packet.source_port;
-            let flags = tcp_packet.flags;
-
-            if flags.contains(TcpFlags::S) {
-                // if it has a port to accept, then response the request
-                if check_accept(lport, &tcp_packet).is_some() {
-                    let mut reply_packet = tcp_packet.ack();
-                    reply_packet.flags = TcpFlags::S | TcpFlags::A;
-                    NET_DEVICE.transmit(&reply_packet.build_data());
-                }
-                return;
-            } else if tcp_packet.flags.contains(TcpFlags::F) {
-                // tcp disconnected
-                let reply_packet = tcp_packet.ack();
-                NET_DEVICE.transmit(&reply_packet.build_data());
-
-                let mut end_packet = reply_packet.ack();
-                end_packet.flags |= TcpFlags::F;
-                NET_DEVICE.transmit(&end_packet.build_data());
-            } else if tcp_packet.flags.contains(TcpFlags::A) && tcp_packet.data_len == 0 {
-                return;
-            }
+/// Run the protocol timers, called on every timer tick
+pub fn net_timer_handler() {
+    tcp::tcp_timer_tick();
+    dhcp::dhcp_timer_tick();
+    arp::arp_timer_tick();
+}
 
-            if let Some(socket_index) = get_socket(target, lport, rport) {
-                push_data(socket_index, tcp_packet.data.to_vec());
-                set_s_a_by_index(socket_index, tcp_packet.seq, tcp_packet.ack);
-            }
+fn handle_packet(data: &[u8]) {
+    if let Some(ip) = Ipv4Packet::parse(data) {
+        // the DHCP client needs its replies before there is an address
+        if ip.protocol == IP_PROTO_UDP && dhcp::handle_reply(&ip) {
+            return;
+        }
+        // loopback traffic needs no address on the interface
+        let local_ip = local_ip();
+        let configured = local_ip != Ipv4Addr::UNSPECIFIED;
+        let for_us = ip.dst.is_loopback() || (configured && ip.dst == local_ip);
+        let broadcast = configured && ip.dst == Ipv4Addr::BROADCAST;
+        match ip.protocol {
+            IP_PROTO_ICMP if for_us => icmp::handle_icmp(&ip),
+            IP_PROTO_TCP if for_us => tcp::handle_segment(&ip),
+            IP_PROTO_UDP if for_us || broadcast => udp::handle_datagram(&ip),
+            _ => {}
         }
-        _ => {}
+    } else if let Some(arp) = ArpPacket::parse(data) {
+        arp::handle_arp(&arp);
     }
 }
 
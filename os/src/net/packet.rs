@@ -0,0 +1,459 @@
+//! Parsing and building of the frames the stack handles itself.
+//!
+//! Multi-byte header fields are big endian on the wire. Checksums are
+//! verified on input and filled in on output.
+use alloc::vec;
+use alloc::vec::Vec;
+use bitflags::*;
+use core::fmt;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+pub const ETH_HEADER_LEN: usize = 14;
+/// Largest IPv4 datagram sent, the Ethernet MTU
+pub const IP_MTU: usize = 1500;
+pub const IPV4_HEADER_LEN: usize = 20;
+pub const TCP_HEADER_LEN: usize = 20;
+pub const UDP_HEADER_LEN: usize = 8;
+pub const ICMP_HEADER_LEN: usize = 8;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const IP_PROTO_ICMP: u8 = 1;
+pub const IP_PROTO_TCP: u8 = 6;
+pub const IP_PROTO_UDP: u8 = 17;
+
+const IP_DEFAULT_TTL: u8 = 64;
+/// Length of an ARP packet for IPv4 over Ethernet
+const ARP_LEN: usize = 28;
+pub const ICMP_ECHO_REPLY: u8 = 0;
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+/// TCP option kinds
+const TCP_OPT_END: u8 = 0;
+const TCP_OPT_NOP: u8 = 1;
+const TCP_OPT_MSS: u8 = 2;
+
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Self = Self([0, 0, 0, 0]);
+    pub const BROADCAST: Self = Self([255, 255, 255, 255]);
+    pub const LOCALHOST: Self = Self([127, 0, 0, 1]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Self([a, b, c, d])
+    }
+
+    /// `addr` is in host order, `10 << 24 | 2` is 10.0.0.2
+    pub const fn from_u32(addr: u32) -> Self {
+        Self(addr.to_be_bytes())
+    }
+
+    pub const fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    /// Parse dotted decimal such as `10.0.2.15`
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut addr = [0u8; 4];
+        let mut parts = s.split('.');
+        for byte in addr.iter_mut() {
+            *byte = parts.next()?.parse().ok()?;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self(addr))
+    }
+
+    /// The netmask of a `prefix_len` bit prefix
+    pub const fn netmask(prefix_len: u8) -> Self {
+        match prefix_len {
+            0 => Self::UNSPECIFIED,
+            len if len >= 32 => Self::BROADCAST,
+            len => Self::from_u32(!0u32 << (32 - len)),
+        }
+    }
+
+    /// Whether `self` and `other` share the first `prefix_len` bits
+    pub const fn same_subnet(self, other: Self, prefix_len: u8) -> bool {
+        let mask = Self::netmask(prefix_len).to_u32();
+        self.to_u32() & mask == other.to_u32() & mask
+    }
+
+    /// Whether this is in 127.0.0.0/8
+    pub const fn is_loopback(self) -> bool {
+        self.0[0] == 127
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: Self = Self([0xff; 6]);
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+/// Add `data` to a ones' complement sum as a run of 16-bit words
+fn sum_words(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The internet checksum of `data`, zero when `data` already carries a valid one
+pub fn checksum(data: &[u8]) -> u16 {
+    fold(sum_words(0, data))
+}
+
+/// Checksum of a TCP or UDP `segment` including the IPv4 pseudo header
+pub fn transport_checksum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, segment: &[u8]) -> u16 {
+    let mut sum = sum_words(0, &src.0);
+    sum = sum_words(sum, &dst.0);
+    sum += protocol as u32;
+    sum += segment.len() as u32;
+    fold(sum_words(sum, segment))
+}
+
+pub const ARP_REQUEST: u16 = 1;
+pub const ARP_REPLY: u16 = 2;
+
+/// An ARP packet resolving an IPv4 address on Ethernet
+pub struct ArpPacket {
+    pub op: u16,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    /// Parse an Ethernet frame, None if it is not ARP for IPv4 over Ethernet
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.len() < ETH_HEADER_LEN + ARP_LEN || read_u16(frame, 12) != ETHERTYPE_ARP {
+            return None;
+        }
+        let arp = &frame[ETH_HEADER_LEN..];
+        // hardware type Ethernet, protocol IPv4, address lengths 6 and 4
+        if read_u16(arp, 0) != 1 || read_u16(arp, 2) != ETHERTYPE_IPV4 || arp[4] != 6 || arp[5] != 4 {
+            return None;
+        }
+        let mac = |offset: usize| {
+            let mut mac = [0u8; 6];
+            mac.copy_from_slice(&arp[offset..offset + 6]);
+            MacAddr(mac)
+        };
+        let ip = |offset: usize| Ipv4Addr([arp[offset], arp[offset + 1], arp[offset + 2], arp[offset + 3]]);
+        Some(Self {
+            op: read_u16(arp, 6),
+            sender_mac: mac(8),
+            sender_ip: ip(14),
+            target_mac: mac(18),
+            target_ip: ip(24),
+        })
+    }
+
+    /// Serialize the packet in an Ethernet frame to `dst_mac`
+    pub fn build(&self, dst_mac: MacAddr) -> Vec<u8> {
+        let mut frame = vec![0u8; ETH_HEADER_LEN + ARP_LEN];
+        frame[0..6].copy_from_slice(&dst_mac.0);
+        frame[6..12].copy_from_slice(&self.sender_mac.0);
+        frame[12..14].copy_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+        let arp = &mut frame[ETH_HEADER_LEN..];
+        arp[0..2].copy_from_slice(&1u16.to_be_bytes());
+        arp[2..4].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        arp[4] = 6;
+        arp[5] = 4;
+        arp[6..8].copy_from_slice(&self.op.to_be_bytes());
+        arp[8..14].copy_from_slice(&self.sender_mac.0);
+        arp[14..18].copy_from_slice(&self.sender_ip.0);
+        arp[18..24].copy_from_slice(&self.target_mac.0);
+        arp[24..28].copy_from_slice(&self.target_ip.0);
+        frame
+    }
+}
+
+/// An IPv4 datagram inside an Ethernet frame
+pub struct Ipv4Packet<'a> {
+    pub src_mac: MacAddr,
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// Parse an Ethernet frame, None if it is not an intact, unfragmented IPv4 datagram
+    pub fn parse(frame: &'a [u8]) -> Option<Self> {
+        if frame.len() < ETH_HEADER_LEN + IPV4_HEADER_LEN
+            || read_u16(frame, 12) != ETHERTYPE_IPV4
+        {
+            return None;
+        }
+        let ip = &frame[ETH_HEADER_LEN..];
+        let header_len = (ip[0] & 0xf) as usize * 4;
+        let total_len = read_u16(ip, 2) as usize;
+        if ip[0] >> 4 != 4
+            || header_len < IPV4_HEADER_LEN
+            || total_len < header_len
+            || total_len > ip.len()
+            || checksum(&ip[..header_len]) != 0
+        {
+            return None;
+        }
+        // more fragments set, or a non-zero fragment offset
+        if read_u16(ip, 6) & 0x3fff != 0 {
+            return None;
+        }
+        let mut src_mac = [0u8; 6];
+        src_mac.copy_from_slice(&frame[6..12]);
+        Some(Self {
+            src_mac: MacAddr(src_mac),
+            src: Ipv4Addr([ip[12], ip[13], ip[14], ip[15]]),
+            dst: Ipv4Addr([ip[16], ip[17], ip[18], ip[19]]),
+            protocol: ip[9],
+            payload: &ip[header_len..total_len],
+        })
+    }
+}
+
+/// Wrap `payload` in an IPv4 header and an Ethernet header
+pub fn build_ipv4(
+    src_mac: MacAddr,
+    dst_mac: MacAddr,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    static IDENT: AtomicU16 = AtomicU16::new(1);
+    let total_len = IPV4_HEADER_LEN + payload.len();
+    let mut frame = vec![0u8; ETH_HEADER_LEN + total_len];
+    frame[0..6].copy_from_slice(&dst_mac.0);
+    frame[6..12].copy_from_slice(&src_mac.0);
+    frame[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+    let ip = &mut frame[ETH_HEADER_LEN..];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+    ip[4..6].copy_from_slice(&IDENT.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    // don't fragment
+    ip[6] = 0x40;
+    ip[8] = IP_DEFAULT_TTL;
+    ip[9] = protocol;
+    ip[12..16].copy_from_slice(&src.0);
+    ip[16..20].copy_from_slice(&dst.0);
+    let sum = checksum(&ip[..IPV4_HEADER_LEN]);
+    ip[10..12].copy_from_slice(&sum.to_be_bytes());
+    ip[IPV4_HEADER_LEN..].copy_from_slice(payload);
+    frame
+}
+
+bitflags! {
+    pub struct TcpFlags: u8 {
+        const FIN = 0x01;
+        const SYN = 0x02;
+        const RST = 0x04;
+        const PSH = 0x08;
+        const ACK = 0x10;
+        const URG = 0x20;
+    }
+}
+
+/// A TCP segment, the only option understood is the maximum segment size
+pub struct TcpSegment<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: TcpFlags,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    /// Parse the payload of `ip`, None if it is malformed or the checksum is wrong
+    pub fn parse(ip: &Ipv4Packet<'a>) -> Option<Self> {
+        let data = ip.payload;
+        if data.len() < TCP_HEADER_LEN
+            || transport_checksum(ip.src, ip.dst, IP_PROTO_TCP, data) != 0
+        {
+            return None;
+        }
+        let header_len = (data[12] >> 4) as usize * 4;
+        if header_len < TCP_HEADER_LEN || header_len > data.len() {
+            return None;
+        }
+        let mut mss = None;
+        let mut options = &data[TCP_HEADER_LEN..header_len];
+        while let Some(&kind) = options.first() {
+            match kind {
+                TCP_OPT_END => break,
+                TCP_OPT_NOP => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        return None;
+                    }
+                    if kind == TCP_OPT_MSS && len == 4 {
+                        mss = Some(read_u16(options, 2));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+        Some(Self {
+            src_port: read_u16(data, 0),
+            dst_port: read_u16(data, 2),
+            seq: read_u32(data, 4),
+            ack: read_u32(data, 8),
+            flags: TcpFlags::from_bits_truncate(data[13]),
+            window: read_u16(data, 14),
+            mss,
+            payload: &data[header_len..],
+        })
+    }
+
+    /// Serialize the segment sent from `src` to `dst`, checksum included
+    pub fn build(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+        let header_len = TCP_HEADER_LEN + if self.mss.is_some() { 4 } else { 0 };
+        let mut data = vec![0u8; header_len + self.payload.len()];
+        data[0..2].copy_from_slice(&self.src_port.to_be_bytes());
+        data[2..4].copy_from_slice(&self.dst_port.to_be_bytes());
+        data[4..8].copy_from_slice(&self.seq.to_be_bytes());
+        data[8..12].copy_from_slice(&self.ack.to_be_bytes());
+        data[12] = (header_len as u8 / 4) << 4;
+        data[13] = self.flags.bits();
+        data[14..16].copy_from_slice(&self.window.to_be_bytes());
+        if let Some(mss) = self.mss {
+            data[20] = TCP_OPT_MSS;
+            data[21] = 4;
+            data[22..24].copy_from_slice(&mss.to_be_bytes());
+        }
+        data[header_len..].copy_from_slice(self.payload);
+        let sum = transport_checksum(src, dst, IP_PROTO_TCP, &data);
+        data[16..18].copy_from_slice(&sum.to_be_bytes());
+        data
+    }
+}
+
+/// A UDP datagram
+pub struct UdpDatagram<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    /// Parse the payload of `ip`, a zero checksum means the sender did not compute one
+    pub fn parse(ip: &Ipv4Packet<'a>) -> Option<Self> {
+        let data = ip.payload;
+        if data.len() < UDP_HEADER_LEN {
+            return None;
+        }
+        let len = read_u16(data, 4) as usize;
+        if len < UDP_HEADER_LEN || len > data.len() {
+            return None;
+        }
+        if read_u16(data, 6) != 0 && transport_checksum(ip.src, ip.dst, IP_PROTO_UDP, &data[..len]) != 0 {
+            return None;
+        }
+        Some(Self {
+            src_port: read_u16(data, 0),
+            dst_port: read_u16(data, 2),
+            payload: &data[UDP_HEADER_LEN..len],
+        })
+    }
+
+    /// Serialize the datagram sent from `src` to `dst`, checksum included
+    pub fn build(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+        let len = UDP_HEADER_LEN + self.payload.len();
+        let mut data = vec![0u8; len];
+        data[0..2].copy_from_slice(&self.src_port.to_be_bytes());
+        data[2..4].copy_from_slice(&self.dst_port.to_be_bytes());
+        data[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+        data[UDP_HEADER_LEN..].copy_from_slice(self.payload);
+        let sum = match transport_checksum(src, dst, IP_PROTO_UDP, &data) {
+            // zero is sent as all ones, zero itself means no checksum
+            0 => 0xffff,
+            sum => sum,
+        };
+        data[6..8].copy_from_slice(&sum.to_be_bytes());
+        data
+    }
+}
+
+/// An ICMP message, `id` and `seq` are the rest of the header as echo messages use it
+pub struct IcmpPacket<'a> {
+    pub icmp_type: u8,
+    pub code: u8,
+    pub id: u16,
+    pub seq: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> IcmpPacket<'a> {
+    /// Parse the payload of `ip`, None if it is too short or the checksum is wrong
+    pub fn parse(ip: &Ipv4Packet<'a>) -> Option<Self> {
+        let data = ip.payload;
+        if data.len() < ICMP_HEADER_LEN || checksum(data) != 0 {
+            return None;
+        }
+        Some(Self {
+            icmp_type: data[0],
+            code: data[1],
+            id: read_u16(data, 4),
+            seq: read_u16(data, 6),
+            payload: &data[ICMP_HEADER_LEN..],
+        })
+    }
+
+    /// Serialize the message, checksum included
+    pub fn build(&self) -> Vec<u8> {
+        let mut data = vec![0u8; ICMP_HEADER_LEN + self.payload.len()];
+        data[0] = self.icmp_type;
+        data[1] = self.code;
+        data[4..6].copy_from_slice(&self.id.to_be_bytes());
+        data[6..8].copy_from_slice(&self.seq.to_be_bytes());
+        data[ICMP_HEADER_LEN..].copy_from_slice(self.payload);
+        let sum = checksum(&data);
+        data[2..4].copy_from_slice(&sum.to_be_bytes());
+        data
+    }
+}
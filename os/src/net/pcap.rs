@@ -0,0 +1,154 @@
+//! Capture of the frames the network device sends and receives.
+//!
+//! Opening `/dev/pcap` starts a capture and closing it stops it. Reading it
+//! yields a pcap stream: the file header, then one record per frame, ready
+//! to be saved and opened in Wireshark. Frames that don't fit in the buffer
+//! while the reader falls behind are dropped.
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::config::CLOCK_FREQ;
+use crate::fs::{File, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::{Condvar, PollQueue, SpinNoIrqLock};
+use crate::task::schedule;
+use crate::timer::get_time;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const SNAPLEN: u32 = 65535;
+const LINKTYPE_ETHERNET: u32 = 1;
+/// Bytes of the stream held for the reader
+const MAX_BUFFERED: usize = 256 * 1024;
+
+struct Capture {
+    /// the pcap stream not read yet
+    buffer: VecDeque<u8>,
+    /// frames left out because the buffer was full
+    dropped: usize,
+    condvar: Arc<Condvar>,      // the reader waiting for frames
+    poll_queue: Arc<PollQueue>, // tasks in `poll` waiting for frames
+}
+
+lazy_static! {
+    static ref CAPTURE: SpinNoIrqLock<Option<Capture>> = SpinNoIrqLock::new(None);
+}
+
+/// Record `frame` if a capture is running
+pub fn capture(frame: &[u8]) {
+    let mut capture = CAPTURE.lock();
+    let capture = match capture.as_mut() {
+        Some(capture) => capture,
+        None => return,
+    };
+    let len = frame.len().min(SNAPLEN as usize);
+    if capture.buffer.len() + 16 + len > MAX_BUFFERED {
+        capture.dropped += 1;
+        return;
+    }
+    // uptime, there is no wall clock
+    let time = get_time();
+    let sec = (time / CLOCK_FREQ) as u32;
+    let usec = (time % CLOCK_FREQ * 1_000_000 / CLOCK_FREQ) as u32;
+    for field in [sec, usec, len as u32, frame.len() as u32] {
+        capture.buffer.extend(field.to_le_bytes());
+    }
+    capture.buffer.extend(&frame[..len]);
+    capture.condvar.signal();
+    capture.poll_queue.wake_all();
+}
+
+/// A running capture, read as `/dev/pcap`
+pub struct PcapCapture;
+
+impl PcapCapture {
+    /// Start capturing, None if a capture is already running
+    pub fn start() -> Option<Self> {
+        let mut capture = CAPTURE.lock();
+        if capture.is_some() {
+            return None;
+        }
+        let mut buffer = VecDeque::new();
+        buffer.extend(PCAP_MAGIC.to_le_bytes());
+        buffer.extend(PCAP_VERSION_MAJOR.to_le_bytes());
+        buffer.extend(PCAP_VERSION_MINOR.to_le_bytes());
+        // time zone offset and timestamp accuracy
+        buffer.extend([0u8; 8]);
+        buffer.extend(SNAPLEN.to_le_bytes());
+        buffer.extend(LINKTYPE_ETHERNET.to_le_bytes());
+        *capture = Some(Capture {
+            buffer,
+            dropped: 0,
+            condvar: Arc::new(Condvar::new()),
+            poll_queue: Arc::new(PollQueue::new()),
+        });
+        Some(Self)
+    }
+}
+
+impl File for PcapCapture {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    /// Block until some of the stream is captured, then copy what fits
+    fn read(&self, buf: UserBuffer) -> usize {
+        let mut capture = CAPTURE.lock();
+        loop {
+            let inner = capture.as_mut().unwrap();
+            if !inner.buffer.is_empty() {
+                break;
+            }
+            let task_cx_ptr = inner.condvar.clone().wait_no_sched();
+            drop(capture);
+            schedule(task_cx_ptr);
+            capture = CAPTURE.lock();
+        }
+        let buffer = &mut capture.as_mut().unwrap().buffer;
+        let mut copied = 0;
+        for byte in buf.into_iter() {
+            match buffer.pop_front() {
+                Some(value) => unsafe { *byte = value },
+                None => break,
+            }
+            copied += 1;
+        }
+        copied
+    }
+
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let capture = CAPTURE.lock();
+        let mut ready = PollEvents::empty();
+        if !capture.as_ref().unwrap().buffer.is_empty() {
+            ready |= PollEvents::IN;
+        }
+        ready & events
+    }
+
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        let capture = CAPTURE.lock();
+        capture.as_ref().map(|capture| capture.poll_queue.clone())
+    }
+}
+
+impl Drop for PcapCapture {
+    fn drop(&mut self) {
+        let capture = CAPTURE.lock().take();
+        if let Some(capture) = capture {
+            if capture.dropped > 0 {
+                warn!("pcap: {} frames dropped", capture.dropped);
+            }
+        }
+    }
+}
@@ -1,26 +1,35 @@
+use alloc::collections::VecDeque;
 use alloc::{sync::Arc, vec::Vec};
 use lazy_static::lazy_static;
-use lose_net_stack::packets::tcp::TCPPacket;
 
-use crate::fs::File;
-use crate::sync::UPIntrFreeCell;
-use crate::task::TaskControlBlock;
+use crate::fs::{File, PollEvents};
+use crate::sync::{Condvar, PollQueue, SpinNoIrqLock};
+use crate::task::schedule;
 
-use super::tcp::TCP;
+use super::tcp::abort_listener;
+
+/// Upper bound for the backlog passed to `listen`
+const SOMAXCONN: usize = 128;
 
 pub struct Port {
     pub port: u16,
-    pub receivable: bool,
-    pub schedule: Option<Arc<TaskControlBlock>>,
+    pub backlog: usize,                 // connections held that nobody has accepted yet
+    pub half_open: usize,               // connections still in the handshake
+    pub accept_queue: VecDeque<usize>,  // established connections waiting for `accept`
+    pub condvar: Arc<Condvar>,          // tasks blocked in `accept`
+    pub poll_queue: Arc<PollQueue>,     // tasks in `poll` waiting for a connection
 }
 
 lazy_static! {
-    static ref LISTEN_TABLE: UPIntrFreeCell<Vec<Option<Port>>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
+    static ref LISTEN_TABLE: SpinNoIrqLock<Vec<Option<Port>>> =
+        SpinNoIrqLock::new(Vec::new());
 }
 
-pub fn listen(port: u16) -> Option<usize> {
-    let mut listen_table = LISTEN_TABLE.exclusive_access();
+pub fn listen(port: u16, backlog: usize) -> Option<usize> {
+    let mut listen_table = LISTEN_TABLE.lock();
+    if listen_table.iter().flatten().any(|listen_port| listen_port.port == port) {
+        return None;
+    }
     let mut index = usize::MAX;
     for i in 0..listen_table.len() {
         if listen_table[i].is_none() {
@@ -31,8 +40,11 @@ pub fn listen(port: u16) -> Option<usize> {
 
     let listen_port = Port {
         port,
-        receivable: false,
-        schedule: None,
+        backlog: backlog.clamp(1, SOMAXCONN),
+        half_open: 0,
+        accept_queue: VecDeque::new(),
+        condvar: Arc::new(Condvar::new()),
+        poll_queue: Arc::new(PollQueue::new()),
     };
 
     if index == usize::MAX {
@@ -44,67 +56,60 @@ pub fn listen(port: u16) -> Option<usize> {
     }
 }
 
-// can accept request
-pub fn accept(listen_index: usize, task: Arc<TaskControlBlock>) {
-    let mut listen_table = LISTEN_TABLE.exclusive_access();
-    assert!(listen_index < listen_table.len());
-    let listen_port = listen_table[listen_index].as_mut();
-    assert!(listen_port.is_some());
-    let listen_port = listen_port.unwrap();
-    listen_port.receivable = true;
-    listen_port.schedule = Some(task);
-}
-
-pub fn port_acceptable(listen_index: usize) -> bool {
-    let mut listen_table = LISTEN_TABLE.exclusive_access();
-    assert!(listen_index < listen_table.len());
-
-    let listen_port = listen_table[listen_index].as_mut();
-    listen_port.map_or(false, |x| x.receivable)
+/// whether a listener is bound to `port`
+pub fn listening_on(port: u16) -> bool {
+    LISTEN_TABLE
+        .lock()
+        .iter()
+        .flatten()
+        .any(|listen_port| listen_port.port == port)
 }
 
-// check whether it can accept request
-pub fn check_accept(port: u16, tcp_packet: &TCPPacket) -> Option<()> {
-    LISTEN_TABLE.exclusive_session(|listen_table| {
-        let mut listen_ports: Vec<&mut Option<Port>> = listen_table
-            .iter_mut()
-            .filter(|x| match x {
-                Some(t) => t.port == port && t.receivable == true,
-                None => false,
-            })
-            .collect();
-        if listen_ports.len() == 0 {
-            None
-        } else {
-            let listen_port = listen_ports[0].as_mut().unwrap();
-            let task = listen_port.schedule.clone().unwrap();
-            // wakeup_task(Arc::clone(&listen_port.schedule.clone().unwrap()));
-            listen_port.schedule = None;
-            listen_port.receivable = false;
-
-            accept_connection(port, tcp_packet, task);
-            Some(())
+/// Take an established connection, blocking until one arrives
+pub fn accept(listen_index: usize) -> Option<usize> {
+    loop {
+        let mut listen_table = LISTEN_TABLE.lock();
+        let listen_port = listen_table.get_mut(listen_index)?.as_mut()?;
+        if let Some(connection) = listen_port.accept_queue.pop_front() {
+            return Some(connection);
         }
-    })
+        let task_cx_ptr = listen_port.condvar.clone().wait_no_sched();
+        drop(listen_table);
+        schedule(task_cx_ptr);
+    }
 }
 
-pub fn accept_connection(_port: u16, tcp_packet: &TCPPacket, task: Arc<TaskControlBlock>) {
-    let process = task.process.upgrade().unwrap();
-    let mut inner = process.inner_exclusive_access();
-    let fd = inner.alloc_fd();
-
-    let tcp_socket = TCP::new(
-        tcp_packet.source_ip,
-        tcp_packet.dest_port,
-        tcp_packet.source_port,
-        tcp_packet.seq,
-        tcp_packet.ack,
-    );
+/// Make room for a new connection on `port`, returning its listener
+pub fn reserve_backlog(port: u16) -> Option<usize> {
+    let mut listen_table = LISTEN_TABLE.lock();
+    let index = listen_table
+        .iter()
+        .position(|x| x.as_ref().map_or(false, |t| t.port == port))?;
+    let listen_port = listen_table[index].as_mut().unwrap();
+    if listen_port.half_open + listen_port.accept_queue.len() >= listen_port.backlog {
+        return None;
+    }
+    listen_port.half_open += 1;
+    Some(index)
+}
 
-    inner.fd_table[fd] = Some(Arc::new(tcp_socket));
+/// The handshake of a connection reserved with `reserve_backlog` completed
+pub fn connection_ready(listen_index: usize, connection: usize) {
+    let mut listen_table = LISTEN_TABLE.lock();
+    if let Some(Some(listen_port)) = listen_table.get_mut(listen_index) {
+        listen_port.half_open -= 1;
+        listen_port.accept_queue.push_back(connection);
+        listen_port.condvar.signal();
+        listen_port.poll_queue.wake_all();
+    }
+}
 
-    let cx = task.inner_exclusive_access().get_trap_cx();
-    cx.x[10] = fd;
+/// The handshake of a connection reserved with `reserve_backlog` failed
+pub fn connection_failed(listen_index: usize) {
+    let mut listen_table = LISTEN_TABLE.lock();
+    if let Some(Some(listen_port)) = listen_table.get_mut(listen_index) {
+        listen_port.half_open -= 1;
+    }
 }
 
 // store in the fd_table, delete the listen table when close the application.
@@ -114,11 +119,24 @@ impl PortFd {
     pub fn new(port_index: usize) -> Self {
         PortFd(port_index)
     }
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+
+    pub fn port(&self) -> u16 {
+        LISTEN_TABLE.lock()[self.0].as_ref().unwrap().port
+    }
 }
 
 impl Drop for PortFd {
+    /// Reset every connection that will never be accepted now
     fn drop(&mut self) {
-        LISTEN_TABLE.exclusive_access()[self.0] = None
+        let listen_port = LISTEN_TABLE.lock()[self.0].take();
+        if let Some(listen_port) = listen_port {
+            let queued: Vec<usize> = listen_port.accept_queue.into_iter().collect();
+            abort_listener(self.0, &queued);
+        }
     }
 }
 
@@ -138,4 +156,23 @@ impl File for PortFd {
     fn write(&self, _buf: crate::mm::UserBuffer) -> usize {
         0
     }
+
+    /// Readable once a connection is waiting to be accepted
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let listen_table = LISTEN_TABLE.lock();
+        match listen_table.get(self.0) {
+            Some(Some(listen_port)) if !listen_port.accept_queue.is_empty() => {
+                events & PollEvents::IN
+            }
+            _ => PollEvents::empty(),
+        }
+    }
+
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        let listen_table = LISTEN_TABLE.lock();
+        listen_table
+            .get(self.0)
+            .and_then(|listen_port| listen_port.as_ref())
+            .map(|listen_port| listen_port.poll_queue.clone())
+    }
 }
@@ -1,123 +1,329 @@
-use alloc::collections::VecDeque;
+//! Sockets as seen through file descriptors.
+//!
+//! A socket starts out unconnected and becomes a stream, a listener or a
+//! datagram endpoint through the socket syscalls. The protocol state lives
+//! in `tcp`, `port_table`, `udp` and `icmp`, this is only the handle to it.
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use lazy_static::lazy_static;
-use lose_net_stack::IPv4;
 
-use crate::sync::UPIntrFreeCell;
+use super::icmp::{self, ICMP};
+use super::packet::{Ipv4Addr, IP_PROTO_ICMP, IP_PROTO_TCP, IP_PROTO_UDP};
+use super::port_table::{accept, listen, PortFd};
+use super::tcp::{self, BoundPort, TCP};
+use super::udp::{self, UDP};
+use super::ephemeral_port;
+use super::iface::local_ip;
+use crate::fs::{File, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::{PollQueue, SpinNoIrqLock};
+use crate::task::{current_add_signal, SignalFlags};
 
-// TODO: specify the protocol, TCP or UDP
-pub struct Socket {
-    pub raddr: IPv4,                // remote address
-    pub lport: u16,                 // local port
-    pub rport: u16,                 // rempote port
-    pub buffers: VecDeque<Vec<u8>>, // datas
-    pub seq: u32,
-    pub ack: u32,
-}
+pub const AF_INET: usize = 2;
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+/// May be or'ed into the type passed to `socket`
+pub const SOCK_CLOEXEC: usize = 0x80000;
 
-lazy_static! {
-    static ref SOCKET_TABLE: UPIntrFreeCell<Vec<Option<Socket>>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
-}
+pub const SHUT_RD: usize = 0;
+pub const SHUT_WR: usize = 1;
+pub const SHUT_RDWR: usize = 2;
 
-/// get the seq and ack by socket index
-pub fn get_s_a_by_index(index: usize) -> Option<(u32, u32)> {
-    let socket_table = SOCKET_TABLE.exclusive_access();
+const EPIPE: isize = 32;
 
-    assert!(index < socket_table.len());
+/// Copy a user buffer into one datagram
+fn gather(buf: &UserBuffer) -> Vec<u8> {
+    buf.buffers.iter().flat_map(|slice| slice.iter().copied()).collect()
+}
 
-    socket_table.get(index).map_or(None, |x| match x {
-        Some(x) => Some((x.seq, x.ack)),
-        None => None,
-    })
+/// `struct sockaddr_in`, port and address in network order
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SockAddrIn {
+    pub family: u16,
+    pub port: [u8; 2],
+    pub addr: [u8; 4],
+    pub zero: [u8; 8],
 }
 
-pub fn set_s_a_by_index(index: usize, seq: u32, ack: u32) {
-    let mut socket_table = SOCKET_TABLE.exclusive_access();
+impl SockAddrIn {
+    pub fn new((addr, port): (Ipv4Addr, u16)) -> Self {
+        Self {
+            family: AF_INET as u16,
+            port: port.to_be_bytes(),
+            addr: addr.0,
+            zero: [0; 8],
+        }
+    }
 
-    assert!(socket_table.len() > index);
-    assert!(socket_table[index].is_some());
+    pub fn endpoint(&self) -> Option<(Ipv4Addr, u16)> {
+        if self.family != AF_INET as u16 {
+            return None;
+        }
+        Some((Ipv4Addr(self.addr), u16::from_be_bytes(self.port)))
+    }
+}
+
+enum SocketState {
+    /// a TCP socket before `connect` or `listen`, with the port from `bind`
+    Tcp(Option<BoundPort>),
+    Stream(Arc<TCP>),
+    Listener(Arc<PortFd>),
+    Datagram(Arc<UDP>),
+    /// a ping socket, see `icmp`
+    Icmp(Arc<ICMP>),
+}
 
-    let sock = socket_table[index].as_mut().unwrap();
+struct SocketInner {
+    state: SocketState,
+    read_shut: bool,
+    write_shut: bool,
+}
 
-    sock.ack = ack;
-    sock.seq = seq;
+pub struct Socket {
+    inner: SpinNoIrqLock<SocketInner>,
 }
 
-pub fn get_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
-    let socket_table = SOCKET_TABLE.exclusive_access();
-    for i in 0..socket_table.len() {
-        let sock = &socket_table[i];
-        if sock.is_none() {
-            continue;
+impl Socket {
+    /// A socket of `sock_type` for `protocol`, 0 picks the default one.
+    /// None for a combination that is not supported.
+    pub fn new(sock_type: usize, protocol: usize) -> Option<Self> {
+        if protocol > u8::MAX as usize {
+            return None;
         }
+        let state = match (sock_type, protocol as u8) {
+            (SOCK_STREAM, 0 | IP_PROTO_TCP) => SocketState::Tcp(None),
+            (SOCK_DGRAM, 0 | IP_PROTO_UDP) => {
+                SocketState::Datagram(Arc::new(UDP::new(0)?))
+            }
+            (SOCK_DGRAM, IP_PROTO_ICMP) => {
+                SocketState::Icmp(Arc::new(ICMP::new(ephemeral_port(icmp::ident_in_use))?))
+            }
+            _ => return None,
+        };
+        Some(Self::with_state(state))
+    }
 
-        let sock = sock.as_ref().unwrap();
-        if sock.raddr == raddr && sock.lport == lport && sock.rport == rport {
-            return Some(i);
+    fn with_state(state: SocketState) -> Self {
+        Self {
+            inner: SpinNoIrqLock::new(SocketInner {
+                state,
+                read_shut: false,
+                write_shut: false,
+            }),
         }
     }
-    None
-}
 
-pub fn add_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
-    if get_socket(raddr, lport, rport).is_some() {
-        return None;
+    /// Give the socket a local port, 0 picks a free one.
+    /// The only addresses that can be bound are ours and the loopback ones.
+    pub fn bind(&self, (addr, port): (Ipv4Addr, u16)) -> bool {
+        if addr != Ipv4Addr::UNSPECIFIED && !addr.is_loopback() && addr != local_ip() {
+            return false;
+        }
+        let mut inner = self.inner.lock();
+        match &inner.state {
+            SocketState::Tcp(None) => match BoundPort::new(port) {
+                Some(port) => {
+                    inner.state = SocketState::Tcp(Some(port));
+                    true
+                }
+                None => false,
+            },
+            SocketState::Datagram(udp) => udp.rebind(port),
+            // the port of a ping socket is the identifier of its requests
+            SocketState::Icmp(icmp) => {
+                let ident = if port == 0 { ephemeral_port(icmp::ident_in_use) } else { port };
+                icmp.rebind(ident)
+            }
+            _ => false,
+        }
     }
 
-    let mut socket_table = SOCKET_TABLE.exclusive_access();
-    let mut index = usize::MAX;
-    for i in 0..socket_table.len() {
-        if socket_table[i].is_none() {
-            index = i;
-            break;
+    /// Start accepting connections, at most `backlog` of them wait for `accept`
+    pub fn listen(&self, backlog: usize) -> bool {
+        let mut inner = self.inner.lock();
+        let port = match &inner.state {
+            SocketState::Tcp(port) => port
+                .as_ref()
+                .map_or_else(|| ephemeral_port(tcp::port_in_use), BoundPort::port),
+            _ => return false,
+        };
+        match listen(port, backlog) {
+            Some(index) => {
+                inner.state = SocketState::Listener(Arc::new(PortFd::new(index)));
+                true
+            }
+            None => false,
         }
     }
 
-    let socket = Socket {
-        raddr,
-        lport,
-        rport,
-        buffers: VecDeque::new(),
-        seq: 0,
-        ack: 0,
-    };
+    /// Wait for a connection on a listening socket
+    pub fn accept(&self) -> Option<(Socket, (Ipv4Addr, u16))> {
+        let index = match &self.inner.lock().state {
+            SocketState::Listener(port_fd) => port_fd.index(),
+            _ => return None,
+        };
+        let stream = TCP::new(accept(index)?);
+        let (_, remote_ip, remote_port) = stream.endpoints();
+        Some((
+            Self::with_state(SocketState::Stream(Arc::new(stream))),
+            (remote_ip, remote_port),
+        ))
+    }
 
-    if index == usize::MAX {
-        socket_table.push(Some(socket));
-        Some(socket_table.len() - 1)
-    } else {
-        socket_table[index] = Some(socket);
-        Some(index)
+    /// Open a TCP connection, blocking until it is established,
+    /// or set the peer of a UDP or ping socket
+    pub fn connect(&self, (addr, port): (Ipv4Addr, u16)) -> bool {
+        let local_port = match &self.inner.lock().state {
+            SocketState::Tcp(local_port) => local_port.as_ref().map(BoundPort::port),
+            SocketState::Datagram(udp) => {
+                udp.connect((addr, port));
+                return true;
+            }
+            SocketState::Icmp(icmp) => {
+                icmp.connect(addr);
+                return true;
+            }
+            _ => return false,
+        };
+        let local_port = local_port.unwrap_or_else(|| ephemeral_port(tcp::port_in_use));
+        // the handshake blocks, so the socket is not held meanwhile
+        let stream = match tcp::connect(local_port, addr, port) {
+            Some(stream) => stream,
+            None => return false,
+        };
+        let mut inner = self.inner.lock();
+        if !matches!(inner.state, SocketState::Tcp(_)) {
+            return false;
+        }
+        inner.state = SocketState::Stream(Arc::new(stream));
+        true
     }
-}
 
-pub fn remove_socket(index: usize) {
-    let mut socket_table = SOCKET_TABLE.exclusive_access();
+    /// Send a datagram to `to`, or on a stream ignore `to` and just write
+    pub fn send_to(&self, buf: UserBuffer, to: Option<(Ipv4Addr, u16)>) -> isize {
+        let to = match to {
+            Some(to) => to,
+            None => return self.write(buf) as isize,
+        };
+        let inner = self.inner.lock();
+        match &inner.state {
+            SocketState::Datagram(_) | SocketState::Icmp(_) if inner.write_shut => {
+                current_add_signal(SignalFlags::SIGPIPE);
+                -EPIPE
+            }
+            // too long for one packet, don't copy it just to fail
+            SocketState::Datagram(_) if buf.len() > udp::MAX_PAYLOAD_LEN => -1,
+            SocketState::Icmp(_) if buf.len() > icmp::MAX_MESSAGE_LEN => -1,
+            SocketState::Datagram(udp) => udp.send_to(&gather(&buf), to) as isize,
+            SocketState::Icmp(icmp) => icmp.send_to(&gather(&buf), to.0) as isize,
+            _ => {
+                drop(inner);
+                self.write(buf) as isize
+            }
+        }
+    }
 
-    assert!(socket_table.len() > index);
+    /// Receive into `buf`, also returning where the data came from
+    pub fn recv_from(&self, buf: UserBuffer) -> (isize, Option<(Ipv4Addr, u16)>) {
+        let inner = self.inner.lock();
+        if inner.read_shut {
+            return (0, None);
+        }
+        match &inner.state {
+            SocketState::Datagram(udp) => {
+                let udp = udp.clone();
+                drop(inner);
+                let (len, addr, port) = udp.recv_from(buf);
+                (len as isize, Some((addr, port)))
+            }
+            SocketState::Icmp(icmp) => {
+                let icmp = icmp.clone();
+                drop(inner);
+                let (len, addr) = icmp.recv_from(buf);
+                (len as isize, Some((addr, 0)))
+            }
+            SocketState::Stream(stream) => {
+                let stream = stream.clone();
+                drop(inner);
+                let (_, remote_ip, remote_port) = stream.endpoints();
+                (stream.read(buf) as isize, Some((remote_ip, remote_port)))
+            }
+            _ => (-1, None),
+        }
+    }
 
-    socket_table[index] = None;
+    /// Stop reading, writing or both. Shutting down writing on a stream sends a FIN.
+    pub fn shutdown(&self, how: usize) -> bool {
+        let mut inner = self.inner.lock();
+        if !matches!(
+            inner.state,
+            SocketState::Stream(_) | SocketState::Datagram(_) | SocketState::Icmp(_)
+        ) {
+            return false;
+        }
+        match how {
+            SHUT_RD => inner.read_shut = true,
+            SHUT_WR => inner.write_shut = true,
+            SHUT_RDWR => {
+                inner.read_shut = true;
+                inner.write_shut = true;
+            }
+            _ => return false,
+        }
+        if let SocketState::Stream(stream) = &inner.state {
+            if inner.write_shut {
+                stream.shutdown_write();
+            }
+        }
+        true
+    }
+
+    /// The state's file, cloned so no lock is held while it blocks
+    fn file(&self) -> Option<Arc<dyn File + Send + Sync>> {
+        match &self.inner.lock().state {
+            SocketState::Tcp(_) => None,
+            SocketState::Stream(stream) => Some(stream.clone()),
+            SocketState::Listener(port_fd) => Some(port_fd.clone()),
+            SocketState::Datagram(udp) => Some(udp.clone()),
+            SocketState::Icmp(icmp) => Some(icmp.clone()),
+        }
+    }
 }
 
-pub fn push_data(index: usize, data: Vec<u8>) {
-    let mut socket_table = SOCKET_TABLE.exclusive_access();
+impl File for Socket {
+    fn readable(&self) -> bool {
+        true
+    }
 
-    assert!(socket_table.len() > index);
-    assert!(socket_table[index].is_some());
+    fn writable(&self) -> bool {
+        true
+    }
 
-    socket_table[index]
-        .as_mut()
-        .unwrap()
-        .buffers
-        .push_back(data);
-}
+    fn read(&self, buf: UserBuffer) -> usize {
+        if self.inner.lock().read_shut {
+            return 0;
+        }
+        self.file().map_or(usize::MAX, |file| file.read(buf))
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        if self.inner.lock().write_shut {
+            current_add_signal(SignalFlags::SIGPIPE);
+            return (-EPIPE) as usize;
+        }
+        self.file().map_or(usize::MAX, |file| file.write(buf))
+    }
 
-pub fn pop_data(index: usize) -> Option<Vec<u8>> {
-    let mut socket_table = SOCKET_TABLE.exclusive_access();
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        self.file()
+            .map_or(PollEvents::empty(), |file| file.poll(events))
+    }
 
-    assert!(socket_table.len() > index);
-    assert!(socket_table[index].is_some());
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        self.file().and_then(|file| file.poll_queue())
+    }
 
-    socket_table[index].as_mut().unwrap().buffers.pop_front()
+    fn as_socket(&self) -> Option<&Socket> {
+        Some(self)
+    }
 }
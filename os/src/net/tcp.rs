@@ -1,44 +1,819 @@
-use alloc::vec;
-use lose_net_stack::packets::tcp::TCPPacket;
-use lose_net_stack::IPv4;
-use lose_net_stack::MacAddress;
-use lose_net_stack::TcpFlags;
-
-use crate::{ fs::File};
-use crate::drivers::net::NET_DEVICE;
-
-use super::socket::get_s_a_by_index;
-use super::{
-    net_interrupt_handler,
-    socket::{add_socket, pop_data, remove_socket},
-    LOSE_NET_STACK,
-};
-
-// add tcp packet info to this structure
-pub struct TCP {
-    pub target: IPv4,
-    pub sport: u16,
-    pub dport: u16,
-    pub seq: u32,
-    pub ack: u32,
-    pub socket_index: usize,
+//! TCP connections following the RFC 793 state machine.
+//!
+//! Each connection has a control block in `CONNECTIONS`. Segments are
+//! handled in the net interrupt and retransmission and TIME-WAIT timeouts
+//! are checked on every timer tick, so a connection keeps making progress
+//! while no task is inside a socket call.
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+use crate::fs::{File, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::{Condvar, PollQueue, SpinNoIrqLock};
+use crate::task::{current_add_signal, schedule, SignalFlags};
+use crate::timer::{get_time, get_time_ms};
+
+use super::packet::{Ipv4Addr, Ipv4Packet, TcpFlags, TcpSegment, IP_PROTO_TCP};
+use super::port_table::{connection_failed, connection_ready, listening_on, reserve_backlog};
+use super::{ephemeral_port, send_ipv4, source_ip};
+
+/// Returned by `write` on a connection that can't send any more
+const EPIPE: isize = 32;
+/// Returned by `read` on a connection the peer reset
+const ECONNRESET: isize = 104;
+
+/// Segment size we accept, an Ethernet MTU minus the IP and TCP headers
+const LOCAL_MSS: usize = 1460;
+/// Segment size assumed when the peer sends no MSS option
+const DEFAULT_MSS: usize = 536;
+const SEND_BUFFER_SIZE: usize = 16 * 1024;
+const RECV_BUFFER_SIZE: usize = 16 * 1024;
+/// Segments kept past a hole in the received data
+const MAX_OUT_OF_ORDER: usize = 32;
+
+const INITIAL_RTO_MS: usize = 1000;
+const MIN_RTO_MS: usize = 200;
+const MAX_RTO_MS: usize = 60_000;
+/// Timeouts in a row before the connection is given up
+const MAX_RETRIES: usize = 8;
+/// Maximum segment lifetime, TIME-WAIT lasts twice as long
+const MSL_MS: usize = 10_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
 }
 
-impl TCP {
-    pub fn new(target: IPv4, sport: u16, dport: u16, seq: u32, ack: u32) -> Self {
-        let index = add_socket(target, sport, dport).expect("can't add socket");
+/// `a` comes before `b` in sequence space
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+/// Transmission control block of one connection
+struct Tcb {
+    state: TcpState,
+    local_port: u16,
+    remote_ip: Ipv4Addr,
+    remote_port: u16,
+    /// listener the connection is reported to once the handshake completes
+    listener: Option<usize>,
+    /// a socket file or an accept queue still refers to the connection
+    attached: bool,
+    /// the peer reset the connection, or it timed out
+    reset: bool,
+
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u32,
+    mss: usize,
+    /// data from `snd_una` on, in flight first and then not yet sent
+    send_buffer: VecDeque<u8>,
+    /// the user closed the connection, a FIN follows the buffered data
+    fin_queued: bool,
+    /// sequence number of our FIN once it is sent
+    fin_seq: Option<u32>,
+
+    rcv_nxt: u32,
+    recv_buffer: VecDeque<u8>,
+    /// segments past a hole, as (sequence number, data)
+    out_of_order: Vec<(u32, Vec<u8>)>,
+    /// sequence number of the peer's FIN, it may arrive before the data in front of it
+    remote_fin: Option<u32>,
+    fin_received: bool,
 
+    rto: usize,
+    srtt: Option<usize>,
+    rttvar: usize,
+    /// segment being timed, as (ack that covers it, time sent)
+    rtt_sample: Option<(u32, usize)>,
+    retransmit_at: Option<usize>,
+    retries: usize,
+    /// end of TIME-WAIT, or of FIN-WAIT-2 once nobody can read the connection
+    close_at: Option<usize>,
+
+    /// tasks blocked reading or writing
+    condvar: Arc<Condvar>,
+    poll_queue: Arc<PollQueue>,
+}
+
+lazy_static! {
+    static ref CONNECTIONS: SpinNoIrqLock<Vec<Option<Tcb>>> =
+        SpinNoIrqLock::new(Vec::new());
+    /// ports of sockets that are bound but not listening or connected yet
+    static ref BOUND_PORTS: SpinNoIrqLock<Vec<u16>> = SpinNoIrqLock::new(Vec::new());
+}
+
+impl Tcb {
+    /// A connection sending its SYN to `remote_ip`
+    fn new_active(local_port: u16, remote_ip: Ipv4Addr, remote_port: u16) -> Self {
+        let iss = initial_sequence();
         Self {
-            target,
-            sport,
-            dport,
+            state: TcpState::SynSent,
+            local_port,
+            remote_ip,
+            remote_port,
+            listener: None,
+            attached: true,
+            reset: false,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1),
+            snd_wnd: 0,
+            mss: DEFAULT_MSS,
+            send_buffer: VecDeque::new(),
+            fin_queued: false,
+            fin_seq: None,
+            rcv_nxt: 0,
+            recv_buffer: VecDeque::new(),
+            out_of_order: Vec::new(),
+            remote_fin: None,
+            fin_received: false,
+            rto: INITIAL_RTO_MS,
+            srtt: None,
+            rttvar: 0,
+            rtt_sample: None,
+            retransmit_at: None,
+            retries: 0,
+            close_at: None,
+            condvar: Arc::new(Condvar::new()),
+            poll_queue: Arc::new(PollQueue::new()),
+        }
+    }
+
+    /// A connection answering the SYN `seg` from `ip`
+    fn new_passive(ip: &Ipv4Packet, seg: &TcpSegment, listener: usize) -> Self {
+        let mut tcb = Self::new_active(seg.dst_port, ip.src, seg.src_port);
+        tcb.state = TcpState::SynReceived;
+        tcb.listener = Some(listener);
+        tcb.attached = false;
+        tcb.snd_wnd = seg.window as u32;
+        tcb.mss = seg.mss.map_or(DEFAULT_MSS, |mss| mss as usize).min(LOCAL_MSS);
+        tcb.rcv_nxt = seg.seq.wrapping_add(1);
+        tcb
+    }
+
+    fn rcv_wnd(&self) -> u32 {
+        (RECV_BUFFER_SIZE - self.recv_buffer.len()) as u32
+    }
+
+    fn send_segment(&self, seq: u32, flags: TcpFlags, payload: &[u8], mss: Option<u16>) {
+        let segment = TcpSegment {
+            src_port: self.local_port,
+            dst_port: self.remote_port,
             seq,
-            ack,
-            socket_index: index,
+            ack: if flags.contains(TcpFlags::ACK) { self.rcv_nxt } else { 0 },
+            flags,
+            window: self.rcv_wnd().min(u16::MAX as u32) as u16,
+            mss,
+            payload,
+        };
+        let data = segment.build(source_ip(self.remote_ip), self.remote_ip);
+        send_ipv4(self.remote_ip, IP_PROTO_TCP, &data);
+    }
+
+    fn send_ack(&self) {
+        self.send_segment(self.snd_nxt, TcpFlags::ACK, &[], None);
+    }
+
+    fn send_syn(&self) {
+        self.send_segment(self.iss, TcpFlags::SYN, &[], Some(LOCAL_MSS as u16));
+    }
+
+    fn send_syn_ack(&self) {
+        self.send_segment(self.iss, TcpFlags::SYN | TcpFlags::ACK, &[], Some(LOCAL_MSS as u16));
+    }
+
+    /// Tell blocked readers, writers and pollers that something changed
+    fn wake(&self) {
+        self.condvar.broadcast();
+        self.poll_queue.wake_all();
+    }
+
+    fn abort(&mut self) {
+        self.reset = true;
+        self.state = TcpState::Closed;
+        self.retransmit_at = None;
+        self.close_at = None;
+        self.wake();
+    }
+
+    fn enter_time_wait(&mut self, now: usize) {
+        self.state = TcpState::TimeWait;
+        self.retransmit_at = None;
+        self.close_at = Some(now + 2 * MSL_MS);
+    }
+
+    fn fin_acked(&self) -> bool {
+        self.fin_seq.map_or(false, |fin| seq_lt(fin, self.snd_una))
+    }
+
+    /// Send as much buffered data as the peer's window allows, then the FIN
+    fn output(&mut self, now: usize) {
+        if !matches!(
+            self.state,
+            TcpState::Established
+                | TcpState::CloseWait
+                | TcpState::FinWait1
+                | TcpState::Closing
+                | TcpState::LastAck
+        ) {
+            return;
+        }
+        while self.fin_seq.is_none() {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let unsent = self.send_buffer.len() - in_flight;
+            let window_end = self.snd_una.wrapping_add(self.snd_wnd);
+            let usable = if seq_lt(self.snd_nxt, window_end) {
+                window_end.wrapping_sub(self.snd_nxt) as usize
+            } else {
+                0
+            };
+            let len = unsent.min(usable).min(self.mss);
+            if len == 0 {
+                if unsent == 0 && self.fin_queued {
+                    self.send_segment(self.snd_nxt, TcpFlags::FIN | TcpFlags::ACK, &[], None);
+                    self.fin_seq = Some(self.snd_nxt);
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                }
+                break;
+            }
+            let data: Vec<u8> = self
+                .send_buffer
+                .range(in_flight..in_flight + len)
+                .copied()
+                .collect();
+            let mut flags = TcpFlags::ACK;
+            if len == unsent {
+                flags |= TcpFlags::PSH;
+            }
+            self.send_segment(self.snd_nxt, flags, &data, None);
+            if self.rtt_sample.is_none() {
+                self.rtt_sample = Some((self.snd_nxt.wrapping_add(len as u32), now));
+            }
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+        }
+        // also covers data held back by a closed window, the timeout probes it
+        if self.retransmit_at.is_none()
+            && (self.snd_nxt != self.snd_una || !self.send_buffer.is_empty())
+        {
+            self.retransmit_at = Some(now + self.rto);
+        }
+    }
+
+    /// Resend the oldest unacknowledged segment and back off
+    fn retransmit(&mut self, now: usize) {
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.abort();
+            return;
+        }
+        if self.state == TcpState::SynSent {
+            self.send_syn();
+        } else if self.state == TcpState::SynReceived {
+            self.send_syn_ack();
+        } else if !self.send_buffer.is_empty() {
+            let len = self
+                .send_buffer
+                .len()
+                .min(self.mss)
+                .min((self.snd_wnd as usize).max(1));
+            let data: Vec<u8> = self.send_buffer.range(..len).copied().collect();
+            self.send_segment(self.snd_una, TcpFlags::ACK | TcpFlags::PSH, &data, None);
+            let end = self.snd_una.wrapping_add(len as u32);
+            if seq_lt(self.snd_nxt, end) {
+                self.snd_nxt = end;
+            }
+        } else if let Some(fin) = self.fin_seq {
+            self.send_segment(fin, TcpFlags::FIN | TcpFlags::ACK, &[], None);
+        }
+        // Karn: a retransmitted segment says nothing about the round trip time
+        self.rtt_sample = None;
+        self.rto = (self.rto * 2).min(MAX_RTO_MS);
+        self.retransmit_at = Some(now + self.rto);
+    }
+
+    /// Update the round trip estimate as in RFC 6298
+    fn sample_rtt(&mut self, rtt: usize) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                self.rttvar = (3 * self.rttvar + srtt.abs_diff(rtt)) / 4;
+                self.srtt = Some((7 * srtt + rtt) / 8);
+            }
+        }
+        self.rto = (self.srtt.unwrap() + (4 * self.rttvar).max(10)).clamp(MIN_RTO_MS, MAX_RTO_MS);
+    }
+
+    /// Process the acknowledgment field, false if the segment should be dropped
+    fn handle_ack(&mut self, seg: &TcpSegment, now: usize) -> bool {
+        if seq_lt(self.snd_nxt, seg.ack) {
+            // acknowledges something not yet sent
+            self.send_ack();
+            return false;
+        }
+        if seq_lt(self.snd_una, seg.ack) {
+            let acked = seg.ack.wrapping_sub(self.snd_una) as usize;
+            // whatever is acked past the buffered data is our SYN or FIN
+            let data_acked = acked.min(self.send_buffer.len());
+            self.send_buffer.drain(..data_acked);
+            self.snd_una = seg.ack;
+            if let Some((end, sent)) = self.rtt_sample {
+                if seq_le(end, seg.ack) {
+                    self.rtt_sample = None;
+                    self.sample_rtt(now - sent);
+                }
+            }
+            self.retries = 0;
+            self.retransmit_at = if self.snd_una == self.snd_nxt {
+                None
+            } else {
+                Some(now + self.rto)
+            };
+            self.wake();
+        }
+        if seq_le(self.snd_una, seg.ack) {
+            self.snd_wnd = seg.window as u32;
+        }
+        true
+    }
+
+    /// Queue received data, keeping segments that arrive past a hole for later
+    fn receive_data(&mut self, seq: u32, payload: &[u8]) {
+        let mut seq = seq;
+        let mut data = payload;
+        if seq_lt(seq, self.rcv_nxt) {
+            let duplicate = self.rcv_nxt.wrapping_sub(seq) as usize;
+            if duplicate >= data.len() {
+                return;
+            }
+            data = &data[duplicate..];
+            seq = self.rcv_nxt;
+        }
+        let offset = seq.wrapping_sub(self.rcv_nxt) as usize;
+        let room = self.rcv_wnd() as usize;
+        if offset >= room {
+            return;
+        }
+        data = &data[..data.len().min(room - offset)];
+        if offset > 0 {
+            if self.out_of_order.len() < MAX_OUT_OF_ORDER {
+                self.out_of_order.push((seq, data.to_vec()));
+            }
+            return;
+        }
+        self.recv_buffer.extend(data);
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(data.len() as u32);
+        // the hole may be filled now
+        while let Some(pos) = self
+            .out_of_order
+            .iter()
+            .position(|(seq, _)| seq_le(*seq, self.rcv_nxt))
+        {
+            let (seq, data) = self.out_of_order.swap_remove(pos);
+            let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+            if skip < data.len() {
+                self.recv_buffer.extend(&data[skip..]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add((data.len() - skip) as u32);
+            }
+        }
+        self.wake();
+    }
+
+    /// RFC 793 "SEGMENT ARRIVES" in SYN-SENT, waiting for the peer's SYN
+    fn on_segment_syn_sent(&mut self, seg: &TcpSegment) {
+        let ack_ok = seq_lt(self.iss, seg.ack) && seq_le(seg.ack, self.snd_nxt);
+        if seg.flags.contains(TcpFlags::ACK) && !ack_ok {
+            if !seg.flags.contains(TcpFlags::RST) {
+                self.send_segment(seg.ack, TcpFlags::RST, &[], None);
+            }
+            return;
+        }
+        if seg.flags.contains(TcpFlags::RST) {
+            // connection refused
+            if ack_ok {
+                self.abort();
+            }
+            return;
+        }
+        if !seg.flags.contains(TcpFlags::SYN) {
+            return;
+        }
+        self.rcv_nxt = seg.seq.wrapping_add(1);
+        self.snd_wnd = seg.window as u32;
+        self.mss = seg.mss.map_or(DEFAULT_MSS, |mss| mss as usize).min(LOCAL_MSS);
+        if seg.flags.contains(TcpFlags::ACK) {
+            self.snd_una = seg.ack;
+            self.state = TcpState::Established;
+            self.retries = 0;
+            self.retransmit_at = None;
+            self.send_ack();
+            self.wake();
+        } else {
+            // simultaneous open
+            self.state = TcpState::SynReceived;
+            self.send_syn_ack();
+        }
+    }
+
+    /// RFC 793 "SEGMENT ARRIVES" for a connection past LISTEN
+    fn on_segment(&mut self, seg: &TcpSegment, now: usize) {
+        if self.state == TcpState::SynSent {
+            self.on_segment_syn_sent(seg);
+            return;
+        }
+        let mut seg_len = seg.payload.len() as u32;
+        if seg.flags.contains(TcpFlags::SYN) {
+            seg_len += 1;
+        }
+        if seg.flags.contains(TcpFlags::FIN) {
+            seg_len += 1;
+        }
+        let wnd = self.rcv_wnd();
+        let in_window = |seq: u32| {
+            seq_le(self.rcv_nxt, seq) && seq_lt(seq, self.rcv_nxt.wrapping_add(wnd))
+        };
+        let acceptable = match (seg_len, wnd) {
+            (0, 0) => seg.seq == self.rcv_nxt,
+            (0, _) => in_window(seg.seq),
+            (_, 0) => false,
+            _ => in_window(seg.seq) || in_window(seg.seq.wrapping_add(seg_len - 1)),
+        };
+        if !acceptable {
+            // a retransmitted SYN means our SYN-ACK was lost
+            if self.state == TcpState::SynReceived
+                && seg.flags.contains(TcpFlags::SYN)
+                && seg.seq.wrapping_add(1) == self.rcv_nxt
+            {
+                self.send_syn_ack();
+            } else if !seg.flags.contains(TcpFlags::RST) {
+                self.send_ack();
+            }
+            return;
+        }
+        if seg.flags.contains(TcpFlags::RST) {
+            self.abort();
+            return;
+        }
+        if seg.flags.contains(TcpFlags::SYN) {
+            self.send_segment(self.snd_nxt, TcpFlags::RST, &[], None);
+            self.abort();
+            return;
+        }
+        if !seg.flags.contains(TcpFlags::ACK) {
+            return;
+        }
+        if self.state == TcpState::SynReceived {
+            if seq_lt(self.snd_una, seg.ack) && seq_le(seg.ack, self.snd_nxt) {
+                self.state = TcpState::Established;
+            } else {
+                self.send_segment(seg.ack, TcpFlags::RST, &[], None);
+                return;
+            }
+        }
+        if !self.handle_ack(seg, now) {
+            return;
+        }
+        if self.fin_acked() {
+            match self.state {
+                TcpState::FinWait1 => {
+                    self.state = TcpState::FinWait2;
+                    if !self.attached {
+                        self.close_at = Some(now + 2 * MSL_MS);
+                    }
+                }
+                TcpState::Closing => self.enter_time_wait(now),
+                TcpState::LastAck => {
+                    self.state = TcpState::Closed;
+                    self.wake();
+                    return;
+                }
+                _ => {}
+            }
+        }
+        let receiving = matches!(
+            self.state,
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+        );
+        if receiving {
+            if !seg.payload.is_empty() {
+                self.receive_data(seg.seq, seg.payload);
+            }
+            if seg.flags.contains(TcpFlags::FIN) {
+                self.remote_fin = Some(seg.seq.wrapping_add(seg.payload.len() as u32));
+            }
+            if !self.fin_received && self.remote_fin == Some(self.rcv_nxt) {
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                self.fin_received = true;
+                match self.state {
+                    TcpState::Established => self.state = TcpState::CloseWait,
+                    TcpState::FinWait1 if self.fin_acked() => self.enter_time_wait(now),
+                    TcpState::FinWait1 => self.state = TcpState::Closing,
+                    _ => self.enter_time_wait(now),
+                }
+                self.wake();
+            }
+        } else if self.state == TcpState::TimeWait && seg.flags.contains(TcpFlags::FIN) {
+            // the peer missed our last ACK
+            self.enter_time_wait(now);
+        }
+        if seg_len > 0 {
+            self.send_ack();
+        }
+    }
+
+    /// Queue a FIN after the buffered data, nothing more can be written
+    fn shutdown(&mut self, now: usize) {
+        match self.state {
+            TcpState::Established => {
+                self.fin_queued = true;
+                self.state = TcpState::FinWait1;
+            }
+            TcpState::CloseWait => {
+                self.fin_queued = true;
+                self.state = TcpState::LastAck;
+            }
+            _ => {}
+        }
+        self.output(now);
+    }
+
+    /// The user is done with the connection
+    fn close(&mut self, now: usize) {
+        self.attached = false;
+        if self.state == TcpState::FinWait2 {
+            self.close_at = Some(now + 2 * MSL_MS);
+        }
+        self.shutdown(now);
+    }
+}
+
+/// Pick an initial send sequence number from the clock, as RFC 793 suggests
+fn initial_sequence() -> u32 {
+    (get_time() as u32).wrapping_mul(2654435761)
+}
+
+fn find_connection(
+    table: &[Option<Tcb>],
+    remote_ip: Ipv4Addr,
+    remote_port: u16,
+    local_port: u16,
+) -> Option<usize> {
+    table.iter().position(|slot| {
+        slot.as_ref().map_or(false, |tcb| {
+            tcb.remote_ip == remote_ip
+                && tcb.remote_port == remote_port
+                && tcb.local_port == local_port
+        })
+    })
+}
+
+/// Free every closed connection nobody refers to any more.
+/// A connection leaving SYN-RECEIVED is reported to its listener on the way.
+fn update_table(table: &mut [Option<Tcb>]) {
+    for (index, slot) in table.iter_mut().enumerate() {
+        let tcb = match slot {
+            Some(tcb) => tcb,
+            None => continue,
+        };
+        if tcb.state != TcpState::SynReceived {
+            if let Some(listener) = tcb.listener.take() {
+                if tcb.state == TcpState::Closed {
+                    connection_failed(listener);
+                } else {
+                    tcb.attached = true;
+                    connection_ready(listener, index);
+                }
+            }
+        }
+        if tcb.state == TcpState::Closed && !tcb.attached {
+            *slot = None;
+        }
+    }
+}
+
+/// Reply to a segment no connection wants with a reset
+fn send_reset(ip: &Ipv4Packet, seg: &TcpSegment) {
+    let (seq, ack, flags) = if seg.flags.contains(TcpFlags::ACK) {
+        (seg.ack, 0, TcpFlags::RST)
+    } else {
+        let mut seg_len = seg.payload.len() as u32;
+        if seg.flags.contains(TcpFlags::SYN) {
+            seg_len += 1;
+        }
+        if seg.flags.contains(TcpFlags::FIN) {
+            seg_len += 1;
+        }
+        (0, seg.seq.wrapping_add(seg_len), TcpFlags::RST | TcpFlags::ACK)
+    };
+    let reset = TcpSegment {
+        src_port: seg.dst_port,
+        dst_port: seg.src_port,
+        seq,
+        ack,
+        flags,
+        window: 0,
+        mss: None,
+        payload: &[],
+    };
+    send_ipv4(ip.src, IP_PROTO_TCP, &reset.build(source_ip(ip.src), ip.src));
+}
+
+/// Handle a TCP segment addressed to us
+pub fn handle_segment(ip: &Ipv4Packet) {
+    let seg = match TcpSegment::parse(ip) {
+        Some(seg) => seg,
+        None => return,
+    };
+    let now = get_time_ms();
+    let mut table = CONNECTIONS.lock();
+    if let Some(index) = find_connection(&table, ip.src, seg.src_port, seg.dst_port) {
+        let tcb = table[index].as_mut().unwrap();
+        tcb.on_segment(&seg, now);
+        // an ACK may have opened the window
+        tcb.output(now);
+        update_table(&mut table);
+        return;
+    }
+    if seg.flags.contains(TcpFlags::RST) {
+        return;
+    }
+    if seg.flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN {
+        if let Some(listener) = reserve_backlog(seg.dst_port) {
+            let mut tcb = Tcb::new_passive(ip, &seg, listener);
+            tcb.send_syn_ack();
+            tcb.retransmit_at = Some(now + tcb.rto);
+            match table.iter().position(|slot| slot.is_none()) {
+                Some(index) => table[index] = Some(tcb),
+                None => table.push(Some(tcb)),
+            }
+            return;
+        }
+    }
+    send_reset(ip, &seg);
+}
+
+/// Run the retransmission and close timers, called on every timer tick
+pub fn tcp_timer_tick() {
+    let now = get_time_ms();
+    let mut table = CONNECTIONS.lock();
+    for tcb in table.iter_mut().flatten() {
+        if tcb.retransmit_at.map_or(false, |at| now >= at) {
+            tcb.retransmit(now);
+        }
+        if tcb.close_at.map_or(false, |at| now >= at) {
+            tcb.close_at = None;
+            tcb.state = TcpState::Closed;
+            tcb.wake();
+        }
+    }
+    update_table(&mut table);
+}
+
+/// Reset a connection nobody will accept any more, `listener` is going away
+pub fn abort_listener(listener: usize, queued: &[usize]) {
+    let mut table = CONNECTIONS.lock();
+    for (index, slot) in table.iter_mut().enumerate() {
+        let tcb = match slot {
+            Some(tcb) => tcb,
+            None => continue,
+        };
+        if tcb.listener == Some(listener) || queued.contains(&index) {
+            tcb.send_segment(tcb.snd_nxt, TcpFlags::RST, &[], None);
+            tcb.listener = None;
+            tcb.attached = false;
+            tcb.abort();
+        }
+    }
+    update_table(&mut table);
+}
+
+/// whether a bound socket, a connection or a listener uses `port` locally
+pub fn port_in_use(port: u16) -> bool {
+    BOUND_PORTS.lock().contains(&port) || port_taken(port)
+}
+
+fn port_taken(port: u16) -> bool {
+    CONNECTIONS
+        .lock()
+        .iter()
+        .flatten()
+        .any(|tcb| tcb.local_port == port)
+        || listening_on(port)
+}
+
+/// A port `bind` reserved for a socket until it listens or connects,
+/// released on drop
+pub struct BoundPort(u16);
+
+impl BoundPort {
+    /// Reserve `port`, 0 picks a free one. None if it is taken
+    pub fn new(port: u16) -> Option<Self> {
+        let mut bound = BOUND_PORTS.lock();
+        let port = match port {
+            0 => ephemeral_port(|port| bound.contains(&port) || port_taken(port)),
+            port if bound.contains(&port) || port_taken(port) => return None,
+            port => port,
+        };
+        bound.push(port);
+        Some(Self(port))
+    }
+
+    pub fn port(&self) -> u16 {
+        self.0
+    }
+}
+
+impl Drop for BoundPort {
+    fn drop(&mut self) {
+        let mut bound = BOUND_PORTS.lock();
+        if let Some(index) = bound.iter().position(|port| *port == self.0) {
+            bound.swap_remove(index);
         }
     }
 }
 
+/// Open a connection from `local_port`, blocking until the handshake ends.
+/// None if the peer refused it or never answered.
+pub fn connect(local_port: u16, remote_ip: Ipv4Addr, remote_port: u16) -> Option<TCP> {
+    let now = get_time_ms();
+    let mut table = CONNECTIONS.lock();
+    if find_connection(&table, remote_ip, remote_port, local_port).is_some() {
+        return None;
+    }
+    let mut tcb = Tcb::new_active(local_port, remote_ip, remote_port);
+    tcb.send_syn();
+    tcb.retransmit_at = Some(now + tcb.rto);
+    let index = match table.iter().position(|slot| slot.is_none()) {
+        Some(index) => {
+            table[index] = Some(tcb);
+            index
+        }
+        None => {
+            table.push(Some(tcb));
+            table.len() - 1
+        }
+    };
+    loop {
+        let tcb = table[index].as_mut().unwrap();
+        match tcb.state {
+            TcpState::SynSent | TcpState::SynReceived => {}
+            TcpState::Closed => {
+                tcb.attached = false;
+                update_table(&mut table);
+                return None;
+            }
+            _ => return Some(TCP::new(index)),
+        }
+        let task_cx_ptr = tcb.condvar.clone().wait_no_sched();
+        drop(table);
+        schedule(task_cx_ptr);
+        table = CONNECTIONS.lock();
+    }
+}
+
+/// A socket file on an open connection
+pub struct TCP {
+    index: usize,
+}
+
+impl TCP {
+    pub fn new(index: usize) -> Self {
+        Self { index }
+    }
+
+    /// (local port, remote address, remote port)
+    pub fn endpoints(&self) -> (u16, Ipv4Addr, u16) {
+        let table = CONNECTIONS.lock();
+        let tcb = table[self.index].as_ref().unwrap();
+        (tcb.local_port, tcb.remote_ip, tcb.remote_port)
+    }
+
+    /// Send a FIN once the buffered data is out
+    pub fn shutdown_write(&self) {
+        let mut table = CONNECTIONS.lock();
+        table[self.index].as_mut().unwrap().shutdown(get_time_ms());
+    }
+}
+
 impl File for TCP {
     fn readable(&self) -> bool {
         true
@@ -48,67 +823,117 @@ impl File for TCP {
         true
     }
 
-    fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
+    /// Block until data arrives, 0 once the peer closed its side
+    fn read(&self, mut buf: UserBuffer) -> usize {
         loop {
-            if let Some(data) = pop_data(self.socket_index) {
-                let data_len = data.len();
-                let mut left = 0;
-                for i in 0..buf.buffers.len() {
-                    let buffer_i_len = buf.buffers[i].len().min(data_len - left);
-
-                    buf.buffers[i][..buffer_i_len]
-                        .copy_from_slice(&data[left..(left + buffer_i_len)]);
-
-                    left += buffer_i_len;
-                    if left == data_len {
+            let mut table = CONNECTIONS.lock();
+            let tcb = table[self.index].as_mut().unwrap();
+            if !tcb.recv_buffer.is_empty() {
+                let window_was_small = (tcb.rcv_wnd() as usize) < tcb.mss;
+                let mut total = 0usize;
+                for slice in buf.buffers.iter_mut() {
+                    let len = slice.len().min(tcb.recv_buffer.len());
+                    for (dst, src) in slice[..len].iter_mut().zip(tcb.recv_buffer.drain(..len)) {
+                        *dst = src;
+                    }
+                    total += len;
+                    if tcb.recv_buffer.is_empty() {
                         break;
                     }
                 }
-                return left;
-            } else {
-                net_interrupt_handler();
+                // let the peer know it may send again
+                if window_was_small && tcb.rcv_wnd() as usize >= tcb.mss {
+                    tcb.send_ack();
+                }
+                return total;
             }
+            if tcb.reset {
+                return (-ECONNRESET) as usize;
+            }
+            if tcb.fin_received || tcb.state == TcpState::Closed {
+                return 0;
+            }
+            let task_cx_ptr = tcb.condvar.clone().wait_no_sched();
+            drop(table);
+            schedule(task_cx_ptr);
         }
     }
 
-    fn write(&self, buf: crate::mm::UserBuffer) -> usize {
-        let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
-
-        let mut data = vec![0u8; buf.len()];
-
-        let mut left = 0;
-        for i in 0..buf.buffers.len() {
-            data[left..(left + buf.buffers[i].len())].copy_from_slice(buf.buffers[i]);
-            left += buf.buffers[i].len();
+    /// Queue all of `buf` for sending, blocking while the send buffer is full.
+    /// Writing once our side is closed or the connection is gone raises SIGPIPE.
+    fn write(&self, buf: UserBuffer) -> usize {
+        let total = buf.len();
+        // the next byte to queue is `buf.buffers[slice][offset]`
+        let (mut slice, mut offset) = (0, 0);
+        let mut written = 0usize;
+        loop {
+            let mut table = CONNECTIONS.lock();
+            let tcb = table[self.index].as_mut().unwrap();
+            if tcb.fin_queued
+                || !matches!(tcb.state, TcpState::Established | TcpState::CloseWait)
+            {
+                drop(table);
+                current_add_signal(SignalFlags::SIGPIPE);
+                return if written > 0 { written } else { (-EPIPE) as usize };
+            }
+            // copy straight from the user buffer, as much as there is room for
+            let mut room = SEND_BUFFER_SIZE - tcb.send_buffer.len();
+            while room > 0 && slice < buf.buffers.len() {
+                let chunk = &buf.buffers[slice][offset..];
+                let len = room.min(chunk.len());
+                tcb.send_buffer.extend(&chunk[..len]);
+                room -= len;
+                written += len;
+                offset += len;
+                if offset == buf.buffers[slice].len() {
+                    slice += 1;
+                    offset = 0;
+                }
+            }
+            tcb.output(get_time_ms());
+            if written == total {
+                return written;
+            }
+            let task_cx_ptr = tcb.condvar.clone().wait_no_sched();
+            drop(table);
+            schedule(task_cx_ptr);
         }
+    }
 
-        let len = data.len();
-
-        // get sock and sequence
-        let (ack, seq) = get_s_a_by_index(self.socket_index).map_or((0, 0), |x| x);
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let table = CONNECTIONS.lock();
+        let tcb = table[self.index].as_ref().unwrap();
+        let mut ready = PollEvents::empty();
+        if !tcb.recv_buffer.is_empty() || tcb.fin_received || tcb.state == TcpState::Closed {
+            ready |= PollEvents::IN;
+        }
+        if matches!(tcb.state, TcpState::Established | TcpState::CloseWait)
+            && !tcb.fin_queued
+            && tcb.send_buffer.len() < SEND_BUFFER_SIZE
+        {
+            ready |= PollEvents::OUT;
+        }
+        if tcb.reset {
+            ready |= PollEvents::ERR;
+        }
+        if tcb.state == TcpState::Closed {
+            ready |= PollEvents::HUP;
+        }
+        ready & (events | PollEvents::ERR | PollEvents::HUP)
+    }
 
-        let tcp_packet = TCPPacket {
-            source_ip: lose_net_stack.ip,
-            source_mac: lose_net_stack.mac,
-            source_port: self.sport,
-            dest_ip: self.target,
-            dest_mac: MacAddress::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
-            dest_port: self.dport,
-            data_len: len,
-            seq,
-            ack,
-            flags: TcpFlags::A,
-            win: 65535,
-            urg: 0,
-            data: data.as_ref(),
-        };
-        NET_DEVICE.transmit(&tcp_packet.build_data());
-        len
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        let table = CONNECTIONS.lock();
+        table[self.index].as_ref().map(|tcb| tcb.poll_queue.clone())
     }
 }
 
 impl Drop for TCP {
     fn drop(&mut self) {
-        remove_socket(self.socket_index)
+        let mut table = CONNECTIONS.lock();
+        if let Some(tcb) = table[self.index].as_mut() {
+            tcb.close(get_time_ms());
+        }
+        update_table(&mut table);
     }
 }
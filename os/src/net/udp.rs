@@ -1,30 +1,186 @@
-use super::net_interrupt_handler;
-use super::socket::{add_socket, pop_data, remove_socket};
-use super::LOSE_NET_STACK;
-use super::NET_DEVICE;
-use crate::fs::File;
-use alloc::vec;
-use lose_net_stack::packets::udp::UDPPacket;
-use lose_net_stack::IPv4;
-use lose_net_stack::MacAddress;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+use super::packet::{
+    Ipv4Addr, Ipv4Packet, UdpDatagram, IPV4_HEADER_LEN, IP_MTU, IP_PROTO_UDP, UDP_HEADER_LEN,
+};
+use super::{ephemeral_port, send_ipv4, source_ip};
+use crate::fs::{File, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::{Condvar, PollQueue, SpinNoIrqLock};
+use crate::task::schedule;
+
+/// Datagrams queued on one socket before new ones are dropped
+const MAX_QUEUED: usize = 64;
+/// Largest payload sent, the datagram has to fit the MTU
+pub const MAX_PAYLOAD_LEN: usize = IP_MTU - IPV4_HEADER_LEN - UDP_HEADER_LEN;
+
+/// A bound UDP port
+struct UdpEndpoint {
+    lport: u16,
+    /// set by `connect`, only datagrams from there are received
+    peer: Option<(Ipv4Addr, u16)>,
+    /// received datagrams with their source address
+    datagrams: VecDeque<(Ipv4Addr, u16, Vec<u8>)>,
+    condvar: Arc<Condvar>,       // readers waiting for a datagram
+    poll_queue: Arc<PollQueue>,  // tasks in `poll` waiting for a datagram
+}
+
+lazy_static! {
+    static ref UDP_TABLE: SpinNoIrqLock<Vec<Option<UdpEndpoint>>> =
+        SpinNoIrqLock::new(Vec::new());
+}
+
+/// whether a socket in `udp_table` is bound to `port`
+fn bound(udp_table: &[Option<UdpEndpoint>], port: u16) -> bool {
+    udp_table
+        .iter()
+        .flatten()
+        .any(|endpoint| endpoint.lport == port)
+}
+
+/// `lport`, or a free port for 0. None if it is taken. The caller keeps the
+/// table locked until the port is in it
+fn pick_port(udp_table: &[Option<UdpEndpoint>], lport: u16) -> Option<u16> {
+    match lport {
+        0 => Some(ephemeral_port(|port| bound(udp_table, port))),
+        lport if bound(udp_table, lport) => None,
+        lport => Some(lport),
+    }
+}
+
+/// Queue a datagram addressed to us on the socket it belongs to
+pub fn handle_datagram(ip: &Ipv4Packet) {
+    let datagram = match UdpDatagram::parse(ip) {
+        Some(datagram) => datagram,
+        None => return,
+    };
+    let mut udp_table = UDP_TABLE.lock();
+    let endpoint = udp_table.iter_mut().flatten().find(|endpoint| {
+        endpoint.lport == datagram.dst_port
+            && endpoint
+                .peer
+                .map_or(true, |peer| peer == (ip.src, datagram.src_port))
+    });
+    if let Some(endpoint) = endpoint {
+        if endpoint.datagrams.len() < MAX_QUEUED {
+            endpoint
+                .datagrams
+                .push_back((ip.src, datagram.src_port, datagram.payload.to_vec()));
+            endpoint.condvar.signal();
+            endpoint.poll_queue.wake_all();
+        }
+    }
+}
 
 pub struct UDP {
-    pub target: IPv4,
-    pub sport: u16,
-    pub dport: u16,
     pub socket_index: usize,
 }
 
 impl UDP {
-    pub fn new(target: IPv4, sport: u16, dport: u16) -> Self {
-        let index = add_socket(target, sport, dport).expect("can't add socket");
-
-        Self {
-            target,
-            sport,
-            dport,
+    /// Bind a socket to `lport`, 0 picks a free one. None if the port is taken
+    pub fn new(lport: u16) -> Option<Self> {
+        let mut udp_table = UDP_TABLE.lock();
+        let endpoint = UdpEndpoint {
+            lport: pick_port(&udp_table, lport)?,
+            peer: None,
+            datagrams: VecDeque::new(),
+            condvar: Arc::new(Condvar::new()),
+            poll_queue: Arc::new(PollQueue::new()),
+        };
+        let index = match udp_table.iter().position(|x| x.is_none()) {
+            Some(index) => {
+                udp_table[index] = Some(endpoint);
+                index
+            }
+            None => {
+                udp_table.push(Some(endpoint));
+                udp_table.len() - 1
+            }
+        };
+        Some(Self {
             socket_index: index,
+        })
+    }
+
+    pub fn local_port(&self) -> u16 {
+        UDP_TABLE.lock()[self.socket_index]
+            .as_ref()
+            .unwrap()
+            .lport
+    }
+
+    pub fn peer(&self) -> Option<(Ipv4Addr, u16)> {
+        UDP_TABLE.lock()[self.socket_index]
+            .as_ref()
+            .unwrap()
+            .peer
+    }
+
+    /// Move the socket to `lport`, 0 picks a free one. False if another
+    /// socket has it
+    pub fn rebind(&self, lport: u16) -> bool {
+        let mut udp_table = UDP_TABLE.lock();
+        if lport != 0 && udp_table[self.socket_index].as_ref().unwrap().lport == lport {
+            return true;
+        }
+        let lport = match pick_port(&udp_table, lport) {
+            Some(lport) => lport,
+            None => return false,
+        };
+        udp_table[self.socket_index].as_mut().unwrap().lport = lport;
+        true
+    }
+
+    /// Set the default destination and receive only from there
+    pub fn connect(&self, peer: (Ipv4Addr, u16)) {
+        let mut udp_table = UDP_TABLE.lock();
+        let endpoint = udp_table[self.socket_index].as_mut().unwrap();
+        endpoint.peer = Some(peer);
+        endpoint
+            .datagrams
+            .retain(|(addr, port, _)| (*addr, *port) == peer);
+    }
+
+    /// Send `data` as one datagram. Fails with usize::MAX if it doesn't
+    /// fit the MTU.
+    pub fn send_to(&self, data: &[u8], (addr, port): (Ipv4Addr, u16)) -> usize {
+        if data.len() > MAX_PAYLOAD_LEN {
+            return usize::MAX;
+        }
+        let datagram = UdpDatagram {
+            src_port: self.local_port(),
+            dst_port: port,
+            payload: data,
+        };
+        send_ipv4(addr, IP_PROTO_UDP, &datagram.build(source_ip(addr), addr));
+        data.len()
+    }
+
+    /// Block until a datagram arrives, copy as much of it as fits and return its source
+    pub fn recv_from(&self, mut buf: UserBuffer) -> (usize, Ipv4Addr, u16) {
+        let (addr, port, data) = loop {
+            let mut udp_table = UDP_TABLE.lock();
+            let endpoint = udp_table[self.socket_index].as_mut().unwrap();
+            if let Some(datagram) = endpoint.datagrams.pop_front() {
+                break datagram;
+            }
+            let task_cx_ptr = endpoint.condvar.clone().wait_no_sched();
+            drop(udp_table);
+            schedule(task_cx_ptr);
+        };
+        let mut copied = 0;
+        for slice in buf.buffers.iter_mut() {
+            let len = slice.len().min(data.len() - copied);
+            slice[..len].copy_from_slice(&data[copied..copied + len]);
+            copied += len;
+            if copied == data.len() {
+                break;
+            }
         }
+        (copied, addr, port)
     }
 }
 
@@ -37,59 +193,44 @@ impl File for UDP {
         true
     }
 
-    fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
-        loop {
-            if let Some(data) = pop_data(self.socket_index) {
-                let data_len = data.len();
-                let mut left = 0;
-                for i in 0..buf.buffers.len() {
-                    let buffer_i_len = buf.buffers[i].len().min(data_len - left);
-
-                    buf.buffers[i][..buffer_i_len]
-                        .copy_from_slice(&data[left..(left + buffer_i_len)]);
-
-                    left += buffer_i_len;
-                    if left == data_len {
-                        break;
-                    }
-                }
-                return left;
-            } else {
-                net_interrupt_handler();
-            }
-        }
+    /// Block until a packet arrives, then copy as much of it as fits
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.recv_from(buf).0
     }
 
-    fn write(&self, buf: crate::mm::UserBuffer) -> usize {
-        let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
-
-        let mut data = vec![0u8; buf.len()];
+    /// Send to the connected peer, fails on a socket that is not connected
+    fn write(&self, buf: UserBuffer) -> usize {
+        let peer = match self.peer() {
+            Some(peer) => peer,
+            None => return usize::MAX,
+        };
+        if buf.len() > MAX_PAYLOAD_LEN {
+            return usize::MAX;
+        }
+        let data: Vec<u8> = buf.buffers.iter().flat_map(|slice| slice.iter().copied()).collect();
+        self.send_to(&data, peer)
+    }
 
-        let mut left = 0;
-        for i in 0..buf.buffers.len() {
-            data[left..(left + buf.buffers[i].len())].copy_from_slice(buf.buffers[i]);
-            left += buf.buffers[i].len();
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let udp_table = UDP_TABLE.lock();
+        let endpoint = udp_table[self.socket_index].as_ref().unwrap();
+        let mut ready = PollEvents::OUT;
+        if !endpoint.datagrams.is_empty() {
+            ready |= PollEvents::IN;
         }
+        ready & events
+    }
 
-        let len = data.len();
-
-        let udp_packet = UDPPacket::new(
-            lose_net_stack.ip,
-            lose_net_stack.mac,
-            self.sport,
-            self.target,
-            MacAddress::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
-            self.dport,
-            len,
-            data.as_ref(),
-        );
-        NET_DEVICE.transmit(&udp_packet.build_data());
-        len
+    fn poll_queue(&self) -> Option<Arc<PollQueue>> {
+        let udp_table = UDP_TABLE.lock();
+        udp_table[self.socket_index]
+            .as_ref()
+            .map(|endpoint| endpoint.poll_queue.clone())
     }
 }
 
 impl Drop for UDP {
     fn drop(&mut self) {
-        remove_socket(self.socket_index)
+        UDP_TABLE.lock()[self.socket_index] = None;
     }
 }
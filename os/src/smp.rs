@@ -0,0 +1,136 @@
+//! Bringing up the other harts.
+//!
+//! The boot hart initializes the kernel alone, then starts every other hart
+//! at `_start_secondary` through the SBI HSM extension. Each of them sets up
+//! its own trap entry, timer and PLIC context before taking tasks from the
+//! shared ready queue. The kernel keeps the id of the hart it runs on in tp.
+//!
+//! Every trap from user mode and every return to it flushes the TLB, so
+//! only a hart running user code can hold a stale entry of a page that was
+//! unmapped. Frames unmapped while other harts run user code are retired:
+//! those harts get an IPI, and the frames are freed once each of them has
+//! entered the kernel again.
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+
+use crate::config::MAX_HARTS;
+use crate::mm::FrameTracker;
+use crate::sync::SpinNoIrqLock;
+
+/// Bit n is set once hart n runs tasks
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+const ENTRIES_INIT: AtomicUsize = AtomicUsize::new(0);
+const IN_USER_INIT: AtomicBool = AtomicBool::new(false);
+/// Times each hart entered the kernel from user mode
+static KERNEL_ENTRIES: [AtomicUsize; MAX_HARTS] = [ENTRIES_INIT; MAX_HARTS];
+/// Whether each hart runs user code, or is on its way there
+static IN_USER: [AtomicBool; MAX_HARTS] = [IN_USER_INIT; MAX_HARTS];
+
+/// Frames a hart may still reach through its TLB
+struct Retired {
+    /// `KERNEL_ENTRIES` of the harts in user mode when they were unmapped
+    entries: [Option<usize>; MAX_HARTS],
+    frames: Vec<FrameTracker>,
+}
+
+static RETIRED: SpinNoIrqLock<Vec<Retired>> = SpinNoIrqLock::new(Vec::new());
+
+/// Id of the hart running this code
+pub fn hart_id() -> usize {
+    let id;
+    unsafe {
+        asm!("mv {}, tp", out(reg) id);
+    }
+    id
+}
+
+/// Record that this hart is about to run tasks
+pub fn set_online() {
+    ONLINE_HARTS.fetch_or(1 << hart_id(), Ordering::Release);
+}
+
+/// Ids of the harts running tasks
+pub fn online_harts() -> impl Iterator<Item = usize> {
+    let online = ONLINE_HARTS.load(Ordering::Acquire);
+    (0..MAX_HARTS).filter(move |id| online & (1 << id) != 0)
+}
+
+/// Start every other hart the machine has and wait until they are online
+pub fn start_secondary_harts() {
+    extern "C" {
+        fn _start_secondary();
+    }
+    let boot_hart = hart_id();
+    let mut started = 1;
+    for id in (0..MAX_HARTS).filter(|id| *id != boot_hart) {
+        // fails for the harts the machine doesn't have
+        if sbi_rt::hart_start(id, _start_secondary as usize, 0).error == 0 {
+            started += 1;
+        }
+    }
+    while online_harts().count() < started {
+        spin_loop();
+    }
+}
+
+/// Free `frames`, which have just been unmapped from a user address space,
+/// once no other hart can reach them through a stale TLB entry
+pub fn retire_frames(frames: Vec<FrameTracker>) {
+    if frames.is_empty() {
+        return;
+    }
+    // the cleared page table entries are visible before IN_USER is read
+    fence(Ordering::SeqCst);
+    let me = hart_id();
+    let mut entries = [None; MAX_HARTS];
+    let mut mask = 0;
+    for id in online_harts().filter(|id| *id != me) {
+        if IN_USER[id].load(Ordering::SeqCst) {
+            entries[id] = Some(KERNEL_ENTRIES[id].load(Ordering::SeqCst));
+            mask |= 1 << id;
+        }
+    }
+    if mask == 0 {
+        return;
+    }
+    RETIRED.lock().push(Retired { entries, frames });
+    // the software interrupt brings them into the kernel
+    sbi_rt::send_ipi(mask, 0);
+}
+
+/// Record that this hart came from user mode, its TLB is flushed
+pub fn enter_kernel() {
+    let id = hart_id();
+    IN_USER[id].store(false, Ordering::SeqCst);
+    KERNEL_ENTRIES[id].fetch_add(1, Ordering::SeqCst);
+    free_retired();
+}
+
+/// Record that this hart is about to run user code
+pub fn leave_kernel() {
+    IN_USER[hart_id()].store(true, Ordering::SeqCst);
+    fence(Ordering::SeqCst);
+}
+
+/// Free the retired frames every hart concerned has moved on from
+fn free_retired() {
+    let mut retired = RETIRED.lock();
+    let mut freed = Vec::new();
+    let mut i = 0;
+    while i < retired.len() {
+        let done = retired[i].entries.iter().enumerate().all(|(id, entries)| {
+            entries.map_or(true, |n| KERNEL_ENTRIES[id].load(Ordering::SeqCst) != n)
+        });
+        if done {
+            freed.push(retired.swap_remove(i));
+        } else {
+            i += 1;
+        }
+    }
+    // the frame allocator lock is taken outside RETIRED
+    drop(retired);
+    drop(freed);
+}
@@ -1,10 +1,10 @@
-use crate::sync::{Mutex, UPIntrFreeCell};
+use crate::sync::{Mutex, SpinNoIrqLock};
 use crate::task::{block_current_and_run_next, block_current_task, current_task, TaskContext, TaskControlBlock};
 use alloc::{collections::VecDeque, sync::Arc};
 use crate::task::manager::wakeup_task;
 
 pub struct Condvar {
-    pub inner: UPIntrFreeCell<CondvarInner>,
+    pub inner: SpinNoIrqLock<CondvarInner>,
 }
 
 pub struct CondvarInner {
@@ -14,38 +14,44 @@ pub struct CondvarInner {
 impl Condvar {
     pub fn new() -> Self {
         Self {
-            inner: unsafe {
-                UPIntrFreeCell::new(CondvarInner {
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinNoIrqLock::new(CondvarInner {
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
 
     pub fn signal(&self) {
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         if let Some(task) = inner.wait_queue.pop_front() {
             wakeup_task(task);
         }
     }
 
+    /// Wake up every waiting task
+    pub fn broadcast(&self) {
+        let mut inner = self.inner.lock();
+        while let Some(task) = inner.wait_queue.pop_front() {
+            wakeup_task(task);
+        }
+    }
+
     pub fn wait(&self, mutex: Arc<dyn Mutex>) {
         mutex.unlock();
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         inner.wait_queue.push_back(current_task().unwrap());
         drop(inner);
         block_current_and_run_next();
         mutex.lock();
     }
     pub fn wait_no_sched(&self) -> *mut TaskContext {
-        self.inner.exclusive_session(|inner| {
+        self.inner.with_lock(|inner| {
             inner.wait_queue.push_back(current_task().unwrap());
         });
         block_current_task()
     }
     pub fn wait_with_mutex(&self, mutex: Arc<dyn Mutex>) {
         mutex.unlock();
-        self.inner.exclusive_session(|inner| {
+        self.inner.with_lock(|inner| {
             inner.wait_queue.push_back(current_task().unwrap());
         });
         block_current_and_run_next();
@@ -1,9 +1,11 @@
-mod up;
+mod spin;
 mod mutex;
 mod semaphore;
 mod condvar;
+mod poll_queue;
 
 pub use condvar::Condvar;
+pub use poll_queue::PollQueue;
 pub use mutex::{Mutex, MutexBlocking, MutexSpin};
 pub use semaphore::Semaphore;
-pub use up::{UPIntrFreeCell, UPIntrRefMut};
+pub use spin::{SpinNoIrqGuard, SpinNoIrqLock};
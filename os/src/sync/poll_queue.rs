@@ -0,0 +1,38 @@
+use crate::sync::SpinNoIrqLock;
+use crate::task::manager::wakeup_task;
+use crate::task::TaskControlBlock;
+use alloc::{collections::VecDeque, sync::Arc};
+
+/// Tasks sleeping in `poll` until a file may have become ready.
+/// One task can wait on several queues at once, so it is only woken
+/// by the first of them and removes itself from the others.
+pub struct PollQueue {
+    wait_queue: SpinNoIrqLock<VecDeque<Arc<TaskControlBlock>>>,
+}
+
+impl PollQueue {
+    pub fn new() -> Self {
+        Self {
+            wait_queue: SpinNoIrqLock::new(VecDeque::new()),
+        }
+    }
+
+    pub fn add(&self, task: Arc<TaskControlBlock>) {
+        self.wait_queue.lock().push_back(task);
+    }
+
+    pub fn remove(&self, task: &Arc<TaskControlBlock>) {
+        self.wait_queue
+            .lock()
+            .retain(|waiter| !Arc::ptr_eq(waiter, task));
+    }
+
+    /// Wake every task in `poll`. The wakeup is held while the queue is
+    /// locked, so a task that left the queue has had all of them
+    pub fn wake_all(&self) {
+        let mut wait_queue = self.wait_queue.lock();
+        while let Some(task) = wait_queue.pop_front() {
+            wakeup_task(task);
+        }
+    }
+}
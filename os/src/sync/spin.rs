@@ -0,0 +1,136 @@
+//! A spinlock that masks interrupts on the local hart while it is held.
+//!
+//! Interrupt handlers take the same locks as the code they interrupt, so a
+//! hart must not be interrupted while holding one or it would spin on itself.
+//! Masking nests: interrupts come back when the outermost lock is released,
+//! and only if they were enabled before it was taken.
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use riscv::register::sstatus;
+
+use crate::config::MAX_HARTS;
+use crate::smp::hart_id;
+
+/// Interrupt masking state of one hart
+struct IntrMaskingInfo {
+    nested_level: usize,
+    sie_before_masking: bool,
+}
+
+impl IntrMaskingInfo {
+    const fn new() -> Self {
+        Self {
+            nested_level: 0,
+            sie_before_masking: false,
+        }
+    }
+
+    fn enter(&mut self) {
+        let sie = sstatus::read().sie();
+        unsafe {
+            sstatus::clear_sie();
+        }
+        if self.nested_level == 0 {
+            self.sie_before_masking = sie;
+        }
+        self.nested_level += 1;
+    }
+
+    fn exit(&mut self) {
+        self.nested_level -= 1;
+        if self.nested_level == 0 && self.sie_before_masking {
+            unsafe {
+                sstatus::set_sie();
+            }
+        }
+    }
+}
+
+/// Every hart only touches its own entry, with interrupts masked
+struct PerHartMasking(UnsafeCell<[IntrMaskingInfo; MAX_HARTS]>);
+
+unsafe impl Sync for PerHartMasking {}
+
+const MASKING_INIT: IntrMaskingInfo = IntrMaskingInfo::new();
+static INTR_MASKING_INFO: PerHartMasking =
+    PerHartMasking(UnsafeCell::new([MASKING_INIT; MAX_HARTS]));
+
+fn masking_info() -> &'static mut IntrMaskingInfo {
+    unsafe { &mut (*INTR_MASKING_INFO.0.get())[hart_id()] }
+}
+
+/// Holder of an unlocked lock
+const NO_OWNER: usize = usize::MAX;
+
+pub struct SpinNoIrqLock<T> {
+    locked: AtomicBool,
+    /// hart holding the lock, to catch a hart taking it twice
+    owner: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinNoIrqLock<T> {}
+
+pub struct SpinNoIrqGuard<'a, T> {
+    lock: &'a SpinNoIrqLock<T>,
+}
+
+impl<T> SpinNoIrqLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            owner: AtomicUsize::new(NO_OWNER),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Spin until the lock is free. Panics if this hart already holds it,
+    /// which would otherwise never end.
+    pub fn lock(&self) -> SpinNoIrqGuard<'_, T> {
+        masking_info().enter();
+        let hart = hart_id();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            if self.owner.load(Ordering::Relaxed) == hart {
+                panic!("lock already held by hart {}", hart);
+            }
+            spin_loop();
+        }
+        self.owner.store(hart, Ordering::Relaxed);
+        SpinNoIrqGuard { lock: self }
+    }
+
+    pub fn with_lock<F, V>(&self, f: F) -> V
+    where
+        F: FnOnce(&mut T) -> V,
+    {
+        let mut inner = self.lock();
+        f(inner.deref_mut())
+    }
+}
+
+impl<'a, T> Drop for SpinNoIrqGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.owner.store(NO_OWNER, Ordering::Relaxed);
+        self.lock.locked.store(false, Ordering::Release);
+        masking_info().exit();
+    }
+}
+
+impl<'a, T> Deref for SpinNoIrqGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for SpinNoIrqGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
@@ -1,14 +1,21 @@
 use alloc::sync::Arc;
-use crate::fs::{open_file, OpenFlags, Stat};
+use alloc::vec::Vec;
+use crate::fs::{mkfifo, open, unlink, FdFlags, OpenFlags, PollEvents, PollFd, Stat, StatMode};
 use crate::mm::page_table::{translated_refmut, translated_str, UserBuffer};
 use crate::mm::translated_byte_buffer;
-use crate::task::{current_task, current_user_token, suspend_current_and_run_next};
+use crate::task::{block_current_task, current_task, current_user_token, schedule, suspend_current_and_run_next};
+use crate::timer::{add_timer, get_time_ms, remove_timer};
 use crate::sbi::console_getchar;
 use crate::fs::make_pipe;
 use crate::task::processor::current_process;
 
 const FD_STDIN: usize = 0;
 const FD_STDOUT: usize = 1;
+/// `dup3` refuses target fds at or above this
+const FD_LIMIT: usize = 1024;
+
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
@@ -57,13 +64,17 @@ pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let task = current_process();
     let token = current_user_token();
     let path = translated_str(token, path);
-    if let Some(inode) = open_file(
-        path.as_str(),
-        OpenFlags::from_bits(flags).unwrap()
-    ) {
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    if let Some(inode) = open(path.as_str(), flags - OpenFlags::CLOEXEC) {
         let mut inner = task.inner_exclusive_access();
         let fd = inner.alloc_fd();
         inner.fd_table[fd] = Some(inode);
+        if flags.contains(OpenFlags::CLOEXEC) {
+            inner.fd_flags[fd] = FdFlags::CLOEXEC;
+        }
         fd as isize
     } else {
         -1
@@ -111,8 +122,125 @@ pub fn sys_dup(fd: usize) -> isize {
     new_fd as isize
 }
 
+/// Duplicate `old_fd` onto exactly `new_fd`, closing the file previously there.
+/// `flags` may only hold `OpenFlags::CLOEXEC`, which marks `new_fd` close-on-exec.
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) if (flags - OpenFlags::CLOEXEC).is_empty() => flags,
+        _ => return -1,
+    };
+    if old_fd == new_fd || new_fd >= FD_LIMIT {
+        return -1;
+    }
+    let task = current_process();
+    let mut inner = task.inner_exclusive_access();
+    let file = match inner.fd_table.get(old_fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -1,
+    };
+    let fd_flags = if flags.contains(OpenFlags::CLOEXEC) {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+    inner.install_fd(new_fd, file, fd_flags);
+    new_fd as isize
+}
+
+/// Get (`F_GETFD`) or set (`F_SETFD`) the `FdFlags` of `fd`
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let task = current_process();
+    let mut inner = task.inner_exclusive_access();
+    if !matches!(inner.fd_table.get(fd), Some(Some(_))) {
+        return -1;
+    }
+    match cmd {
+        F_GETFD => inner.fd_flags[fd].bits() as isize,
+        F_SETFD => match FdFlags::from_bits(arg as u32) {
+            Some(flags) => {
+                inner.fd_flags[fd] = flags;
+                0
+            }
+            None => -1,
+        },
+        _ => -1,
+    }
+}
+
+/// Wait until one of the `nfds` entries at `fds` is ready, or `timeout_ms` has passed.
+/// A negative timeout waits forever, zero only checks.
+/// Returns how many entries got a non-empty `revents`.
+pub fn sys_poll(fds: *mut PollFd, nfds: usize, timeout_ms: isize) -> isize {
+    let token = current_user_token();
+    let expire_ms = if timeout_ms >= 0 {
+        Some(get_time_ms() + timeout_ms as usize)
+    } else {
+        None
+    };
+    let task = current_task().unwrap();
+    loop {
+        let process = current_process();
+        let inner = process.inner_exclusive_access();
+        let mut ready = 0;
+        let mut queues = Vec::new();
+        for i in 0..nfds {
+            let pollfd = translated_refmut(token, unsafe { fds.add(i) });
+            let events = PollEvents::from_bits_truncate(pollfd.events as u16);
+            let revents = if pollfd.fd < 0 {
+                PollEvents::empty()
+            } else if let Some(Some(file)) = inner.fd_table.get(pollfd.fd as usize) {
+                if let Some(queue) = file.poll_queue() {
+                    queues.push(queue);
+                }
+                file.poll(events)
+            } else {
+                PollEvents::NVAL
+            };
+            pollfd.revents = revents.bits() as i16;
+            if !revents.is_empty() {
+                ready += 1;
+            }
+        }
+        if ready > 0 || expire_ms.map_or(false, |expire_ms| get_time_ms() >= expire_ms) {
+            return ready;
+        }
+        // a wakeup between joining the queues and blocking, from an
+        // interrupt or another hart, is kept and skips the block
+        for queue in queues.iter() {
+            queue.add(task.clone());
+        }
+        if let Some(expire_ms) = expire_ms {
+            add_timer(expire_ms, task.clone());
+        }
+        let task_cx_ptr = block_current_task();
+        drop(inner);
+        schedule(task_cx_ptr);
+        // woken by one queue or the timer, leave all the others
+        for queue in queues.iter() {
+            queue.remove(&task);
+        }
+        remove_timer(task.clone());
+        // nothing can wake it now, so a wakeup that raced with leaving is stale
+        task.inner_exclusive_access().wakeup_pending = false;
+    }
+}
+
 
 
+/// Create a special file, only named pipes are supported
+pub fn sys_mknodat(path: *const u8, mode: u32) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    if StatMode::from_bits_truncate(mode & 0o170000) != StatMode::FIFO {
+        return -1;
+    }
+    if mkfifo(path.as_str()) {
+        0
+    } else {
+        -1
+    }
+}
+
 // YOUR JOB: 扩展 easy-fs 和内核以实现以下三个 syscall
 pub fn sys_fstat(_fd: usize, _st: *mut Stat) -> isize {
     -1
@@ -122,8 +250,14 @@ pub fn sys_linkat(_old_name: *const u8, _new_name: *const u8) -> isize {
     -1
 }
 
-pub fn sys_unlinkat(_name: *const u8) -> isize {
-    -1
+pub fn sys_unlinkat(name: *const u8) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, name);
+    if unlink(path.as_str()) {
+        0
+    } else {
+        -1
+    }
 }
 
 
@@ -1,54 +1,184 @@
-use crate::net::port_table::{accept, listen, port_acceptable, PortFd};
-use crate::net::udp::UDP;
-use crate::net::{net_interrupt_handler, IPv4};
-use crate::task::{current_process, current_task, current_trap_cx};
+use crate::fs::{FdFlags, File};
+use crate::mm::{translated_byte_buffer, translated_ref, translated_refmut, UserBuffer};
+use crate::net::dhcp;
+use crate::net::iface::{self, IfConfig, CONFIG_DHCP, CONFIG_NONE, CONFIG_STATIC};
+use crate::net::packet::Ipv4Addr;
+use crate::net::socket::{SockAddrIn, Socket, AF_INET, SOCK_CLOEXEC};
+use crate::task::{current_process, current_user_token};
 use alloc::sync::Arc;
+use core::mem::size_of;
 
-// just support udp
-pub fn sys_connect(raddr: u32, lport: u16, rport: u16) -> isize {
+/// The file at `fd` if it is a socket
+fn socket_file(fd: usize) -> Option<Arc<dyn File + Send + Sync>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = inner.fd_table.get(fd)?.clone()?;
+    file.as_socket()?;
+    Some(file)
+}
+
+/// Read a `sockaddr_in` from user space
+fn read_addr(addr: *const SockAddrIn, addrlen: usize) -> Option<(Ipv4Addr, u16)> {
+    if addr.is_null() || addrlen < size_of::<SockAddrIn>() {
+        return None;
+    }
+    translated_ref(current_user_token(), addr).endpoint()
+}
+
+/// Store `endpoint` at `addr` unless it is null, and its size at `addrlen`
+fn write_addr(addr: *mut SockAddrIn, addrlen: *mut u32, endpoint: (Ipv4Addr, u16)) {
+    if addr.is_null() {
+        return;
+    }
+    let token = current_user_token();
+    *translated_refmut(token, addr) = SockAddrIn::new(endpoint);
+    if !addrlen.is_null() {
+        *translated_refmut(token, addrlen) = size_of::<SockAddrIn>() as u32;
+    }
+}
+
+fn install_socket(socket: Socket, flags: FdFlags) -> isize {
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
     let fd = inner.alloc_fd();
-    // todo 区分TCP和UDP 模拟网络栈
-    let udp_node = UDP::new(IPv4::from_u32(raddr), lport, rport);
-    inner.fd_table[fd] = Some(Arc::new(udp_node));
+    inner.install_fd(fd, Arc::new(socket), flags);
     fd as isize
 }
 
-// listen a port
-pub fn sys_listen(port: u16) -> isize {
-    match listen(port) {
-        Some(port_index) => {
-            let process = current_process();
-            let mut inner = process.inner_exclusive_access();
-            let fd = inner.alloc_fd();
-            let port_fd = PortFd::new(port_index);
-            inner.fd_table[fd] = Some(Arc::new(port_fd));
+/// Create an unconnected socket, `sock_type` may carry `SOCK_CLOEXEC`
+pub fn sys_socket(domain: usize, sock_type: usize, protocol: usize) -> isize {
+    if domain != AF_INET {
+        return -1;
+    }
+    let flags = if sock_type & SOCK_CLOEXEC != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+    match Socket::new(sock_type & !SOCK_CLOEXEC, protocol) {
+        Some(socket) => install_socket(socket, flags),
+        None => -1,
+    }
+}
+
+pub fn sys_bind(fd: usize, addr: *const SockAddrIn, addrlen: usize) -> isize {
+    let (file, endpoint) = match (socket_file(fd), read_addr(addr, addrlen)) {
+        (Some(file), Some(endpoint)) => (file, endpoint),
+        _ => return -1,
+    };
+    if file.as_socket().unwrap().bind(endpoint) {
+        0
+    } else {
+        -1
+    }
+}
 
-            // NOTICE: this return the port index, not the fd
-            port_index as isize
+pub fn sys_listen(fd: usize, backlog: usize) -> isize {
+    match socket_file(fd) {
+        Some(file) if file.as_socket().unwrap().listen(backlog) => 0,
+        _ => -1,
+    }
+}
+
+// accept a tcp connection, blocks until a handshake on the port completes
+pub fn sys_accept(fd: usize, addr: *mut SockAddrIn, addrlen: *mut u32) -> isize {
+    let file = match socket_file(fd) {
+        Some(file) => file,
+        None => return -1,
+    };
+    match file.as_socket().unwrap().accept() {
+        Some((socket, peer)) => {
+            write_addr(addr, addrlen, peer);
+            install_socket(socket, FdFlags::empty())
         }
         None => -1,
     }
 }
 
-// accept a tcp connection
-pub fn sys_accept(port_index: usize) -> isize {
-    println!("accepting port {}", port_index);
+pub fn sys_connect(fd: usize, addr: *const SockAddrIn, addrlen: usize) -> isize {
+    let (file, endpoint) = match (socket_file(fd), read_addr(addr, addrlen)) {
+        (Some(file), Some(endpoint)) => (file, endpoint),
+        _ => return -1,
+    };
+    if file.as_socket().unwrap().connect(endpoint) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Send to `addr`, or to the connected peer when it is null
+pub fn sys_sendto(
+    fd: usize,
+    buf: *const u8,
+    len: usize,
+    _flags: usize,
+    addr: *const SockAddrIn,
+    addrlen: usize,
+) -> isize {
+    let file = match socket_file(fd) {
+        Some(file) => file,
+        None => return -1,
+    };
+    let to = if addr.is_null() {
+        None
+    } else {
+        match read_addr(addr, addrlen) {
+            Some(endpoint) => Some(endpoint),
+            None => return -1,
+        }
+    };
+    let buf = UserBuffer::new(translated_byte_buffer(current_user_token(), buf, len));
+    file.as_socket().unwrap().send_to(buf, to)
+}
+
+/// Receive data, storing the sender at `addr` unless it is null
+pub fn sys_recvfrom(
+    fd: usize,
+    buf: *mut u8,
+    len: usize,
+    _flags: usize,
+    addr: *mut SockAddrIn,
+    addrlen: *mut u32,
+) -> isize {
+    let file = match socket_file(fd) {
+        Some(file) => file,
+        None => return -1,
+    };
+    let buf = UserBuffer::new(translated_byte_buffer(current_user_token(), buf, len));
+    let (len, from) = file.as_socket().unwrap().recv_from(buf);
+    if let Some(from) = from {
+        write_addr(addr, addrlen, from);
+    }
+    len
+}
 
-    let task = current_task().unwrap();
-    accept(port_index, task);
-    // block_current_and_run_next();
+pub fn sys_shutdown(fd: usize, how: usize) -> isize {
+    match socket_file(fd) {
+        Some(file) if file.as_socket().unwrap().shutdown(how) => 0,
+        _ => -1,
+    }
+}
 
-    // NOTICE: There does not have interrupt handler, just call it munually.
-    loop {
-        net_interrupt_handler();
+pub fn sys_ifconfig_get(config: *mut IfConfig) -> isize {
+    *translated_refmut(current_user_token(), config) = iface::config();
+    0
+}
 
-        if !port_acceptable(port_index) {
-            break;
+/// Set a static address, start DHCP or deconfigure the interface, by `source`
+pub fn sys_ifconfig_set(config: *const IfConfig) -> isize {
+    let config = *translated_ref(current_user_token(), config);
+    match config.source {
+        CONFIG_DHCP => dhcp::start(),
+        CONFIG_STATIC if config.prefix_len <= 32 => {
+            dhcp::stop();
+            iface::set_config(config);
         }
+        CONFIG_NONE => {
+            dhcp::stop();
+            iface::clear_config();
+        }
+        _ => return -1,
     }
-
-    let cx = current_trap_cx();
-    cx.x[10] as isize
+    0
 }
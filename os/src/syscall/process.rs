@@ -1,19 +1,26 @@
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use log::info;
-use crate::config::MAX_SYSCALL_NUM;
+use crate::config::{MAX_SYSCALL_NUM, USER_HEAP_MAX, USER_STACK_SIZE};
 use crate::fs::{open_file, OpenFlags};
 // use crate::loader::get_app_data_by_name;
 use crate::mm::page_table::{PageTable, translated_ref, translated_refmut, translated_str};
-use crate::mm::{PhysAddr, VirtAddr};
+use crate::mm::{MemorySet, PhysAddr, VirtAddr};
 
 use crate::task::{add_task, current_task, current_user_token, exit_current_and_run_next, MAX_SIG, pid2process, SignalAction, SignalFlags, suspend_current_and_run_next, TaskStatus};
+use crate::task::process::user_stack_size;
 use crate::task::processor::current_process;
+use crate::task::ptrace::{is_tracing, wait_stop, wake_killed, WUNTRACED};
 
 
 use crate::timer::get_time_ms;
 
+const ENOEXEC: isize = 8;
+/// `#!` lines are cut after this many bytes
+const SHEBANG_MAX: usize = 128;
+
 /// task exits and submit an exit code
 pub fn sys_exit(exit_code: i32) -> ! {
     info!("[kernel] Application exited with code {}", exit_code);
@@ -84,6 +91,23 @@ pub fn sys_set_priority(_prio: isize) -> isize {
 }
 
 // YOUR JOB: 扩展内核以实现 sys_mmap 和 sys_munmap
+/// Move the end of the heap to `addr` and return the new end, `brk(0)` only
+/// reads it. The end stays where it was if `addr` is below the heap, past
+/// `USER_HEAP_MAX` or the frames run out
+pub fn sys_brk(addr: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let (heap_bottom, program_brk) = (inner.heap_bottom, inner.program_brk);
+    if addr < heap_bottom || addr > heap_bottom + USER_HEAP_MAX {
+        return program_brk as isize;
+    }
+    if !inner.memory_set.resize_area(heap_bottom.into(), addr.into()) {
+        return program_brk as isize;
+    }
+    inner.program_brk = addr;
+    addr as isize
+}
+
 pub fn sys_mmap(_start: usize, _len: usize, _port: usize) -> isize {
     return -1;
     // if (_start % PAGE_SIZE) != 0 { return -1; }
@@ -132,8 +156,17 @@ pub fn sys_fork() -> isize {
 
 /// If there is not a child process whose pid is same as given, return -1.
 /// Else if there is a child process but it is still running, return -2.
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+/// With `WUNTRACED` in `options`, stops of the tracees of the caller are
+/// reported too, and exit codes are encoded as in Linux, `code << 8`.
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32, options: usize) -> isize {
     let process = current_process();
+    let untraced = options & WUNTRACED != 0;
+    if untraced {
+        if let Some((found_pid, status)) = wait_stop(process.getpid(), pid) {
+            *translated_refmut(process.inner_exclusive_access().memory_set.token(), exit_code_ptr) = status;
+            return found_pid as isize;
+        }
+    }
     // find a child process
 
     let mut inner = process.inner_exclusive_access();
@@ -142,6 +175,10 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         .iter()
         .any(|p| pid == -1 || pid as usize == p.getpid())
     {
+        // an attached tracee, not a child
+        if untraced && pid > 0 && is_tracing(process.getpid(), pid as usize) {
+            return -2;
+        }
         return -1;
         // ---- release current PCB
     }
@@ -156,8 +193,11 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.getpid();
         // ++++ temporarily access child PCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
+        let mut exit_code = child.inner_exclusive_access().exit_code;
         // ++++ release child PCB
+        if untraced {
+            exit_code = (exit_code & 0xff) << 8;
+        }
         *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
         found_pid as isize
     } else {
@@ -166,37 +206,100 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     // ---- release current PCB automatically
 }
 
-/// Syscall Exec which accepts the elf path
-pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
-    let token = current_user_token();
-    let path = translated_str(token, path);
-    let mut args_vec: Vec<String> = Vec::new();
+/// Strings of a user array of string pointers ending with a null pointer
+fn translated_str_array(token: usize, mut array: *const usize) -> Vec<String> {
+    let mut strings = Vec::new();
+    if array.is_null() {
+        return strings;
+    }
     loop {
-        let arg_str_ptr = *translated_ref(token, args);
-        if arg_str_ptr == 0 {
+        let str_ptr = *translated_ref(token, array);
+        if str_ptr == 0 {
             break;
         }
-        args_vec.push(translated_str(token, arg_str_ptr as *const u8));
+        strings.push(translated_str(token, str_ptr as *const u8));
         unsafe {
-            args = args.add(1);
+            array = array.add(1);
         }
     }
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
-        let process = current_process();
-        let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
-        // return argc because cx.x[10] will be covered with it later
-        argc as isize
-    } else {
-        -1
+    strings
+}
+
+/// The contents of an executable file
+fn read_exec_file(path: &str) -> Option<Vec<u8>> {
+    open_file(path.trim_start_matches('/'), OpenFlags::RDONLY).map(|inode| inode.read_all())
+}
+
+/// The interpreter a `#!` script names, and its argument if any
+fn parse_shebang(data: &[u8]) -> Option<(String, Option<String>)> {
+    let line = data.strip_prefix(b"#!")?;
+    let len = line.iter().position(|byte| *byte == b'\n').unwrap_or(line.len());
+    let line = core::str::from_utf8(&line[..len.min(SHEBANG_MAX)]).ok()?.trim();
+    let (interpreter, arg) = match line.split_once(|c: char| c == ' ' || c == '\t') {
+        Some((interpreter, arg)) => (interpreter, Some(arg.trim())),
+        None => (line, None),
+    };
+    if interpreter.is_empty() {
+        return None;
     }
+    Some((String::from(interpreter), arg.map(String::from)))
+}
+
+/// Syscall Exec which accepts the elf path, the arguments and the
+/// environment, both arrays ending with a null pointer. A null `envs` is
+/// an empty environment. A `#!` script runs as `interpreter [arg] path
+/// args[1..]`. Returns -ENOEXEC if the program can't be loaded
+pub fn sys_exec(path: *const u8, args: *const usize, envs: *const usize) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let mut args_vec = translated_str_array(token, args);
+    let envs_vec = translated_str_array(token, envs);
+    let mut data = match read_exec_file(path.as_str()) {
+        Some(data) => data,
+        None => return -1,
+    };
+    if let Some((interpreter, arg)) = parse_shebang(&data) {
+        let mut script_args = vec![interpreter.clone()];
+        script_args.extend(arg);
+        script_args.push(path.clone());
+        script_args.extend(args_vec.into_iter().skip(1));
+        args_vec = script_args;
+        data = match read_exec_file(interpreter.as_str()) {
+            Some(data) => data,
+            None => return -1,
+        };
+        // interpreters can't be scripts themselves
+        if data.starts_with(b"#!") {
+            return -ENOEXEC;
+        }
+    }
+    let (memory_set, ustack_base, elf_info) = match MemorySet::from_elf(data.as_slice()) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            info!("[kernel] can't exec {}: {}", path, err);
+            return -ENOEXEC;
+        }
+    };
+    // the strings, their pointers, the program headers and the auxiliary
+    // vector must leave room on the new user stack
+    if user_stack_size(&args_vec, &envs_vec, &elf_info) > USER_STACK_SIZE / 2 {
+        return -1;
+    }
+    let process = current_process();
+    let argc = args_vec.len();
+    process.exec(memory_set, ustack_base, elf_info, args_vec, envs_vec);
+    // return argc because cx.x[10] will be covered with it later
+    argc as isize
 }
 
 pub fn sys_kill(pid: usize, signal: u32) -> isize {
     if let Some(process) = pid2process(pid) {
         if let Some(flag) = SignalFlags::from_bits(signal) {
             process.inner_exclusive_access().signals |= flag;
+            // a stopped tracee would never see it
+            if flag.contains(SignalFlags::SIGKILL) {
+                wake_killed(&process);
+            }
             0
         } else {
             -1
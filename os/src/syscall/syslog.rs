@@ -0,0 +1,28 @@
+use crate::config::LOG_BUFFER_SIZE;
+use crate::logging::{clear_log, log_len, read_log};
+use crate::mm::{translated_byte_buffer, UserBuffer};
+use crate::task::current_user_token;
+
+// the actions of Linux `syslog` that the kernel log supports
+const SYSLOG_ACTION_READ_ALL: usize = 3;
+const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+const SYSLOG_ACTION_CLEAR: usize = 5;
+const SYSLOG_ACTION_SIZE_UNREAD: usize = 9;
+const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+
+/// Read or clear the kernel log, reads get its newest `len` bytes
+pub fn sys_syslog(action: usize, buf: *mut u8, len: usize) -> isize {
+    match action {
+        SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR => {
+            let buf = UserBuffer::new(translated_byte_buffer(current_user_token(), buf, len));
+            read_log(buf, action == SYSLOG_ACTION_READ_CLEAR) as isize
+        }
+        SYSLOG_ACTION_CLEAR => {
+            clear_log();
+            0
+        }
+        SYSLOG_ACTION_SIZE_UNREAD => log_len() as isize,
+        SYSLOG_ACTION_SIZE_BUFFER => LOG_BUFFER_SIZE as isize,
+        _ => -1,
+    }
+}
@@ -3,9 +3,17 @@ use crate::{
     trap::{trap_handler, TrapContext},
 };
 use alloc::sync::Arc;
+use crate::config::{PAGE_SIZE, USER_STACK_LIMIT, USER_STACK_MAX};
 use crate::mm::memory_set::kernel_token;
 
-pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
+/// Start a thread at `entry` whose stack may grow to `stack_size` bytes,
+/// `USER_STACK_LIMIT` if it is 0. Returns -1 if it is over `USER_STACK_MAX`
+pub fn sys_thread_create(entry: usize, arg: usize, stack_size: usize) -> isize {
+    let ustack_limit = match stack_size {
+        0 => USER_STACK_LIMIT,
+        size if size <= USER_STACK_MAX => (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+        _ => return -1,
+    };
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
     // create a new thread
@@ -16,6 +24,7 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
             .as_ref()
             .unwrap()
             .ustack_base(),
+        ustack_limit,
         true,
     ));
     // add new task to scheduler
@@ -10,15 +10,18 @@ mod signal;
 mod action;
 mod semaphore;
 pub mod process;
+pub mod ptrace;
 
 // use crate::loader::get_app_data_by_name;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use lazy_static::*;
+use log::debug;
 use switch::__switch;
 pub use task::{TaskControlBlock, TaskStatus};
 
 use crate::fs::{open_file, OpenFlags};
+use crate::mm::StackFault;
 
 pub use context::TaskContext;
 pub use action::{SignalAction, SignalActions};
@@ -41,7 +44,7 @@ lazy_static! {
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
         let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
         let v = inode.read_all();
-        ProcessControlBlock::new(v.as_slice())
+        ProcessControlBlock::new("initproc", v.as_slice())
     };
 }
 
@@ -54,16 +57,21 @@ pub fn add_initproc() {
 pub fn block_current_task() -> *mut TaskContext {
     let task = take_current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
-    task_inner.task_status = TaskStatus::Blocked;
-    &mut task_inner.task_cx as *mut TaskContext
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    // woken before it got here, it only yields
+    if task_inner.wakeup_pending {
+        task_inner.wakeup_pending = false;
+        task_inner.task_status = TaskStatus::Ready;
+        drop(task_inner);
+        add_task(task);
+    } else {
+        task_inner.task_status = TaskStatus::Blocked;
+    }
+    task_cx_ptr
 }
 
 pub fn block_current_and_run_next() {
-    let task = take_current_task().unwrap();
-    let mut task_inner = task.inner_exclusive_access();
-    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
-    task_inner.task_status = TaskStatus::Blocked;
-    drop(task_inner);
+    let task_cx_ptr = block_current_task();
     schedule(task_cx_ptr);
 }
 
@@ -119,6 +127,7 @@ pub fn exit_current_and_run_next(exit_code: i32) {
             }
         }
         remove_from_pid2process(pid);
+        ptrace::release_tracees(pid);
         let mut process_inner = process.inner_exclusive_access();
         // mark this process as a zombie process
         process_inner.is_zombie = true;
@@ -164,6 +173,7 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         process_inner.memory_set.recycle_data_pages();
         // drop file descriptors
         process_inner.fd_table.clear();
+        process_inner.fd_flags.clear();
         // remove all tasks
         process_inner.tasks.clear();
     }
@@ -179,6 +189,21 @@ pub fn current_add_signal(signal: SignalFlags) {
     process_inner.signals |= signal;
 }
 
+/// A load or store page fault of the current process at `va`: grow the
+/// stack below which it hit, else SIGSEGV
+pub fn current_page_fault(va: usize) {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    match process_inner.memory_set.grow_stack(va) {
+        StackFault::Grown => {}
+        StackFault::Overflow => {
+            process_inner.stack_overflow = true;
+            process_inner.signals |= SignalFlags::SIGSEGV;
+        }
+        StackFault::NotStack => process_inner.signals |= SignalFlags::SIGSEGV,
+    }
+}
+
 pub fn handle_signals() {
     loop {
         check_pending_signals();
@@ -222,11 +247,11 @@ fn check_pending_signals() {
                     || signal == SignalFlags::SIGCONT
                     || signal == SignalFlags::SIGDEF
                 {
-                    println!("call_kernel_signal_handler: signal: {:?}", signal);
+                    debug!("call_kernel_signal_handler: signal: {:?}", signal);
                     // signal is a kernel signal
                     call_kernel_signal_handler(signal);
                 } else {
-                    println!("call_user_signal_handler: signal: {:?}", signal);
+                    debug!("call_user_signal_handler: signal: {:?}", signal);
                     // signal is a user signal
                     call_user_signal_handler(sig, signal);
                     return;
@@ -285,7 +310,7 @@ fn call_user_signal_handler(sig: usize, signal: SignalFlags) {
         trap_ctx.x[10] = sig;
     } else {
         // default action
-        println!("[K] task/call_user_signal_handler: default action: ignore it or kill process");
+        debug!("task/call_user_signal_handler: default action: ignore it or kill process");
     }
 }
 
@@ -314,7 +339,10 @@ pub fn check_signals_error_of_current() -> Option<(i32, &'static str)> {
     //     "[K] check_signals_error_of_current {:?}",
     //     task_inner.signals
     // );
-    task_inner.signals.check_error()
+    match task_inner.signals.check_error() {
+        Some((-11, _)) if task_inner.stack_overflow => Some((-11, "Stack Overflow, SIGSEGV=11")),
+        error => error,
+    }
 }
 
 pub fn remove_inactive_task(task: Arc<TaskControlBlock>) {
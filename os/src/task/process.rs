@@ -1,73 +1,83 @@
 use super::id::RecycleAllocator;
 use super::manager::insert_into_pid2process;
+use super::ptrace::Tracee;
 use super::TaskControlBlock;
 use super::{add_task, SignalFlags};
 use super::{pid_alloc, PidHandle};
-use crate::fs::{File, Stdin, Stdout};
-use crate::mm::{MemorySet, KERNEL_SPACE};
-use crate::sync::{Condvar, Mutex, Semaphore, UPIntrFreeCell, UPIntrRefMut};
+use crate::fs::{FdFlags, File, Stdin, Stdout};
+use crate::config::{PAGE_SIZE, USER_STACK_LIMIT};
+use crate::mm::{ElfInfo, MemorySet, KERNEL_SPACE};
+use crate::sync::{Condvar, Mutex, Semaphore, SpinNoIrqLock, SpinNoIrqGuard};
 use crate::trap::{trap_handler, TrapContext};
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::RefMut;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use crate::mm::page_table::translated_refmut;
 use crate::task::semaphore::SemaphoreFlags;
 use crate::task::SignalActions;
+use crate::timer::get_time;
 
 pub struct ProcessControlBlock{
     pub pid:PidHandle,
-    inner: UPIntrFreeCell<ProcessControlBlockInner>
+    inner: SpinNoIrqLock<ProcessControlBlockInner>
 }
 
 impl ProcessControlBlock {
-    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, ProcessControlBlockInner> {
-        self.inner.exclusive_access()
+    pub fn inner_exclusive_access(&self) -> SpinNoIrqGuard<'_, ProcessControlBlockInner> {
+        self.inner.lock()
     }
 
-    pub fn new(elf_data: &[u8]) -> Arc<Self> {
+    pub fn new(name: &str, elf_data: &[u8]) -> Arc<Self> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, ustack_base, elf_info) = MemorySet::from_elf(elf_data).unwrap();
+        let token = memory_set.token();
         // allocate a pid
         let pid_handle = pid_alloc();
         let process = Arc::new(Self {
             pid: pid_handle,
-            inner: unsafe {
-                UPIntrFreeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: None,
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: vec![
-                        // 0 -> stdin
-                        Some(Arc::new(Stdin)),
-                        // 1 -> stdout
-                        Some(Arc::new(Stdout)),
-                        // 2 -> stderr
-                        Some(Arc::new(Stdout)),
-                    ],
-                    semaphore: SemaphoreFlags::empty(),
-                    signals: SignalFlags::empty(),
-                    signal_mask: SignalFlags::empty(),
-                    handling_sig: -1,
-                    signal_actions: SignalActions::default(),
-                    killed: false,
-                    frozen: false,
-                    trap_ctx_backup: None,
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    mutex_list: Vec::new(),
-                    semaphore_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                })
-            },
+            inner: SpinNoIrqLock::new(ProcessControlBlockInner {
+                name: String::from(name),
+                is_zombie: false,
+                memory_set,
+                parent: None,
+                children: Vec::new(),
+                exit_code: 0,
+                fd_table: vec![
+                    // 0 -> stdin
+                    Some(Arc::new(Stdin)),
+                    // 1 -> stdout
+                    Some(Arc::new(Stdout)),
+                    // 2 -> stderr
+                    Some(Arc::new(Stdout)),
+                ],
+                fd_flags: vec![FdFlags::empty(); 3],
+                semaphore: SemaphoreFlags::empty(),
+                signals: SignalFlags::empty(),
+                signal_mask: SignalFlags::empty(),
+                handling_sig: -1,
+                signal_actions: SignalActions::default(),
+                killed: false,
+                stack_overflow: false,
+                heap_bottom: elf_info.heap_bottom,
+                program_brk: elf_info.heap_bottom,
+                frozen: false,
+                trap_ctx_backup: None,
+                tracee: None,
+                tasks: Vec::new(),
+                task_res_allocator: RecycleAllocator::new(),
+                mutex_list: Vec::new(),
+                semaphore_list: Vec::new(),
+                condvar_list: Vec::new(),
+            }),
         });
         // create a main thread, we should allocate ustack and trap_cx here
         let task = Arc::new(TaskControlBlock::new(
             Arc::clone(&process),
             ustack_base,
+            USER_STACK_LIMIT,
             true,
         ));
         // prepare trap_cx of main thread
@@ -76,13 +86,17 @@ impl ProcessControlBlock {
         let ustack_top = task_inner.res.as_ref().unwrap().ustack_top();
         let kstack_top = task.kernel_stack.get_top();
         drop(task_inner);
+        let (user_sp, argv_base, envp_base) =
+            init_user_stack(token, ustack_top, &[], &[], &elf_info);
         *trap_cx = TrapContext::app_init_context(
-            entry_point,
-            ustack_top,
-            KERNEL_SPACE.exclusive_access().token(),
+            elf_info.entry,
+            user_sp,
+            KERNEL_SPACE.lock().token(),
             kstack_top,
             trap_handler as usize,
         );
+        trap_cx.x[11] = argv_base;
+        trap_cx.x[12] = envp_base;
         // add main thread to the process
         let mut process_inner = process.inner_exclusive_access();
         process_inner.tasks.push(Some(Arc::clone(&task)));
@@ -93,14 +107,33 @@ impl ProcessControlBlock {
         process
     }
 
-    /// Only support processes with a single thread.
-    pub fn exec(self: &Arc<Self>, elf_data: &[u8], args: Vec<String>) {
+    /// Only support processes with a single thread. Switches to a program
+    /// `MemorySet::from_elf` loaded, with `args` and `envs` that
+    /// `user_stack_size` says fit on its stack
+    pub fn exec(
+        self: &Arc<Self>,
+        memory_set: MemorySet,
+        ustack_base: usize,
+        elf_info: ElfInfo,
+        args: Vec<String>,
+        envs: Vec<String>,
+    ) {
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
-        // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
         let new_token = memory_set.token();
         // substitute memory_set
-        self.inner_exclusive_access().memory_set = memory_set;
+        let mut inner = self.inner_exclusive_access();
+        inner.memory_set = memory_set;
+        inner.heap_bottom = elf_info.heap_bottom;
+        inner.program_brk = elf_info.heap_bottom;
+        if let Some(name) = args.first() {
+            inner.name = name.clone();
+        }
+        inner.close_on_exec();
+        // a tracer gets a chance to set breakpoints in the new program
+        if inner.tracee.is_some() {
+            inner.signals |= SignalFlags::SIGTRAP;
+        }
+        drop(inner);
         // then we alloc user resource for main thread again
         // since memory_set has been changed
         let task = self.inner_exclusive_access().get_task(0);
@@ -108,41 +141,21 @@ impl ProcessControlBlock {
         task_inner.res.as_mut().unwrap().ustack_base = ustack_base;
         task_inner.res.as_mut().unwrap().alloc_user_res();
         task_inner.trap_cx_ppn = task_inner.res.as_mut().unwrap().trap_cx_ppn();
-        // push arguments on user stack
-        let mut user_sp = task_inner.res.as_mut().unwrap().ustack_top();
-        user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
-        let argv_base = user_sp;
-        let mut argv: Vec<_> = (0..=args.len())
-            .map(|arg| {
-                translated_refmut(
-                    new_token,
-                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
-                )
-            })
-            .collect();
-        *argv[args.len()] = 0;
-        for i in 0..args.len() {
-            user_sp -= args[i].len() + 1;
-            *argv[i] = user_sp;
-            let mut p = user_sp;
-            for c in args[i].as_bytes() {
-                *translated_refmut(new_token, p as *mut u8) = *c;
-                p += 1;
-            }
-            *translated_refmut(new_token, p as *mut u8) = 0;
-        }
-        // make the user_sp aligned to 8B for k210 platform
-        user_sp -= user_sp % core::mem::size_of::<usize>();
+        // push arguments, environment and auxiliary vector on user stack
+        let ustack_top = task_inner.res.as_mut().unwrap().ustack_top();
+        let (user_sp, argv_base, envp_base) =
+            init_user_stack(new_token, ustack_top, &args, &envs, &elf_info);
         // initialize trap_cx
         let mut trap_cx = TrapContext::app_init_context(
-            entry_point,
+            elf_info.entry,
             user_sp,
-            KERNEL_SPACE.exclusive_access().token(),
+            KERNEL_SPACE.lock().token(),
             task.kernel_stack.get_top(),
             trap_handler as usize,
         );
         trap_cx.x[10] = args.len();
         trap_cx.x[11] = argv_base;
+        trap_cx.x[12] = envp_base;
         *task_inner.get_trap_cx() = trap_cx;
     }
 
@@ -166,43 +179,48 @@ impl ProcessControlBlock {
         // create child process pcb
         let child = Arc::new(Self {
             pid,
-            inner: unsafe {
-                UPIntrFreeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: Some(Arc::downgrade(self)),
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: new_fd_table,
-                    semaphore: SemaphoreFlags::empty(),
-                    signals: SignalFlags::empty(),
-                    // inherit the signal_mask and signal_action
-                    signal_mask: parent.signal_mask,
-                    handling_sig: -1,
-                    signal_actions: parent.signal_actions.clone(),
-                    killed: false,
-                    frozen: false,
-                    trap_ctx_backup: None,
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    mutex_list: Vec::new(),
-                    semaphore_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                })
-            },
+            inner: SpinNoIrqLock::new(ProcessControlBlockInner {
+                name: parent.name.clone(),
+                is_zombie: false,
+                memory_set,
+                parent: Some(Arc::downgrade(self)),
+                children: Vec::new(),
+                exit_code: 0,
+                fd_table: new_fd_table,
+                fd_flags: parent.fd_flags.clone(),
+                semaphore: SemaphoreFlags::empty(),
+                signals: SignalFlags::empty(),
+                // inherit the signal_mask and signal_action
+                signal_mask: parent.signal_mask,
+                handling_sig: -1,
+                signal_actions: parent.signal_actions.clone(),
+                killed: false,
+                stack_overflow: false,
+                heap_bottom: parent.heap_bottom,
+                program_brk: parent.program_brk,
+                frozen: false,
+                trap_ctx_backup: None,
+                tracee: None,
+                tasks: Vec::new(),
+                task_res_allocator: RecycleAllocator::new(),
+                mutex_list: Vec::new(),
+                semaphore_list: Vec::new(),
+                condvar_list: Vec::new(),
+            }),
         });
         // add child
         parent.children.push(Arc::clone(&child));
-        // create main thread of child process
+        // create main thread of child process, its stack is as the parent's
+        let (ustack_base, ustack_limit) = {
+            let parent_task = parent.get_task(0);
+            let parent_task_inner = parent_task.inner_exclusive_access();
+            let res = parent_task_inner.res.as_ref().unwrap();
+            (res.ustack_base(), res.ustack_limit)
+        };
         let task = Arc::new(TaskControlBlock::new(
             Arc::clone(&child),
-            parent
-                .get_task(0)
-                .inner_exclusive_access()
-                .res
-                .as_ref()
-                .unwrap()
-                .ustack_base(),
+            ustack_base,
+            ustack_limit,
             // here we do not allocate trap_cx or ustack again
             // but mention that we allocate a new kernel_stack here
             false,
@@ -228,13 +246,129 @@ impl ProcessControlBlock {
 }
 
 
+// keys of the auxiliary vector, as in Linux
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+/// Entries of the auxiliary vector, AT_NULL included
+const AUXV_LEN: usize = 8;
+/// Length of the AT_RANDOM bytes
+const RANDOM_LEN: usize = 16;
+
+/// Copy `bytes` and a terminating 0 below `sp`, returns their address
+fn push_bytes(token: usize, sp: &mut usize, bytes: &[u8]) -> usize {
+    *sp -= bytes.len() + 1;
+    for (i, byte) in bytes.iter().chain(core::iter::once(&0)).enumerate() {
+        *translated_refmut(token, (*sp + i) as *mut u8) = *byte;
+    }
+    *sp
+}
+
+/// Bytes for AT_RANDOM. Only as random as the timer, not for keys
+fn random_bytes() -> [u8; RANDOM_LEN] {
+    static SEED: AtomicUsize = AtomicUsize::new(0);
+    let mut x = SEED.fetch_add(1, Ordering::Relaxed) as u64 ^ get_time() as u64;
+    let mut bytes = [0u8; RANDOM_LEN];
+    for chunk in bytes.chunks_mut(8) {
+        // splitmix64
+        x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = x;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        chunk.copy_from_slice(&(z ^ (z >> 31)).to_le_bytes());
+    }
+    bytes
+}
+
+/// Bytes `init_user_stack` takes at most for `args`, `envs` and the
+/// program `elf_info` describes
+pub fn user_stack_size(args: &[String], envs: &[String], elf_info: &ElfInfo) -> usize {
+    let strings: usize = args
+        .iter()
+        .chain(envs.iter())
+        .map(|string| string.len() + 1)
+        .sum();
+    let ph_table = if elf_info.phdr != 0 {
+        0
+    } else {
+        elf_info.ph_table.len() + 7
+    };
+    // argc, argv and envp with their nulls, the auxiliary vector
+    let words = 1 + args.len() + 1 + envs.len() + 1 + 2 * AUXV_LEN;
+    strings + RANDOM_LEN + 1 + ph_table + words * core::mem::size_of::<usize>() + 15
+}
+
+/// Lay out the initial user stack as the System V ABI does. From the
+/// returned sp up: argc, the argv and then the envp pointers, each list
+/// ending with 0, the auxiliary vector, and above it the strings, the
+/// AT_RANDOM bytes and, if needed, the program headers. Returns sp and the
+/// addresses of argv and envp
+fn init_user_stack(
+    token: usize,
+    ustack_top: usize,
+    args: &[String],
+    envs: &[String],
+    elf_info: &ElfInfo,
+) -> (usize, usize, usize) {
+    let mut sp = ustack_top;
+    let argv: Vec<usize> = args
+        .iter()
+        .map(|arg| push_bytes(token, &mut sp, arg.as_bytes()))
+        .collect();
+    let envp: Vec<usize> = envs
+        .iter()
+        .map(|env| push_bytes(token, &mut sp, env.as_bytes()))
+        .collect();
+    let random = push_bytes(token, &mut sp, &random_bytes());
+    // programs that don't load their program headers get a copy
+    let phdr = if elf_info.phdr != 0 {
+        elf_info.phdr
+    } else {
+        sp = (sp - elf_info.ph_table.len()) & !0x7;
+        for (i, byte) in elf_info.ph_table.iter().enumerate() {
+            *translated_refmut(token, (sp + i) as *mut u8) = *byte;
+        }
+        sp
+    };
+    let auxv: [(usize, usize); AUXV_LEN] = [
+        (AT_PHDR, phdr),
+        (AT_PHENT, elf_info.phent),
+        (AT_PHNUM, elf_info.phnum),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, elf_info.base),
+        (AT_ENTRY, elf_info.entry),
+        (AT_RANDOM, random),
+        (AT_NULL, 0),
+    ];
+    let mut words = vec![args.len()];
+    words.extend(argv.iter().chain(core::iter::once(&0)));
+    words.extend(envp.iter().chain(core::iter::once(&0)));
+    words.extend(auxv.iter().flat_map(|(key, value)| [*key, *value]));
+    // sp is 16-byte aligned at entry
+    sp = (sp - words.len() * core::mem::size_of::<usize>()) & !0xf;
+    for (i, word) in words.iter().enumerate() {
+        *translated_refmut(token, (sp + i * core::mem::size_of::<usize>()) as *mut usize) = *word;
+    }
+    let argv_base = sp + core::mem::size_of::<usize>();
+    (sp, argv_base, argv_base + (args.len() + 1) * core::mem::size_of::<usize>())
+}
+
 pub struct ProcessControlBlockInner {
+    // the program name, argv[0] of the last exec
+    pub name: String,
     pub is_zombie: bool,
     pub memory_set: MemorySet,
     pub parent: Option<Weak<ProcessControlBlock>>,
     pub children: Vec<Arc<ProcessControlBlock>>,
     pub exit_code: i32,
     pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    // flags of each fd, always as long as fd_table
+    pub fd_flags: Vec<FdFlags>,
     pub semaphore: SemaphoreFlags,
 
     // the signal which is being handling
@@ -245,9 +379,16 @@ pub struct ProcessControlBlockInner {
     pub signal_actions: SignalActions,
     // if the task is killed
     pub killed: bool,
+    // if its SIGSEGV came from overflowing a stack
+    pub stack_overflow: bool,
+    // the heap is [heap_bottom, program_brk), brk moves its end
+    pub heap_bottom: usize,
+    pub program_brk: usize,
     // if the task is frozen by a signal
     pub frozen: bool,
     pub trap_ctx_backup: Option<TrapContext>,
+    // tracing state, if some process traces this one
+    pub tracee: Option<Tracee>,
 
 
     pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
@@ -266,13 +407,35 @@ impl ProcessControlBlockInner {
 
     pub fn alloc_fd(&mut self) -> usize {
         if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
+            self.fd_flags[fd] = FdFlags::empty();
             fd
         } else {
             self.fd_table.push(None);
+            self.fd_flags.push(FdFlags::empty());
             self.fd_table.len() - 1
         }
     }
 
+    /// Put `file` at exactly `fd`, closing whatever was there
+    pub fn install_fd(&mut self, fd: usize, file: Arc<dyn File + Send + Sync>, flags: FdFlags) {
+        if fd >= self.fd_table.len() {
+            self.fd_table.resize(fd + 1, None);
+            self.fd_flags.resize(fd + 1, FdFlags::empty());
+        }
+        self.fd_table[fd] = Some(file);
+        self.fd_flags[fd] = flags;
+    }
+
+    /// Drop every fd marked `FdFlags::CLOEXEC`
+    pub fn close_on_exec(&mut self) {
+        for fd in 0..self.fd_table.len() {
+            if self.fd_flags[fd].contains(FdFlags::CLOEXEC) {
+                self.fd_table[fd] = None;
+                self.fd_flags[fd] = FdFlags::empty();
+            }
+        }
+    }
+
     pub fn alloc_tid(&mut self) -> usize {
         self.task_res_allocator.alloc()
     }
@@ -1,10 +1,16 @@
 use super::__switch;
 use super::{fetch_task, TaskStatus};
 use super::{TaskContext, TaskControlBlock};
-use crate::sync::UPIntrFreeCell;
+use crate::config::MAX_HARTS;
+use crate::smp::hart_id;
+use crate::sync::SpinNoIrqLock;
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
+use riscv::register::sstatus;
 use crate::task::process::ProcessControlBlock;
 
 pub struct Processor {
@@ -31,13 +37,38 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: UPIntrFreeCell<Processor> = unsafe { UPIntrFreeCell::new(Processor::new()) };
+    /// One processor per hart, indexed by hart id
+    pub static ref PROCESSORS: Vec<SpinNoIrqLock<Processor>> = (0..MAX_HARTS)
+        .map(|_| SpinNoIrqLock::new(Processor::new()))
+        .collect();
 }
 
+const SWITCH_COUNTER_INIT: AtomicUsize = AtomicUsize::new(0);
+/// Number of times each hart has switched to a task
+static TASK_SWITCHES: [AtomicUsize; MAX_HARTS] = [SWITCH_COUNTER_INIT; MAX_HARTS];
+
+/// Get how many times hart `hart_id` has switched to a task
+pub fn task_switches(hart_id: usize) -> usize {
+    TASK_SWITCHES[hart_id].load(Ordering::Relaxed)
+}
+
+/// The processor of the hart running this code
+fn processor() -> &'static SpinNoIrqLock<Processor> {
+    &PROCESSORS[hart_id()]
+}
+
+/// Run tasks from the shared ready queue on this hart, every hart takes the
+/// next ready task as soon as it is free so the load spreads by itself
 pub fn run_tasks() {
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
+        let mut processor = processor().lock();
         if let Some(task) = fetch_task() {
+            // another hart may have put it back in the ready queue while
+            // still switching away from it
+            while task.on_cpu.load(Ordering::Acquire) {
+                core::hint::spin_loop();
+            }
+            task.on_cpu.store(true, Ordering::Relaxed);
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
             // access coming task TCB exclusively
             let mut task_inner = task.inner_exclusive_access();
@@ -45,24 +76,35 @@ pub fn run_tasks() {
             task_inner.task_status = TaskStatus::Running;
             drop(task_inner);
             // release coming task TCB manually
-            processor.current = Some(task);
+            processor.current = Some(task.clone());
             // release processor manually
             drop(processor);
+            TASK_SWITCHES[hart_id()].fetch_add(1, Ordering::Relaxed);
             unsafe {
+                // kernel stacks may have been remapped by another hart
+                asm!("sfence.vma");
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            // its context is saved, other harts may run it now
+            task.on_cpu.store(false, Ordering::Release);
         } else {
-            println!("no tasks available in run_tasks");
+            drop(processor);
+            // sleep until an interrupt, the timer one at the latest
+            unsafe {
+                sstatus::set_sie();
+                asm!("wfi");
+                sstatus::clear_sie();
+            }
         }
     }
 }
 
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().take_current()
+    processor().lock().take_current()
 }
 
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().current()
+    processor().lock().current()
 }
 
 pub fn current_process() -> Arc<ProcessControlBlock> {
@@ -96,7 +138,7 @@ pub fn current_kstack_top() -> usize {
 }
 
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-    let mut processor = PROCESSOR.exclusive_access();
+    let mut processor = processor().lock();
     let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
     drop(processor);
     unsafe {
@@ -0,0 +1,402 @@
+//! Process tracing, after Linux `ptrace`.
+//!
+//! A tracer attaches to one of its descendants, or a child asks its parent to
+//! trace it. initproc is never traced.
+//! The tracee then stops before a signal is delivered to it, which includes
+//! `ebreak` raising SIGTRAP, and at every syscall entry and exit when the
+//! tracer resumes it with `PTRACE_SYSCALL`. The tracer collects stops with
+//! `waitpid(pid, status, WUNTRACED)`, reads or writes the registers of the
+//! stopped thread and the memory of the process, then resumes it.
+//!
+//! Tracing is per process: a stop blocks the thread that hit it, the other
+//! threads keep running.
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::manager::{wakeup_task, PID2PCB};
+use super::process::ProcessControlBlock;
+use super::{block_current_task, current_process, current_task, current_user_token, pid2process};
+use super::{schedule, SignalFlags, TaskControlBlock, INITPROC, MAX_SIG};
+use crate::mm::page_table::{translated_refmut, PTEFlags, PageTable};
+use crate::mm::VirtAddr;
+
+pub const PTRACE_TRACEME: usize = 0;
+pub const PTRACE_PEEKDATA: usize = 2;
+pub const PTRACE_POKEDATA: usize = 5;
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_KILL: usize = 8;
+pub const PTRACE_GETREGS: usize = 12;
+pub const PTRACE_SETREGS: usize = 13;
+pub const PTRACE_ATTACH: usize = 16;
+pub const PTRACE_DETACH: usize = 17;
+pub const PTRACE_SYSCALL: usize = 24;
+
+/// `waitpid` option to report the stops of tracees
+pub const WUNTRACED: usize = 2;
+
+const SIGTRAP: usize = 5;
+const SIGKILL: usize = 9;
+const SIGSTOP: usize = 19;
+/// Signal reported by syscall stops, as with `PTRACE_O_TRACESYSGOOD`
+const SYSCALL_STOP: usize = SIGTRAP | 0x80;
+
+/// A thread of a tracee stopped for its tracer
+pub struct TraceStop {
+    pub task: Arc<TaskControlBlock>,
+    /// The signal reported, or `SYSCALL_STOP`
+    pub signal: usize,
+    /// Whether `waitpid` has reported the stop
+    pub reported: bool,
+}
+
+/// Tracing state of a traced process
+pub struct Tracee {
+    pub tracer: usize,
+    pub stop: Option<TraceStop>,
+    /// Stop at the next syscall entry and exit
+    pub syscall_stops: bool,
+    /// Stop with SIGSTOP on the next return to user space
+    pub attach_stop: bool,
+    /// Signal the tracer resumed with, to be delivered
+    pub resume_signal: usize,
+    /// Signals the tracer let through, delivered without another stop
+    pub passed: SignalFlags,
+}
+
+impl Tracee {
+    fn new(tracer: usize) -> Self {
+        Self {
+            tracer,
+            stop: None,
+            syscall_stops: false,
+            attach_stop: false,
+            resume_signal: 0,
+            passed: SignalFlags::empty(),
+        }
+    }
+}
+
+/// Stop the current thread for the tracer until it resumes it, then queue
+/// the signal it resumed with
+fn stop(signal: usize) {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let tracee = match inner.tracee.as_mut() {
+        Some(tracee) => tracee,
+        None => return,
+    };
+    tracee.stop = Some(TraceStop {
+        task: current_task().unwrap(),
+        signal,
+        reported: false,
+    });
+    // blocked before the tracer can see the stop and wake us up
+    let task_cx_ptr = block_current_task();
+    drop(inner);
+    schedule(task_cx_ptr);
+
+    let mut inner = process.inner_exclusive_access();
+    let inner = &mut *inner;
+    if let Some(tracee) = inner.tracee.as_mut() {
+        let resume_signal = core::mem::take(&mut tracee.resume_signal);
+        if resume_signal != 0 {
+            let flag = SignalFlags::from_bits(1 << resume_signal).unwrap();
+            tracee.passed |= flag;
+            inner.signals |= flag;
+        }
+    }
+}
+
+/// Syscall entry or exit stop, if the tracer asked for them
+pub fn syscall_stop() {
+    let process = current_process();
+    let syscall_stops = process
+        .inner_exclusive_access()
+        .tracee
+        .as_ref()
+        .map_or(false, |tracee| tracee.syscall_stops);
+    drop(process);
+    if syscall_stops {
+        stop(SYSCALL_STOP);
+    }
+}
+
+/// Let the tracer intercept pending signals before they are delivered.
+/// SIGKILL can't be intercepted
+pub fn trace_signals() {
+    loop {
+        let process = current_process();
+        let mut guard = process.inner_exclusive_access();
+        let inner = &mut *guard;
+        let pending = inner.signals - inner.signal_mask;
+        let tracee = match inner.tracee.as_mut() {
+            Some(tracee) if !pending.contains(SignalFlags::SIGKILL) => tracee,
+            _ => return,
+        };
+        tracee.passed &= inner.signals;
+        let passed = tracee.passed;
+        let signal = if core::mem::take(&mut tracee.attach_stop) {
+            SIGSTOP
+        } else {
+            let found = (1..=MAX_SIG).filter(|sig| *sig != SIGKILL).find(|sig| {
+                let flag = SignalFlags::from_bits(1 << sig).unwrap();
+                pending.contains(flag) && !passed.contains(flag)
+            });
+            match found {
+                Some(sig) => {
+                    inner
+                        .signals
+                        .remove(SignalFlags::from_bits(1 << sig).unwrap());
+                    sig
+                }
+                None => return,
+            }
+        };
+        drop(guard);
+        stop(signal);
+    }
+}
+
+/// Report the first unreported stop of a tracee of `tracer` matching `pid`,
+/// -1 for any. Returns its pid and a `waitpid` status
+pub fn wait_stop(tracer: usize, pid: isize) -> Option<(usize, i32)> {
+    let processes: Vec<_> = PID2PCB.lock().values().cloned().collect();
+    for process in processes {
+        if pid != -1 && pid as usize != process.getpid() {
+            continue;
+        }
+        let mut inner = process.inner_exclusive_access();
+        let stop = match inner.tracee.as_mut() {
+            Some(tracee) if tracee.tracer == tracer => tracee.stop.as_mut(),
+            _ => None,
+        };
+        if let Some(stop) = stop.filter(|stop| !stop.reported) {
+            stop.reported = true;
+            return Some((process.getpid(), ((stop.signal as i32) << 8) | 0x7f));
+        }
+    }
+    None
+}
+
+/// Whether `tracer` traces the live process `pid`
+pub fn is_tracing(tracer: usize, pid: usize) -> bool {
+    pid2process(pid).map_or(false, |process| {
+        matches!(&process.inner_exclusive_access().tracee, Some(tracee) if tracee.tracer == tracer)
+    })
+}
+
+/// Wake a stopped tracee so that it dies of a pending SIGKILL
+pub fn wake_killed(process: &Arc<ProcessControlBlock>) {
+    let stop = process
+        .inner_exclusive_access()
+        .tracee
+        .as_mut()
+        .and_then(|tracee| tracee.stop.take());
+    if let Some(stop) = stop {
+        wakeup_task(stop.task);
+    }
+}
+
+/// Detach the tracees of an exiting tracer
+pub fn release_tracees(tracer: usize) {
+    let processes: Vec<_> = PID2PCB.lock().values().cloned().collect();
+    for process in processes {
+        let mut inner = process.inner_exclusive_access();
+        if matches!(&inner.tracee, Some(tracee) if tracee.tracer == tracer) {
+            let stop = inner.tracee.take().unwrap().stop;
+            drop(inner);
+            if let Some(stop) = stop {
+                wakeup_task(stop.task);
+            }
+        }
+    }
+}
+
+/// The byte at `addr` in a user address space, whatever the permissions
+/// of its page, so that breakpoints can be written into code
+fn user_byte(page_table: &PageTable, addr: usize) -> Option<&'static mut u8> {
+    let va = VirtAddr::from(addr);
+    page_table
+        .translate(va.floor())
+        .filter(|pte| pte.is_valid() && pte.flags().contains(PTEFlags::U))?;
+    page_table.translate_va(va).map(|pa| pa.get_mut())
+}
+
+/// Resume a stopped tracee, delivering `signal` unless it is 0
+fn resume(process: &Arc<ProcessControlBlock>, signal: usize, syscall_stops: bool) -> isize {
+    if signal > MAX_SIG {
+        return -1;
+    }
+    let mut inner = process.inner_exclusive_access();
+    let tracee = inner.tracee.as_mut().unwrap();
+    let stop = match tracee.stop.take() {
+        Some(stop) => stop,
+        None => return -1,
+    };
+    tracee.syscall_stops = syscall_stops;
+    tracee.resume_signal = signal;
+    drop(inner);
+    wakeup_task(stop.task);
+    0
+}
+
+/// Whether `tracer_pid` may trace `process`: only its own descendants can
+/// be traced, and never initproc
+fn may_trace(tracer_pid: usize, process: &Arc<ProcessControlBlock>) -> bool {
+    if Arc::ptr_eq(process, &INITPROC) {
+        return false;
+    }
+    let parent_of = |process: &Arc<ProcessControlBlock>| {
+        let inner = process.inner_exclusive_access();
+        inner.parent.as_ref().and_then(|parent| parent.upgrade())
+    };
+    let mut ancestor = parent_of(process);
+    while let Some(process) = ancestor {
+        if process.getpid() == tracer_pid {
+            return true;
+        }
+        ancestor = parent_of(&process);
+    }
+    false
+}
+
+/// Trace a process. Registers are 32 words, the pc first and then x1 to
+/// x31. Peeked words and registers are written to `data`. Returns -1 on
+/// errors, including on requests for a tracee that isn't stopped
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    let tracer = current_process();
+    let tracer_pid = tracer.getpid();
+    match request {
+        PTRACE_TRACEME => {
+            let parent = tracer
+                .inner_exclusive_access()
+                .parent
+                .as_ref()
+                .and_then(|parent| parent.upgrade());
+            let parent_pid = match parent {
+                Some(parent) if may_trace(parent.getpid(), &tracer) => parent.getpid(),
+                _ => return -1,
+            };
+            let mut inner = tracer.inner_exclusive_access();
+            if inner.tracee.is_some() {
+                return -1;
+            }
+            inner.tracee = Some(Tracee::new(parent_pid));
+            0
+        }
+        PTRACE_ATTACH => {
+            let process = match pid2process(pid) {
+                Some(process) if may_trace(tracer_pid, &process) => process,
+                _ => return -1,
+            };
+            // a tracer can't trace itself through its tracee
+            if matches!(&tracer.inner_exclusive_access().tracee, Some(tracee) if tracee.tracer == pid)
+            {
+                return -1;
+            }
+            let mut inner = process.inner_exclusive_access();
+            if inner.tracee.is_some() || inner.is_zombie {
+                return -1;
+            }
+            let mut tracee = Tracee::new(tracer_pid);
+            tracee.attach_stop = true;
+            inner.tracee = Some(tracee);
+            0
+        }
+        _ => {
+            drop(tracer);
+            let process = match pid2process(pid) {
+                Some(process) if is_tracing(tracer_pid, pid) => process,
+                _ => return -1,
+            };
+            traced_request(&process, request, addr, data)
+        }
+    }
+}
+
+/// Requests on a process the caller traces
+fn traced_request(
+    process: &Arc<ProcessControlBlock>,
+    request: usize,
+    addr: usize,
+    data: usize,
+) -> isize {
+    let token = current_user_token();
+    match request {
+        PTRACE_CONT => resume(process, data, false),
+        PTRACE_SYSCALL => resume(process, data, true),
+        PTRACE_KILL => {
+            process.inner_exclusive_access().signals |= SignalFlags::SIGKILL;
+            wake_killed(process);
+            0
+        }
+        PTRACE_DETACH => {
+            let tracee = process.inner_exclusive_access().tracee.take().unwrap();
+            if let Some(stop) = tracee.stop {
+                if data != 0 && data <= MAX_SIG {
+                    process.inner_exclusive_access().signals |=
+                        SignalFlags::from_bits(1 << data).unwrap();
+                }
+                wakeup_task(stop.task);
+            }
+            0
+        }
+        PTRACE_PEEKDATA | PTRACE_POKEDATA => {
+            let page_table =
+                PageTable::from_token(process.inner_exclusive_access().get_user_token());
+            let mut bytes = Vec::new();
+            for offset in 0..core::mem::size_of::<usize>() {
+                let byte = addr
+                    .checked_add(offset)
+                    .and_then(|va| user_byte(&page_table, va));
+                match byte {
+                    Some(byte) => bytes.push(byte),
+                    None => return -1,
+                }
+            }
+            if request == PTRACE_PEEKDATA {
+                let mut word = 0;
+                for (i, byte) in bytes.iter().enumerate() {
+                    word |= (**byte as usize) << (i * 8);
+                }
+                *translated_refmut(token, data as *mut usize) = word;
+            } else {
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    *byte = (data >> (i * 8)) as u8;
+                }
+            }
+            0
+        }
+        PTRACE_GETREGS | PTRACE_SETREGS => {
+            let inner = process.inner_exclusive_access();
+            let task = match inner
+                .tracee
+                .as_ref()
+                .and_then(|tracee| tracee.stop.as_ref())
+            {
+                Some(stop) => Arc::clone(&stop.task),
+                None => return -1,
+            };
+            drop(inner);
+            let trap_cx = task.inner_exclusive_access().get_trap_cx();
+            for i in 0..32 {
+                let reg = translated_refmut(
+                    token,
+                    (data + i * core::mem::size_of::<usize>()) as *mut usize,
+                );
+                let target = if i == 0 {
+                    &mut trap_cx.sepc
+                } else {
+                    &mut trap_cx.x[i]
+                };
+                if request == PTRACE_GETREGS {
+                    *reg = *target;
+                } else {
+                    *target = *reg;
+                }
+            }
+            0
+        }
+        _ => -1,
+    }
+}
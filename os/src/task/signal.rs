@@ -45,6 +45,8 @@ impl SignalFlags {
             Some((-2, "Killed, SIGINT=2"))
         } else if self.contains(Self::SIGILL) {
             Some((-4, "Illegal Instruction, SIGILL=4"))
+        } else if self.contains(Self::SIGTRAP) {
+            Some((-5, "Trace/breakpoint trap, SIGTRAP=5"))
         } else if self.contains(Self::SIGABRT) {
             Some((-6, "Aborted, SIGABRT=6"))
         } else if self.contains(Self::SIGFPE) {
@@ -53,6 +55,8 @@ impl SignalFlags {
             Some((-9, "Killed, SIGKILL=9"))
         } else if self.contains(Self::SIGSEGV) {
             Some((-11, "Segmentation Fault, SIGSEGV=11"))
+        } else if self.contains(Self::SIGPIPE) {
+            Some((-13, "Broken Pipe, SIGPIPE=13"))
         } else {
             //println!("[K] signalflags check_error  {:?}", self);
             None
@@ -7,9 +7,10 @@ use alloc::sync::{Arc, Weak};
 
 use alloc::vec::Vec;
 use core::cell::RefMut;
+use core::sync::atomic::AtomicBool;
 use crate::fs::{File, Stdin, Stdout};
 use crate::mm::page_table::translated_refmut;
-use crate::sync::{UPIntrFreeCell, UPIntrRefMut};
+use crate::sync::{SpinNoIrqLock, SpinNoIrqGuard};
 use crate::task::action::SignalActions;
 use crate::task::id::{KernelStack, kstack_alloc, pid_alloc, PidHandle, TaskUserRes};
 use crate::task::process::ProcessControlBlock;
@@ -22,8 +23,10 @@ pub struct TaskControlBlock {
     pub process: Weak<ProcessControlBlock>,
     /// Kernel stack corresponding to PID
     pub kernel_stack: KernelStack,
+    /// Set while a hart runs the task or is still saving its context
+    pub on_cpu: AtomicBool,
     // mutable
-    inner: UPIntrFreeCell<TaskControlBlockInner>,
+    inner: SpinNoIrqLock<TaskControlBlockInner>,
 }
 
 pub struct TaskControlBlockInner {
@@ -31,6 +34,9 @@ pub struct TaskControlBlockInner {
     pub trap_cx_ppn: PhysPageNum,
     pub task_cx: TaskContext,
     pub task_status: TaskStatus,
+    /// a wakeup came while the task was still running towards a block,
+    /// the block is skipped then
+    pub wakeup_pending: bool,
     pub exit_code: Option<i32>,
 }
 
@@ -49,29 +55,30 @@ impl TaskControlBlock {
     pub fn new(
         process: Arc<ProcessControlBlock>,
         ustack_base: usize,
+        ustack_limit: usize,
         alloc_user_res: bool,
     ) -> Self {
-        let res = TaskUserRes::new(Arc::clone(&process), ustack_base, alloc_user_res);
+        let res = TaskUserRes::new(Arc::clone(&process), ustack_base, ustack_limit, alloc_user_res);
         let trap_cx_ppn = res.trap_cx_ppn();
         let kernel_stack = kstack_alloc();
         let kstack_top = kernel_stack.get_top();
         Self {
             process: Arc::downgrade(&process),
             kernel_stack,
-            inner: unsafe {
-                UPIntrFreeCell::new(TaskControlBlockInner {
-                    res: Some(res),
-                    trap_cx_ppn,
-                    task_cx: TaskContext::goto_trap_return(kstack_top),
-                    task_status: TaskStatus::Ready,
-                    exit_code: None,
-                })
-            },
+            on_cpu: AtomicBool::new(false),
+            inner: SpinNoIrqLock::new(TaskControlBlockInner {
+                res: Some(res),
+                trap_cx_ppn,
+                task_cx: TaskContext::goto_trap_return(kstack_top),
+                task_status: TaskStatus::Ready,
+                wakeup_pending: false,
+                exit_code: None,
+            }),
         }
     }
 
-    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, TaskControlBlockInner> {
-        self.inner.exclusive_access()
+    pub fn inner_exclusive_access(&self) -> SpinNoIrqGuard<'_, TaskControlBlockInner> {
+        self.inner.lock()
     }
 
     pub fn get_user_token(&self) -> usize {
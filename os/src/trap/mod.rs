@@ -1,7 +1,11 @@
 use core::arch::global_asm;
 use log::error;
 use crate::syscall::syscall;
-use crate::task::{check_signals_error_of_current, current_add_signal, current_trap_cx, current_user_token, exit_current_and_run_next, handle_signals, SignalFlags, suspend_current_and_run_next};
+use crate::task::{check_signals_error_of_current, current_add_signal, current_page_fault, current_trap_cx, current_user_token, exit_current_and_run_next, handle_signals, SignalFlags, suspend_current_and_run_next};
+use crate::task::ptrace::{syscall_stop, trace_signals};
+use crate::net::{loopback_interrupt_handler, net_timer_handler};
+use crate::smp::{enter_kernel, hart_id, leave_kernel};
+use crate::backtrace::Symbolized;
 use crate::timer::{check_timer, set_next_trigger};
 use riscv::register::{mtvec::TrapMode, scause::{self, Exception, Interrupt, Trap}, sie, sscratch, sstatus, stval, stvec};
 
@@ -54,6 +58,7 @@ fn disable_supervisor_interrupt() {
 /// handle an interrupt, exception, or system call from user space]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    enter_kernel();
     let scause = scause::read();
     let stval = stval::read();
     match scause.cause() {
@@ -62,18 +67,26 @@ pub fn trap_handler() -> ! {
             let mut cx = current_trap_cx();
             cx.sepc += 4;
             enable_supervisor_interrupt();
+            // a tracer may change the arguments at the entry stop
+            syscall_stop();
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12],cx.x[13]]);
+            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]]);
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
+            syscall_stop();
+        }
+        Trap::Exception(Exception::Breakpoint) => {
+            current_add_signal(SignalFlags::SIGTRAP);
+        }
+        // below a stack, it may grow
+        Trap::Exception(Exception::StorePageFault) | Trap::Exception(Exception::LoadPageFault) => {
+            current_page_fault(stval);
         }
         Trap::Exception(Exception::StoreFault)
-        | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionFault)
         | Trap::Exception(Exception::InstructionPageFault)
-        | Trap::Exception(Exception::LoadFault)
-        | Trap::Exception(Exception::LoadPageFault) => {
+        | Trap::Exception(Exception::LoadFault) => {
             /*
             println!(
                 "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.",
@@ -90,11 +103,15 @@ pub fn trap_handler() -> ! {
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
             check_timer();
+            net_timer_handler();
             suspend_current_and_run_next();
         }
         Trap::Interrupt(Interrupt::SupervisorExternal) => {
             crate::board::irq_handler();
         }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            loopback_interrupt_handler();
+        }
         _ => {
             panic!(
                 "Unsupported trap {:?}, stval = {:#x}!",
@@ -103,6 +120,8 @@ pub fn trap_handler() -> ! {
             );
         }
     }
+    // a tracer sees the signals first
+    trace_signals();
     // handle signals (handle the sent signal)
     // println!("[K] trap_handler:: handle_signals");
     handle_signals();
@@ -119,8 +138,11 @@ pub fn trap_handler() -> ! {
 pub fn trap_return() -> ! {
     disable_supervisor_interrupt();
     set_user_trap_entry();
+    // the task may come back on another hart
+    current_trap_cx().hart_id = hart_id();
     let trap_cx_ptr = current_trap_cx_user_va();
     let user_satp = current_user_token();
+    leave_kernel();
     extern "C" {
         fn __alltraps();
         fn __restore();
@@ -140,7 +162,7 @@ pub fn trap_return() -> ! {
 
 
 #[no_mangle]
-pub fn trap_from_kernel(_trap_cx: &TrapContext) {
+pub fn trap_from_kernel(trap_cx: &TrapContext) {
     let scause = scause::read();
     let stval = stval::read();
     match scause.cause() {
@@ -150,13 +172,18 @@ pub fn trap_from_kernel(_trap_cx: &TrapContext) {
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
             check_timer();
+            net_timer_handler();
             // do not schedule now
         }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            loopback_interrupt_handler();
+        }
         _ => {
             panic!(
-                "Unsupported trap from kernel: {:?}, stval = {:#x}!",
+                "Unsupported trap from kernel: {:?}, stval = {:#x}, sepc = {}!",
                 scause.cause(),
-                stval
+                stval,
+                Symbolized(trap_cx.sepc)
             );
         }
     }
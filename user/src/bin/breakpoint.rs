@@ -0,0 +1,17 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+
+#[no_mangle]
+fn main() -> i32 {
+    println!("Hit a breakpoint without a tracer");
+    println!("Kernel should kill this application!");
+    unsafe {
+        asm!("ebreak");
+    }
+    0
+}
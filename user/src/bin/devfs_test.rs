@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, write, OpenFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/dev/null\0", OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"discarded"), 9);
+    close(fd);
+
+    let fd = open("/dev/null\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut buffer = [0xffu8; 16];
+    assert_eq!(read(fd, &mut buffer), 0);
+    close(fd);
+
+    let fd = open("/dev/zero\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(read(fd, &mut buffer), 16);
+    assert!(buffer.iter().all(|&b| b == 0));
+    close(fd);
+
+    let fd = open("/dev/tty\0", OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, b"hello from /dev/tty\n");
+    close(fd);
+
+    assert!(open("/dev/nonexistent\0", OpenFlags::RDONLY) < 0);
+    println!("devfs_test passed!");
+    0
+}
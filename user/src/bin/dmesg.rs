@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    klogctl, SYSLOG_ACTION_CLEAR, SYSLOG_ACTION_READ_ALL, SYSLOG_ACTION_READ_CLEAR,
+    SYSLOG_ACTION_SIZE_BUFFER,
+};
+
+/// As large as the kernel log, too large for the stack or heap
+const LOG_SIZE: usize = 64 * 1024;
+static mut LOG: [u8; LOG_SIZE] = [0; LOG_SIZE];
+
+fn usage() -> i32 {
+    println!("usage: dmesg [-c | -C] [-l error|warn|info|debug|trace]");
+    -1
+}
+
+/// Print the kernel log. `-c` clears it after printing, `-C` only clears
+/// it, `-l` keeps the records of one level
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let mut action = SYSLOG_ACTION_READ_ALL;
+    let mut level = None;
+    let mut i = 1;
+    while i < argc {
+        match argv[i] {
+            "-c" => action = SYSLOG_ACTION_READ_CLEAR,
+            "-C" => action = SYSLOG_ACTION_CLEAR,
+            "-l" if i + 1 < argc => {
+                i += 1;
+                level = Some(argv[i]);
+            }
+            _ => return usage(),
+        }
+        i += 1;
+    }
+    let buf = unsafe { &mut LOG[..] };
+    if klogctl(SYSLOG_ACTION_SIZE_BUFFER, buf) as usize > LOG_SIZE {
+        println!("dmesg: the kernel log is larger than {} bytes", LOG_SIZE);
+    }
+    let len = klogctl(action, buf);
+    if len < 0 {
+        println!("dmesg: can't read the kernel log");
+        return -1;
+    }
+    let log = core::str::from_utf8(&buf[..len as usize]).unwrap_or("");
+    for line in log.lines() {
+        // records look like `[   1.234] [ INFO] message`
+        let record_level = line.split(']').nth(1).unwrap_or("");
+        let record_level = record_level.trim_start_matches(|c| c == ' ' || c == '[');
+        if level.map_or(true, |level| record_level.eq_ignore_ascii_case(level)) {
+            println!("{}", line);
+        }
+    }
+    0
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, dup2, dup3, exec, fcntl, fork, pipe, read, wait, write, OpenFlags, FD_CLOEXEC, F_GETFD,
+    F_SETFD,
+};
+
+/// Kept open across exec
+const KEPT_FD: usize = 10;
+/// Marked close-on-exec
+const CLOEXEC_FD: usize = 11;
+
+#[no_mangle]
+pub fn main(argc: usize, _argv: &[&str]) -> i32 {
+    if argc > 1 {
+        // running again after exec
+        assert!(fcntl(KEPT_FD, F_GETFD, 0) >= 0);
+        assert_eq!(fcntl(CLOEXEC_FD, F_GETFD, 0), -1);
+        write(KEPT_FD, b"ok");
+        return 0;
+    }
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    // dup2 picks the target fd, growing the table as needed
+    assert_eq!(dup2(pipe_fd[1], KEPT_FD), KEPT_FD as isize);
+    assert_eq!(dup3(pipe_fd[1], CLOEXEC_FD, OpenFlags::CLOEXEC), CLOEXEC_FD as isize);
+    assert_eq!(fcntl(KEPT_FD, F_GETFD, 0), 0);
+    assert_eq!(fcntl(CLOEXEC_FD, F_GETFD, 0), FD_CLOEXEC as isize);
+    // dup2 onto itself is a no-op, onto a closed source fails
+    assert_eq!(dup2(KEPT_FD, KEPT_FD), KEPT_FD as isize);
+    assert_eq!(dup2(42, KEPT_FD), -1);
+    // the flag can be cleared and set again
+    assert_eq!(fcntl(CLOEXEC_FD, F_SETFD, 0), 0);
+    assert_eq!(fcntl(CLOEXEC_FD, F_GETFD, 0), 0);
+    assert_eq!(fcntl(CLOEXEC_FD, F_SETFD, FD_CLOEXEC), 0);
+    if fork() == 0 {
+        close(pipe_fd[0]);
+        close(pipe_fd[1]);
+        exec("dup_test\0", &["dup_test\0".as_ptr(), "child\0".as_ptr(), core::ptr::null()]);
+        panic!("exec failed");
+    }
+    close(pipe_fd[1]);
+    close(KEPT_FD);
+    close(CLOEXEC_FD);
+    let mut buffer = [0u8; 2];
+    assert_eq!(read(pipe_fd[0], &mut buffer), 2);
+    assert_eq!(&buffer, b"ok");
+    let mut exit_code: i32 = 0;
+    wait(&mut exit_code);
+    assert_eq!(exit_code, 0);
+    close(pipe_fd[0]);
+    println!("dup_test passed!");
+    0
+}
@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::env::{self, AT_BASE, AT_ENTRY, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM};
+use user_lib::{exec, execve, exit, fork, waitpid};
+
+const PT_LOAD: u32 = 1;
+
+/// Run this program again as `env_test MODE`, with `execve` if `envp` is
+/// given, else with `exec`, and check it succeeds
+fn run(mode: &str, envp: Option<&[*const u8]>) {
+    let pid = fork();
+    if pid == 0 {
+        let args = ["env_test\0".as_ptr(), mode.as_ptr(), core::ptr::null()];
+        match envp {
+            Some(envp) => execve("env_test\0", &args, envp),
+            None => exec("env_test\0", &args),
+        };
+        exit(-1);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(
+        exit_code,
+        0,
+        "env_test {} failed",
+        mode.trim_end_matches('\0')
+    );
+}
+
+fn check_auxv() {
+    assert_eq!(env::getauxval(AT_PAGESZ), Some(4096));
+    assert_eq!(env::getauxval(AT_ENTRY), Some(user_lib::_start as usize));
+    // the program headers include the segment with the entry point
+    let phdr = env::getauxval(AT_PHDR).expect("no AT_PHDR");
+    let phent = env::getauxval(AT_PHENT).unwrap();
+    let phnum = env::getauxval(AT_PHNUM).unwrap();
+    let entry = user_lib::_start as usize;
+    // addresses in the headers are as linked, a PIE is moved by AT_BASE
+    let base = env::getauxval(AT_BASE).expect("no AT_BASE");
+    let loads_entry = (0..phnum).any(|i| {
+        let ph = phdr + i * phent;
+        let p_type = unsafe { (ph as *const u32).read_volatile() };
+        let vaddr = base + unsafe { ((ph + 16) as *const usize).read_volatile() };
+        let memsz = unsafe { ((ph + 40) as *const usize).read_volatile() };
+        p_type == PT_LOAD && (vaddr..vaddr + memsz).contains(&entry)
+    });
+    assert!(loads_entry, "AT_PHDR doesn't point to the program headers");
+    let random = env::getauxval(AT_RANDOM).expect("no AT_RANDOM");
+    let bytes = unsafe { core::slice::from_raw_parts(random as *const u8, 16) };
+    assert!(bytes.iter().any(|byte| *byte != 0));
+}
+
+/// Check `exec` passes on the environment, `execve` replaces it, and the
+/// auxiliary vector describes the program
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    match argv.get(1).copied() {
+        Some("inherited") => {
+            assert_eq!(env::var("INHERITED").as_deref(), Some("yes"));
+            assert_eq!(env::var("REMOVED"), None);
+            check_auxv();
+            return 0;
+        }
+        Some("replaced") => {
+            assert_eq!(env::var("GREETING").as_deref(), Some("hello world"));
+            assert_eq!(env::var("EMPTY").as_deref(), Some(""));
+            assert_eq!(env::var("INHERITED"), None);
+            assert_eq!(env::vars().len(), 2);
+            check_auxv();
+            return 0;
+        }
+        _ => assert_eq!(argc, 1),
+    }
+    env::set_var("INHERITED", "no");
+    env::set_var("INHERITED", "yes");
+    env::set_var("REMOVED", "soon");
+    env::remove_var("REMOVED");
+    run("inherited\0", None);
+    let envp = [
+        "GREETING=hello world\0".as_ptr(),
+        "EMPTY=\0".as_ptr(),
+        core::ptr::null(),
+    ];
+    run("replaced\0", Some(&envp));
+    println!("env_test passed!");
+    0
+}
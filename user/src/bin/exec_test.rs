@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exec, exit, fork, open, read, waitpid, write, OpenFlags};
+
+const ENOEXEC: isize = 8;
+/// Offset of `e_machine` in the ELF header
+const E_MACHINE: usize = 18;
+const EM_X86_64: u8 = 0x3e;
+/// Offsets of `e_phoff`, `e_phentsize` and `e_phnum` in the ELF header
+const E_PHOFF: usize = 32;
+const E_PHENTSIZE: usize = 54;
+const E_PHNUM: usize = 56;
+const PT_LOAD: u64 = 1;
+const PF_W: u64 = 2;
+/// More memory than the machine has, but still inside user space
+const HUGE_MEMSZ: u64 = 1 << 36;
+
+static mut HEAD: [u8; 4096] = [0; 4096];
+
+fn write_file(path: &str, data: &[u8]) {
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd >= 0, "can't create {}", path);
+    assert_eq!(write(fd as usize, data), data.len() as isize);
+    close(fd as usize);
+}
+
+fn get(bytes: &[u8], offset: usize, len: usize) -> u64 {
+    bytes[offset..offset + len]
+        .iter()
+        .rev()
+        .fold(0, |value, byte| value << 8 | *byte as u64)
+}
+
+fn set(bytes: &mut [u8], offset: usize, len: usize, value: u64) {
+    for (i, byte) in bytes[offset..offset + len].iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+}
+
+/// Drop the file contents of the loadable segments in `head` so it loads on
+/// its own, and make the writable one need `HUGE_MEMSZ` bytes of memory
+fn make_huge(head: &mut [u8]) {
+    let ph_offset = get(head, E_PHOFF, 8) as usize;
+    let ph_entry_size = get(head, E_PHENTSIZE, 2) as usize;
+    for i in 0..get(head, E_PHNUM, 2) as usize {
+        let ph = &mut head[ph_offset + i * ph_entry_size..][..ph_entry_size];
+        if get(ph, 0, 4) != PT_LOAD {
+            continue;
+        }
+        let align = get(ph, 48, 8).max(1);
+        let vaddr = get(ph, 16, 8);
+        // p_offset, p_filesz
+        set(ph, 8, 8, vaddr % align);
+        set(ph, 32, 8, 0);
+        if get(ph, 4, 4) & PF_W != 0 {
+            // p_memsz
+            set(ph, 40, 8, HUGE_MEMSZ);
+        }
+    }
+}
+
+fn exec_file(path: &str) -> isize {
+    exec(path, &[path.as_ptr(), core::ptr::null()])
+}
+
+/// Check that broken executables fail to exec instead of bringing the
+/// kernel down, and that `#!` scripts run through their interpreter
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    // running as the interpreter of exec_script
+    if argv.get(1) == Some(&"--interp") {
+        assert_eq!(argc, 4);
+        assert_eq!(argv[0], "/exec_test");
+        assert_eq!(argv[2], "exec_script");
+        assert_eq!(argv[3], "x");
+        return 0;
+    }
+
+    write_file("exec_garbage\0", b"this is not a program\n");
+    assert_eq!(exec_file("exec_garbage\0"), -ENOEXEC);
+
+    // the header of this very program, without its segments
+    let head = unsafe { &mut *core::ptr::addr_of_mut!(HEAD) };
+    let fd = open("exec_test\0", OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    assert_eq!(read(fd as usize, head), head.len() as isize);
+    close(fd as usize);
+    write_file("exec_truncated\0", head);
+    assert_eq!(exec_file("exec_truncated\0"), -ENOEXEC);
+    // a segment larger than the free memory is refused, not a kernel panic
+    let mut huge = *head;
+    make_huge(&mut huge);
+    write_file("exec_huge\0", &huge);
+    assert_eq!(exec_file("exec_huge\0"), -ENOEXEC);
+    head[E_MACHINE] = EM_X86_64;
+    write_file("exec_x86\0", head);
+    assert_eq!(exec_file("exec_x86\0"), -ENOEXEC);
+
+    write_file("exec_no_interp\0", b"#!/no_such_interpreter\n");
+    assert_eq!(exec_file("exec_no_interp\0"), -1);
+
+    write_file("exec_script\0", b"#!/exec_test --interp\nignored\n");
+    let pid = fork();
+    if pid == 0 {
+        exec(
+            "exec_script\0",
+            &["exec_script\0".as_ptr(), "x\0".as_ptr(), core::ptr::null()],
+        );
+        exit(-1);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(
+        exit_code, 0,
+        "the script didn't run through its interpreter"
+    );
+    println!("exec_test passed!");
+    0
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fork, mkfifo, open, read, wait, write, OpenFlags};
+
+static FIFO: &str = "fifo_test_pipe\0";
+static STR: &str = "Hello through a named pipe!";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // the fifo persists in easy-fs, so it may be left over from an earlier run
+    mkfifo(FIFO);
+    if fork() == 0 {
+        // child process, the opener of the write end
+        let fd = open(FIFO, OpenFlags::WRONLY);
+        assert!(fd >= 0);
+        let fd = fd as usize;
+        write(fd, STR.as_bytes());
+        close(fd);
+        0
+    } else {
+        // parent process, blocks until the child has opened the write end
+        let fd = open(FIFO, OpenFlags::RDONLY);
+        assert!(fd >= 0);
+        let fd = fd as usize;
+        let mut buffer = [0u8; 64];
+        let mut len_read = 0usize;
+        loop {
+            let len = read(fd, &mut buffer[len_read..]) as usize;
+            if len == 0 {
+                break;
+            }
+            len_read += len;
+        }
+        close(fd);
+        assert_eq!(core::str::from_utf8(&buffer[..len_read]).unwrap(), STR);
+        let mut child_exit_code: i32 = 0;
+        wait(&mut child_exit_code);
+        assert_eq!(child_exit_code, 0);
+        println!("fifo_test passed!");
+        0
+    }
+}
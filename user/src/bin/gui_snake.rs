@@ -6,7 +6,7 @@ extern crate user_lib;
 extern crate alloc;
 
 use user_lib::console::{getchar, print};
-use user_lib::{framebuffer, framebuffer_flush, key_pressed, sleep};
+use user_lib::{framebuffer, framebuffer_flush, get_time, poll, PollEvents, PollFd};
 
 use embedded_graphics::pixelcolor::*;
 use embedded_graphics::prelude::{Drawable, Point, RgbColor, Size};
@@ -378,13 +378,20 @@ mod tests {
 
 const LF: u8 = 0x0au8;
 const CR: u8 = 0x0du8;
+const STDIN: usize = 0;
+/// Time between two moves of the snake
+const TICK_MS: isize = 130;
 #[no_mangle]
 pub fn main() -> i32 {
     let mut disp = Display::new(Size::new(1280, 800), Point::new(0, 0));
     let mut game = SnakeGame::<20, Rgb888>::new(1280, 800, 20, 20, Rgb888::RED, Rgb888::YELLOW, 5000);
     let _ = disp.clear(Rgb888::BLACK).unwrap();
+    let mut next_tick = get_time() + TICK_MS;
     loop {
-        if key_pressed() {
+        // sleep in poll until a key arrives or the next tick is due
+        let mut fds = [PollFd::new(STDIN, PollEvents::IN)];
+        let timeout = (next_tick - get_time()).max(0);
+        if poll(&mut fds, timeout) > 0 && fds[0].revents.contains(PollEvents::IN) {
             let c = getchar();
             match c {
                 LF => break,
@@ -395,11 +402,12 @@ pub fn main() -> i32 {
                 b'd' => game.set_direction(Direction::Right),
                 _ => (),
             }
+            continue;
         }
+        next_tick = get_time() + TICK_MS;
         println!("snake tik");
         let _ = disp.clear(Rgb888::BLACK).unwrap();
         game.draw(&mut disp);
-        sleep(130);
     }
     0
 }
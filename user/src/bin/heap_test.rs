@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{brk, exit, fork, sbrk, waitpid};
+
+const PAGE_SIZE: usize = 4096;
+/// Past the largest heap the kernel gives
+const TOO_BIG: usize = 1 << 30;
+const N: usize = 1 << 19;
+
+/// Check `brk` and `sbrk` move the end of the heap, and that the allocator
+/// grows past its first 16 KiB
+#[no_mangle]
+pub fn main() -> i32 {
+    let end = brk(0);
+    assert!(end > 0);
+    let page = sbrk(PAGE_SIZE as isize);
+    assert_eq!(page, end);
+    assert_eq!(brk(0), end + PAGE_SIZE as isize);
+    let bytes = unsafe { core::slice::from_raw_parts_mut(page as *mut u8, PAGE_SIZE) };
+    assert!(bytes.iter().all(|byte| *byte == 0));
+    bytes.fill(0xa5);
+    assert_eq!(sbrk(-(PAGE_SIZE as isize)), end + PAGE_SIZE as isize);
+    assert_eq!(brk(0), end);
+    // out of range, the end stays
+    assert_eq!(brk(end as usize + TOO_BIG), end);
+    assert_eq!(sbrk(TOO_BIG as isize), -1);
+    assert_eq!(brk(1), end);
+
+    let mut v: Vec<usize> = Vec::new();
+    for i in 0..N {
+        v.push(i);
+    }
+    let big: Vec<u8> = alloc::vec![7; 4 * 1024 * 1024];
+    assert!(brk(0) >= end + (8 << 20));
+    let pid = fork();
+    if pid == 0 {
+        // the child has a copy of the heap
+        let sum: usize = v.iter().sum();
+        exit((sum != N * (N - 1) / 2) as i32);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert!(v.iter().enumerate().all(|(i, n)| i == *n));
+    assert!(big.iter().all(|byte| *byte == 7));
+    println!("heap_test passed!");
+    0
+}
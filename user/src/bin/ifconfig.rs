@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::net::{
+    ifconfig, parse_ipv4, set_ifconfig, IfConfig, CONFIG_DHCP, CONFIG_NONE, CONFIG_STATIC,
+};
+
+fn usage() -> i32 {
+    println!("usage: ifconfig [dhcp | none | ADDR[/PREFIX] [gw ADDR] [dns ADDR]]");
+    -1
+}
+
+fn show(config: &IfConfig) {
+    let [a, b, c, d, e, f] = config.mac;
+    println!("hwaddr  {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, f);
+    let [a, b, c, d] = config.ip;
+    println!("inet    {}.{}.{}.{}/{}", a, b, c, d, config.prefix_len);
+    let [a, b, c, d] = config.gateway;
+    println!("gateway {}.{}.{}.{}", a, b, c, d);
+    let [a, b, c, d] = config.dns;
+    println!("dns     {}.{}.{}.{}", a, b, c, d);
+    let source = match config.source {
+        CONFIG_STATIC => "static",
+        CONFIG_DHCP => "dhcp",
+        _ => "none",
+    };
+    println!("source  {}", source);
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let mut config = ifconfig();
+    if argc == 1 {
+        show(&config);
+        return 0;
+    }
+    match argv[1] {
+        "dhcp" => config.source = CONFIG_DHCP,
+        "none" => config.source = CONFIG_NONE,
+        addr => {
+            let (ip, prefix_len) = match addr.split_once('/') {
+                Some((ip, prefix_len)) => match prefix_len.parse() {
+                    Ok(prefix_len) => (ip, prefix_len),
+                    Err(_) => return usage(),
+                },
+                None => (addr, 24),
+            };
+            config.ip = match parse_ipv4(ip) {
+                Some(ip) => ip,
+                None => return usage(),
+            };
+            config.prefix_len = prefix_len;
+            config.source = CONFIG_STATIC;
+            for option in argv[2..].chunks(2) {
+                let addr = match option {
+                    [_, addr] => parse_ipv4(addr),
+                    _ => None,
+                };
+                match (option[0], addr) {
+                    ("gw", Some(addr)) => config.gateway = addr,
+                    ("dns", Some(addr)) => config.dns = addr,
+                    _ => return usage(),
+                }
+            }
+        }
+    }
+    if !set_ifconfig(&config) {
+        println!("ifconfig: invalid configuration");
+        return -1;
+    }
+    if config.source != CONFIG_DHCP {
+        show(&ifconfig());
+    }
+    0
+}
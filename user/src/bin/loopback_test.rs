@@ -0,0 +1,115 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::net::{
+    IcmpSocket, SocketAddrV4, TcpListener, TcpStream, UdpSocket, ICMP_ECHO_REPLY, SHUT_WR,
+};
+use user_lib::{exit, fork, waitpid};
+
+const LOCALHOST: [u8; 4] = [127, 0, 0, 1];
+const UDP_PORT: u16 = 6100;
+const TCP_PORT: u16 = 6101;
+/// More than one segment and one socket buffer's worth
+const TCP_LENGTH: usize = 20000;
+/// Largest UDP payload that fits the 1500 byte MTU
+const UDP_MAX_PAYLOAD: usize = 1472;
+
+fn udp_test() {
+    let server = UdpSocket::bind(SocketAddrV4::new(LOCALHOST, UDP_PORT)).unwrap();
+    let client = UdpSocket::bind(SocketAddrV4::new([0; 4], 0)).unwrap();
+    let server_addr = SocketAddrV4::new(LOCALHOST, UDP_PORT);
+    assert_eq!(client.send_to(b"ping", server_addr), 4);
+    let mut buf = [0u8; 16];
+    let (len, peer) = server.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"ping");
+    assert_eq!(peer.ip, LOCALHOST);
+    assert_eq!(server.send_to(b"pong", peer), 4);
+    let (len, from) = client.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"pong");
+    assert_eq!(from, server_addr);
+    // datagrams are never cut to fit the MTU
+    let big = [0x5au8; UDP_MAX_PAYLOAD + 1];
+    let sent = client.send_to(&big[..UDP_MAX_PAYLOAD], server_addr);
+    assert_eq!(sent, UDP_MAX_PAYLOAD as isize);
+    assert_eq!(client.send_to(&big, server_addr), -1);
+    let mut received = [0u8; UDP_MAX_PAYLOAD + 1];
+    let (len, _) = server.recv_from(&mut received).unwrap();
+    assert_eq!(&received[..len], &big[..UDP_MAX_PAYLOAD]);
+    println!("udp over loopback passed!");
+}
+
+fn pattern(i: usize) -> u8 {
+    (i * 7 % 251) as u8
+}
+
+fn tcp_test() {
+    let addr = SocketAddrV4::new(LOCALHOST, TCP_PORT);
+    let listener = TcpListener::bind(addr, 1).unwrap();
+    let pid = fork();
+    if pid == 0 {
+        // client: send the pattern, then read the echo back
+        drop(listener);
+        let stream = TcpStream::connect(addr).unwrap();
+        let data: Vec<u8> = (0..TCP_LENGTH).map(pattern).collect();
+        assert!(stream.write_all(&data));
+        assert!(stream.shutdown(SHUT_WR));
+        let mut echo = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            let len = stream.read(&mut buf);
+            assert!(len >= 0);
+            if len == 0 {
+                break;
+            }
+            echo.extend_from_slice(&buf[..len as usize]);
+        }
+        assert!(echo == data);
+        exit(0);
+    }
+    // server: echo everything until the client shuts down its side
+    let (stream, peer) = listener.accept().unwrap();
+    assert_eq!(peer.ip, LOCALHOST);
+    let mut received = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        let len = stream.read(&mut buf);
+        assert!(len >= 0);
+        if len == 0 {
+            break;
+        }
+        received.extend_from_slice(&buf[..len as usize]);
+    }
+    assert_eq!(received.len(), TCP_LENGTH);
+    assert!(stream.write_all(&received));
+    drop(stream);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("tcp over loopback passed!");
+}
+
+fn icmp_test() {
+    let socket = IcmpSocket::new().unwrap();
+    assert!(socket.send_echo(LOCALHOST, 1, b"loopback"));
+    let mut buf = [0u8; 64];
+    let (len, from) = socket.recv_from(&mut buf).unwrap();
+    assert_eq!(from, LOCALHOST);
+    assert_eq!(buf[0], ICMP_ECHO_REPLY);
+    assert_eq!(&buf[8..len], b"loopback");
+    println!("icmp over loopback passed!");
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    udp_test();
+    tcp_test();
+    icmp_test();
+    println!("loopback_test passed!");
+    0
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{
+    close, exec, exit, fork, open, poll, read, waitpid_nb, write, OpenFlags, PollEvents, PollFd,
+};
+
+/// How often to check whether the command has exited
+const POLL_MS: isize = 100;
+
+/// Copy what has been captured so far to `out`
+fn drain(capture: usize, out: usize, buf: &mut [u8]) {
+    loop {
+        let mut fds = [PollFd::new(capture, PollEvents::IN)];
+        if poll(&mut fds, 0) <= 0 {
+            return;
+        }
+        let len = read(capture, buf);
+        if len <= 0 {
+            return;
+        }
+        write(out, &buf[..len as usize]);
+    }
+}
+
+/// Capture the network traffic while running a command, e.g.
+/// `pcap /ping.pcap ping 10.0.2.2 3`, then open the file in Wireshark
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 3 {
+        println!("usage: pcap FILE COMMAND [ARGS...]");
+        return -1;
+    }
+    let capture = open("/dev/pcap\0", OpenFlags::RDONLY | OpenFlags::CLOEXEC);
+    if capture < 0 {
+        println!("pcap: a capture is already running");
+        return -1;
+    }
+    let capture = capture as usize;
+    let mut path = String::from(argv[1]);
+    path.push('\0');
+    let out = open(&path, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    if out < 0 {
+        println!("pcap: can't create {}", argv[1]);
+        return -1;
+    }
+    let out = out as usize;
+    let args: Vec<String> = argv[2..]
+        .iter()
+        .map(|arg| {
+            let mut arg = String::from(*arg);
+            arg.push('\0');
+            arg
+        })
+        .collect();
+    let pid = fork();
+    if pid == 0 {
+        let mut args_addr: Vec<*const u8> = args.iter().map(|arg| arg.as_ptr()).collect();
+        args_addr.push(core::ptr::null());
+        if exec(&args[0], &args_addr) < 0 {
+            println!("pcap: can't run {}", argv[2]);
+            exit(-4);
+        }
+        unreachable!();
+    }
+    let mut buf = [0u8; 4096];
+    let mut exit_code = 0;
+    loop {
+        let mut fds = [PollFd::new(capture, PollEvents::IN)];
+        poll(&mut fds, POLL_MS);
+        drain(capture, out, &mut buf);
+        if waitpid_nb(pid as usize, &mut exit_code) != -2 {
+            break;
+        }
+    }
+    // the last replies may still be on their way
+    drain(capture, out, &mut buf);
+    close(capture);
+    close(out);
+    println!("pcap: capture saved to {}", argv[1]);
+    exit_code
+}
@@ -0,0 +1,244 @@
+#![no_std]
+#![no_main]
+#![allow(clippy::println_empty_string)]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+const LF: u8 = 0x0au8;
+const CR: u8 = 0x0du8;
+const DL: u8 = 0x7fu8;
+const BS: u8 = 0x08u8;
+const PROMPT: &str = "(pdb) ";
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::console::getchar;
+use user_lib::{
+    exec, exit, fork, ptrace, ptrace_getregs, ptrace_peek, ptrace_setregs, waitpid_options,
+    wexitstatus, wifexited, wstopsig, PTRACE_ATTACH, PTRACE_CONT, PTRACE_DETACH, PTRACE_KILL,
+    PTRACE_POKEDATA, PTRACE_TRACEME, WUNTRACED,
+};
+
+const SIGTRAP: i32 = 5;
+const SIGSTOP: i32 = 19;
+const EBREAK: usize = 0x0010_0073;
+const REG_NAMES: [&str; 32] = [
+    "pc", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5",
+    "t6",
+];
+
+/// A breakpoint and the word its `ebreak` replaced
+struct Breakpoint {
+    addr: usize,
+    saved: usize,
+}
+
+struct Debugger {
+    pid: usize,
+    breakpoints: Vec<Breakpoint>,
+    /// Signal the tracee stopped with, passed on when it continues
+    pending: usize,
+}
+
+impl Debugger {
+    fn peek(&self, addr: usize) -> Option<usize> {
+        ptrace_peek(self.pid, addr)
+    }
+
+    fn poke(&self, addr: usize, word: usize) -> bool {
+        ptrace(PTRACE_POKEDATA, self.pid, addr, word) == 0
+    }
+
+    fn set_breakpoint(&mut self, addr: usize) {
+        match self.peek(addr) {
+            Some(saved) if self.poke(addr, (saved & !0xffff_ffff) | EBREAK) => {
+                self.breakpoints.push(Breakpoint { addr, saved });
+                println!("breakpoint {} at {:#x}", self.breakpoints.len(), addr);
+            }
+            _ => println!("can't write to {:#x}", addr),
+        }
+    }
+
+    fn print_regs(&self) {
+        let regs = match ptrace_getregs(self.pid) {
+            Some(regs) => regs,
+            None => {
+                println!("can't read the registers");
+                return;
+            }
+        };
+        println!("{:>4} {:#018x}", REG_NAMES[0], regs.pc);
+        for (n, name) in REG_NAMES.iter().enumerate().skip(1) {
+            print!("{:>4} {:#018x}", name, regs.reg(n));
+            print!("{}", if n % 4 == 0 { "\n" } else { "  " });
+        }
+    }
+
+    /// Wait for the next stop. Returns false once the tracee is gone
+    fn wait(&mut self) -> bool {
+        let mut status = 0;
+        if waitpid_options(self.pid as isize, &mut status, WUNTRACED) != self.pid as isize {
+            println!("process {} is gone", self.pid);
+            return false;
+        }
+        if wifexited(status) {
+            println!("process {} exited with {}", self.pid, wexitstatus(status));
+            return false;
+        }
+        let sig = wstopsig(status);
+        let mut regs = ptrace_getregs(self.pid).unwrap();
+        self.pending = 0;
+        // a breakpoint of ours: put the code back and run it from the start
+        if sig == SIGTRAP {
+            if let Some(idx) = self.breakpoints.iter().position(|bp| bp.addr == regs.pc) {
+                let bp = self.breakpoints.remove(idx);
+                self.poke(bp.addr, bp.saved);
+                println!("breakpoint at {:#x}", bp.addr);
+                return true;
+            }
+        } else if sig != SIGSTOP {
+            // SIGSTOP is how attaching stops the process
+            self.pending = sig as usize;
+        }
+        println!("stopped by signal {} at {:#x}", sig, regs.pc);
+        // step over an ebreak or c.ebreak of the program itself
+        let insn = self.peek(regs.pc).unwrap_or(0) as u32;
+        if sig == SIGTRAP && (insn == EBREAK as u32 || insn & 0xffff == 0x9002) {
+            regs.pc += if insn & 0x3 == 0x3 { 4 } else { 2 };
+            ptrace_setregs(self.pid, &regs);
+        }
+        true
+    }
+
+    /// Run one command. Returns false to quit
+    fn run(&mut self, line: &str) -> bool {
+        let words: Vec<_> = line.split(' ').filter(|word| !word.is_empty()).collect();
+        let number = |i: usize| words.get(i).and_then(|word| parse_number(word));
+        match words.first().copied() {
+            None => {}
+            Some("regs") => self.print_regs(),
+            Some("x") => match number(1) {
+                Some(addr) => match self.peek(addr) {
+                    Some(word) => println!("{:#x}: {:#018x}", addr, word),
+                    None => println!("can't read {:#x}", addr),
+                },
+                None => println!("usage: x ADDR"),
+            },
+            Some("set") => match (number(1), number(2)) {
+                (Some(addr), Some(word)) => {
+                    if !self.poke(addr, word) {
+                        println!("can't write to {:#x}", addr);
+                    }
+                }
+                _ => println!("usage: set ADDR WORD"),
+            },
+            Some("b") => match number(1) {
+                Some(addr) => self.set_breakpoint(addr),
+                None => println!("usage: b ADDR"),
+            },
+            Some("c") => {
+                ptrace(PTRACE_CONT, self.pid, 0, self.pending);
+                return self.wait();
+            }
+            Some("kill") => {
+                ptrace(PTRACE_KILL, self.pid, 0, 0);
+                self.wait();
+                return false;
+            }
+            Some("detach") | Some("q") => {
+                for bp in self.breakpoints.iter() {
+                    self.poke(bp.addr, bp.saved);
+                }
+                ptrace(PTRACE_DETACH, self.pid, 0, self.pending);
+                return false;
+            }
+            Some(_) => {
+                println!("commands: regs, x ADDR, set ADDR WORD, b ADDR, c, kill, detach, q")
+            }
+        }
+        true
+    }
+}
+
+/// Hexadecimal with `0x`, else decimal
+fn parse_number(word: &str) -> Option<usize> {
+    match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+fn read_line() -> String {
+    let mut line = String::new();
+    loop {
+        let c = getchar();
+        match c {
+            LF | CR => {
+                println!("");
+                return line;
+            }
+            BS | DL => {
+                if line.pop().is_some() {
+                    print!("{} {}", BS as char, BS as char);
+                }
+            }
+            _ => {
+                print!("{}", c as char);
+                line.push(c as char);
+            }
+        }
+    }
+}
+
+/// A small debugger. `pdb PROGRAM [ARGS...]` stops the program before its
+/// first instruction, `pdb -p PID` attaches to a running process started below pdb
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let pid = match argv.get(1).copied() {
+        Some("-p") if argc == 3 => {
+            let pid = argv[2].parse().unwrap_or(0);
+            if ptrace(PTRACE_ATTACH, pid, 0, 0) != 0 {
+                println!("pdb: can't attach to {}", argv[2]);
+                return -1;
+            }
+            pid
+        }
+        Some(program) if !program.starts_with('-') => {
+            let args: Vec<String> = argv[1..]
+                .iter()
+                .map(|arg| String::from(*arg) + "\0")
+                .collect();
+            let mut args_addr: Vec<*const u8> = args.iter().map(|arg| arg.as_ptr()).collect();
+            args_addr.push(core::ptr::null());
+            let pid = fork();
+            if pid == 0 {
+                ptrace(PTRACE_TRACEME, 0, 0, 0);
+                exec(args[0].as_str(), args_addr.as_slice());
+                println!("pdb: can't run {}", program);
+                exit(-1);
+            }
+            pid as usize
+        }
+        _ => {
+            println!("usage: pdb PROGRAM [ARGS...] | pdb -p PID");
+            return -1;
+        }
+    };
+    let mut debugger = Debugger {
+        pid,
+        breakpoints: Vec::new(),
+        pending: 0,
+    };
+    if !debugger.wait() {
+        return -1;
+    }
+    loop {
+        print!("{}", PROMPT);
+        if !debugger.run(read_line().as_str()) {
+            return 0;
+        }
+    }
+}
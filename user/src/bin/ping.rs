@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::net::{parse_ipv4, IcmpSocket, ICMP_ECHO_REPLY};
+use user_lib::{get_time, poll, sleep, PollEvents, PollFd};
+
+/// Bytes of data after the ICMP header, as ping sends by default
+const PAYLOAD_LEN: usize = 56;
+/// How long to wait for each reply, also the interval between requests
+const INTERVAL_MS: isize = 1000;
+
+/// Wait until `deadline` for the reply to `seq`, returning its length
+fn wait_reply(socket: &IcmpSocket, seq: u16, deadline: isize) -> Option<usize> {
+    let mut buf = [0u8; 8 + PAYLOAD_LEN];
+    loop {
+        let timeout = deadline - get_time();
+        if timeout <= 0 {
+            return None;
+        }
+        let mut fds = [PollFd::new(socket.fd(), PollEvents::IN)];
+        if poll(&mut fds, timeout) <= 0 {
+            return None;
+        }
+        let (len, _) = socket.recv_from(&mut buf)?;
+        // a late reply to an earlier request is skipped
+        if len >= 8 && buf[0] == ICMP_ECHO_REPLY && u16::from_be_bytes([buf[6], buf[7]]) == seq {
+            return Some(len);
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        println!("usage: ping ADDR [COUNT]");
+        return -1;
+    }
+    let ip = match parse_ipv4(argv[1]) {
+        Some(ip) => ip,
+        None => {
+            println!("ping: bad address {}", argv[1]);
+            return -1;
+        }
+    };
+    let count: u16 = match argv.get(2).map(|count| count.parse()) {
+        None => 4,
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            println!("ping: bad count {}", argv[2]);
+            return -1;
+        }
+    };
+    let socket = match IcmpSocket::new() {
+        Some(socket) => socket,
+        None => {
+            println!("ping: can't open an ICMP socket");
+            return -1;
+        }
+    };
+    let mut payload = [0u8; PAYLOAD_LEN];
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    println!("PING {} {} data bytes", argv[1], PAYLOAD_LEN);
+    let (mut received, mut min, mut max, mut total) = (0, isize::MAX, 0, 0);
+    for seq in 1..=count {
+        let start = get_time();
+        if !socket.send_echo(ip, seq, &payload) {
+            println!("ping: sending icmp_seq={} failed", seq);
+        } else {
+            match wait_reply(&socket, seq, start + INTERVAL_MS) {
+                Some(len) => {
+                    let rtt = get_time() - start;
+                    println!("{} bytes from {}: icmp_seq={} time={} ms", len, argv[1], seq, rtt);
+                    received += 1;
+                    min = min.min(rtt);
+                    max = max.max(rtt);
+                    total += rtt;
+                }
+                None => println!("Request timeout for icmp_seq={}", seq),
+            }
+        }
+        let elapsed = get_time() - start;
+        if seq != count && elapsed < INTERVAL_MS {
+            sleep((INTERVAL_MS - elapsed) as usize);
+        }
+    }
+    println!("--- {} ping statistics ---", argv[1]);
+    println!(
+        "{} packets transmitted, {} received, {}% packet loss",
+        count,
+        received,
+        (count as isize - received) * 100 / count.max(1) as isize
+    );
+    if received > 0 {
+        println!("rtt min/avg/max = {}/{}/{} ms", min, total / received, max);
+        0
+    } else {
+        1
+    }
+}
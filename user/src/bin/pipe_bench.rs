@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exec, fork, get_time, pipe, read, waitpid, write};
+
+/// bytes streamed through the pipe for every chunk size
+const TOTAL: usize = 1024 * 1024;
+const CHUNK_SIZES: [usize; 3] = [64, 1024, 4096];
+
+/// Stream `TOTAL` bytes from a child to the parent in `chunk`-sized writes,
+/// returns the elapsed time in ms
+fn stream(chunk: usize) -> usize {
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let mut buffer = [0u8; 4096];
+    let start = get_time();
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        let mut sent = 0usize;
+        while sent < TOTAL {
+            sent += write(pipe_fd[1], &buffer[..chunk]) as usize;
+        }
+        close(pipe_fd[1]);
+        user_lib::exit(0);
+    }
+    close(pipe_fd[1]);
+    let mut received = 0usize;
+    loop {
+        let len = read(pipe_fd[0], &mut buffer[..chunk]);
+        if len <= 0 {
+            break;
+        }
+        received += len as usize;
+    }
+    close(pipe_fd[0]);
+    let mut exit_code: i32 = 0;
+    waitpid(pid as usize, &mut exit_code);
+    assert_eq!(received, TOTAL);
+    (get_time() - start) as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    for chunk in CHUNK_SIZES {
+        let time_ms = stream(chunk).max(1);
+        println!(
+            "pipe_bench: {}KiB in {}B chunks, time cost = {}ms, throughput = {}KiB/s",
+            TOTAL / 1024,
+            chunk,
+            time_ms,
+            TOTAL / 1024 * 1000 / time_ms
+        );
+    }
+    // the 3000-byte round trip of pipe_large_test as a latency baseline
+    let start = get_time();
+    let pid = fork();
+    if pid == 0 {
+        exec("pipe_large_test\0", &[core::ptr::null::<u8>()]);
+        panic!("unreachable!");
+    }
+    let mut exit_code: i32 = 0;
+    waitpid(pid as usize, &mut exit_code);
+    assert_eq!(exit_code, 0);
+    println!(
+        "pipe_bench: pipe_large_test took {}ms",
+        get_time() - start
+    );
+    0
+}
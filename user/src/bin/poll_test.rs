@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fork, get_time, pipe, poll, read, sleep, wait, write, PollEvents, PollFd};
+
+static STR: &str = "Hello, poll!";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let (read_end, write_end) = (pipe_fd[0], pipe_fd[1]);
+    // an empty pipe is writable but not readable
+    let mut fds = [
+        PollFd::new(read_end, PollEvents::IN),
+        PollFd::new(write_end, PollEvents::OUT),
+    ];
+    assert_eq!(poll(&mut fds, 0), 1);
+    assert!(fds[0].revents.is_empty());
+    assert_eq!(fds[1].revents, PollEvents::OUT);
+    // nothing arrives, so poll returns after the timeout
+    let start = get_time();
+    let mut fds = [PollFd::new(read_end, PollEvents::IN)];
+    assert_eq!(poll(&mut fds, 100), 0);
+    assert!(get_time() - start >= 100);
+    // a closed fd is reported as invalid
+    let mut fds = [PollFd::new(42, PollEvents::IN)];
+    assert_eq!(poll(&mut fds, 0), 1);
+    assert_eq!(fds[0].revents, PollEvents::NVAL);
+    if fork() == 0 {
+        // child process, write after a while and hang up
+        close(read_end);
+        sleep(50);
+        write(write_end, STR.as_bytes());
+        close(write_end);
+        0
+    } else {
+        // parent process, wait for the data without a timeout
+        close(write_end);
+        let mut fds = [PollFd::new(read_end, PollEvents::IN)];
+        assert_eq!(poll(&mut fds, -1), 1);
+        assert!(fds[0].revents.contains(PollEvents::IN));
+        let mut buffer = [0u8; 32];
+        let len = read(read_end, &mut buffer) as usize;
+        assert_eq!(core::str::from_utf8(&buffer[..len]).unwrap(), STR);
+        let mut child_exit_code: i32 = 0;
+        wait(&mut child_exit_code);
+        assert_eq!(child_exit_code, 0);
+        // every write end is closed now
+        assert_eq!(poll(&mut fds, -1), 1);
+        assert!(fds[0].revents.contains(PollEvents::HUP));
+        close(read_end);
+        println!("poll_test passed!");
+        0
+    }
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use user_lib::{close, open, read, OpenFlags};
+
+/// Read a whole proc file into a string
+fn read_to_string(path: &str) -> Option<String> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let fd = fd as usize;
+    let mut content = String::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let size = read(fd, &mut buf);
+        if size <= 0 {
+            break;
+        }
+        content.push_str(core::str::from_utf8(&buf[..size as usize]).unwrap());
+    }
+    close(fd);
+    Some(content)
+}
+
+/// Find the value of `key` in a `Key:\tvalue` formatted status file
+fn field<'a>(status: &'a str, key: &str) -> &'a str {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(key))
+        .and_then(|rest| rest.strip_prefix(':'))
+        .map_or("?", |value| value.trim())
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let entries = match read_to_string("/proc\0") {
+        Some(entries) => entries,
+        None => {
+            println!("ps: /proc is not available");
+            return -1;
+        }
+    };
+    println!("  PID  PPID THR STATE         RSS NAME");
+    for pid in entries.lines().filter_map(|line| line.parse::<usize>().ok()) {
+        // the process may have exited since the listing was taken
+        if let Some(status) = read_to_string(format!("/proc/{}/status\0", pid).as_str()) {
+            println!(
+                "{:>5} {:>5} {:>3} {:<13} {:>7} {}",
+                pid,
+                field(&status, "PPid"),
+                field(&status, "Threads"),
+                field(&status, "State"),
+                field(&status, "VmRSS"),
+                field(&status, "Name"),
+            );
+        }
+    }
+    0
+}
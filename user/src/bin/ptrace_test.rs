@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use core::ptr::{addr_of, read_volatile};
+use user_lib::{
+    exit, fork, getpid, ptrace, ptrace_getregs, ptrace_peek, ptrace_setregs, waitpid_options,
+    wexitstatus, wifexited, wifstopped, wstopsig, PTRACE_POKEDATA, PTRACE_SYSCALL, PTRACE_TRACEME,
+    SYSCALL_STOP_SIG, WUNTRACED,
+};
+
+const SIGTRAP: i32 = 5;
+const SYSCALL_GETPID: usize = 172;
+const MAGIC: usize = 0x5eed_cafe;
+
+/// Written by the tracer while the child is stopped
+static mut SECRET: usize = 0;
+
+fn child() -> ! {
+    assert_eq!(ptrace(PTRACE_TRACEME, 0, 0, 0), 0);
+    unsafe {
+        asm!("ebreak");
+    }
+    let secret = unsafe { read_volatile(addr_of!(SECRET)) };
+    getpid();
+    exit(if secret == MAGIC { 7 } else { 1 });
+}
+
+/// Stop a child on `ebreak`, patch its memory and registers to step over
+/// the breakpoint, then follow its syscalls until it exits
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        child();
+    }
+    let pid = pid as usize;
+    let mut status = 0;
+    assert_eq!(
+        waitpid_options(pid as isize, &mut status, WUNTRACED),
+        pid as isize
+    );
+    assert!(wifstopped(status) && wstopsig(status) == SIGTRAP);
+
+    let mut regs = ptrace_getregs(pid).expect("can't read the registers");
+    let insn = ptrace_peek(pid, regs.pc).expect("can't read the code") as u32;
+    // ebreak or c.ebreak
+    let len = if insn & 0x3 == 0x3 { 4 } else { 2 };
+    assert!(
+        insn == 0x0010_0073 || insn & 0xffff == 0x9002,
+        "{:#x} isn't ebreak",
+        insn
+    );
+    let secret = addr_of!(SECRET) as usize;
+    assert_eq!(ptrace(PTRACE_POKEDATA, pid, secret, MAGIC), 0);
+    assert_eq!(ptrace_peek(pid, secret), Some(MAGIC));
+    regs.pc += len;
+    assert_eq!(ptrace_setregs(pid, &regs), 0);
+
+    let mut saw_getpid = false;
+    assert_eq!(ptrace(PTRACE_SYSCALL, pid, 0, 0), 0);
+    loop {
+        assert_eq!(
+            waitpid_options(pid as isize, &mut status, WUNTRACED),
+            pid as isize
+        );
+        if wifexited(status) {
+            break;
+        }
+        assert!(wifstopped(status) && wstopsig(status) == SYSCALL_STOP_SIG);
+        let regs = ptrace_getregs(pid).unwrap();
+        // at the exit stop, a0 holds the result
+        if regs.reg(17) == SYSCALL_GETPID && regs.reg(10) == pid {
+            saw_getpid = true;
+        }
+        assert_eq!(ptrace(PTRACE_SYSCALL, pid, 0, 0), 0);
+    }
+    assert_eq!(wexitstatus(status), 7);
+    assert!(saw_getpid, "no getpid stop");
+    println!("ptrace_test passed!");
+    0
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{close, exit, fork, get_time, open, read, waitpid, OpenFlags};
+
+const WORKERS: usize = 4;
+const ROUNDS: usize = 2_000_000;
+
+fn read_to_string(path: &str) -> String {
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd >= 0, "can't open {}", path);
+    let fd = fd as usize;
+    let mut content = String::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let size = read(fd, &mut buf);
+        if size <= 0 {
+            break;
+        }
+        content.push_str(core::str::from_utf8(&buf[..size as usize]).unwrap());
+    }
+    close(fd);
+    content
+}
+
+/// Task switches of every online hart, from /proc/cpuinfo
+fn hart_switches() -> Vec<usize> {
+    read_to_string("/proc/cpuinfo\0")
+        .lines()
+        .filter_map(|line| line.strip_prefix("switches"))
+        .map(|rest| rest.trim_start_matches(|c| c == '\t' || c == ':' || c == ' '))
+        .map(|value| value.parse().unwrap())
+        .collect()
+}
+
+/// Something the compiler can't fold away
+fn work(seed: usize) -> i32 {
+    let mut x = seed;
+    for _ in 0..ROUNDS {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+    }
+    (x >> 56) as i32
+}
+
+/// Run CPU bound workers side by side, then check every online hart has
+/// been scheduling. `make run SMP=4` should finish them about 4 times as fast
+#[no_mangle]
+pub fn main() -> i32 {
+    let before = hart_switches();
+    assert!(!before.is_empty(), "no hart in /proc/cpuinfo");
+    println!("{} harts online", before.len());
+    let start = get_time();
+    let mut pids = Vec::new();
+    for i in 0..WORKERS {
+        let pid = fork();
+        if pid == 0 {
+            exit(work(i));
+        }
+        assert!(pid > 0);
+        pids.push(pid as usize);
+    }
+    let mut results = Vec::new();
+    for pid in pids {
+        let mut exit_code = 0;
+        assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+        results.push(exit_code);
+    }
+    println!("{} workers took {} ms", WORKERS, get_time() - start);
+    for (i, result) in results.into_iter().enumerate() {
+        assert_eq!(result, work(i), "worker {} computed a wrong result", i);
+    }
+    let after = hart_switches();
+    for (hart, (before, after)) in before.iter().zip(after.iter()).enumerate() {
+        println!("processor {}: {} task switches", hart, after - before);
+        assert!(after > before, "processor {} ran nothing", hart);
+    }
+    println!("smp_test passed!");
+    0
+}
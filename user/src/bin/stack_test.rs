@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::ptr::{read_volatile, write_volatile};
+use user_lib::{exit, fork, thread_create, thread_create_with_stack, waitpid, waittid};
+
+const KIB: usize = 1024;
+
+/// Use `depth` KiB of stack, returns `depth`
+#[inline(never)]
+fn recurse(depth: usize) -> usize {
+    let mut frame = [0u8; KIB];
+    unsafe { write_volatile(&mut frame[KIB - 1], 1) };
+    if depth == 0 {
+        return 0;
+    }
+    recurse(depth - 1) + unsafe { read_volatile(&frame[KIB - 1]) } as usize
+}
+
+fn use_stack(depth: usize) -> ! {
+    assert_eq!(recurse(depth), depth);
+    exit(0)
+}
+
+/// Check stacks grow past their first pages up to their limit, and that
+/// going further kills the process with SIGSEGV
+#[no_mangle]
+pub fn main() -> i32 {
+    // the default limit is 1 MiB
+    assert_eq!(recurse(512), 512);
+    let small = thread_create(use_stack as usize, 64);
+    let large = thread_create_with_stack(use_stack as usize, 2048, 4 * KIB * KIB);
+    assert!(small > 0 && large > 0);
+    assert_eq!(waittid(small as usize), 0);
+    assert_eq!(waittid(large as usize), 0);
+    assert_eq!(
+        thread_create_with_stack(use_stack as usize, 0, 32 * KIB * KIB),
+        -1
+    );
+
+    let pid = fork();
+    if pid == 0 {
+        let tid = thread_create_with_stack(use_stack as usize, 256, 64 * KIB);
+        waittid(tid as usize);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -11, "overflowing a 64 KiB stack didn't SIGSEGV");
+    println!("stack_test passed!");
+    0
+}
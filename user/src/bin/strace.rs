@@ -0,0 +1,147 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{
+    exec, exit, fork, ptrace, ptrace_getregs, waitpid_options, wexitstatus, wifexited, wstopsig,
+    PTRACE_SYSCALL, PTRACE_TRACEME, SYSCALL_STOP_SIG, WUNTRACED,
+};
+
+const SIGTRAP: i32 = 5;
+
+fn syscall_name(id: usize) -> &'static str {
+    match id {
+        24 => "dup",
+        25 => "fcntl",
+        26 => "dup3",
+        33 => "mknodat",
+        35 => "unlinkat",
+        37 => "linkat",
+        56 => "openat",
+        57 => "close",
+        59 => "pipe",
+        63 => "read",
+        64 => "write",
+        73 => "poll",
+        80 => "fstat",
+        93 => "exit",
+        101 => "sleep",
+        116 => "syslog",
+        117 => "ptrace",
+        124 => "yield",
+        129 => "kill",
+        134 => "sigaction",
+        135 => "sigprocmask",
+        139 => "sigreturn",
+        140 => "set_priority",
+        169 => "gettimeofday",
+        172 => "getpid",
+        198 => "socket",
+        200 => "bind",
+        201 => "listen",
+        202 => "accept",
+        203 => "connect",
+        206 => "sendto",
+        207 => "recvfrom",
+        210 => "shutdown",
+        215 => "munmap",
+        220 => "fork",
+        221 => "exec",
+        222 => "mmap",
+        260 => "waitpid",
+        400 => "spawn",
+        410 => "task_info",
+        500 => "ifconfig_get",
+        501 => "ifconfig_set",
+        1000 => "thread_create",
+        1001 => "gettid",
+        1002 => "waittid",
+        1010 => "mutex_create",
+        1011 => "mutex_lock",
+        1012 => "mutex_unlock",
+        1020 => "semaphore_create",
+        1021 => "semaphore_up",
+        1022 => "semaphore_down",
+        1030 => "condvar_create",
+        1031 => "condvar_signal",
+        1032 => "condvar_wait",
+        2000 => "framebuffer",
+        2001 => "framebuffer_flush",
+        3000 => "event_get",
+        3001 => "key_pressed",
+        _ => "unknown",
+    }
+}
+
+/// Run a program and print its syscalls, with their first three arguments
+/// and their result, to the console
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        println!("usage: strace PROGRAM [ARGS...]");
+        return -1;
+    }
+    let args: Vec<String> = argv[1..]
+        .iter()
+        .map(|arg| String::from(*arg) + "\0")
+        .collect();
+    let mut args_addr: Vec<*const u8> = args.iter().map(|arg| arg.as_ptr()).collect();
+    args_addr.push(core::ptr::null());
+
+    let pid = fork();
+    if pid == 0 {
+        ptrace(PTRACE_TRACEME, 0, 0, 0);
+        exec(args[0].as_str(), args_addr.as_slice());
+        println!("strace: can't run {}", argv[1]);
+        exit(-1);
+    }
+    let pid = pid as usize;
+    let mut status = 0;
+    // the child stops with SIGTRAP once the program is loaded
+    if waitpid_options(pid as isize, &mut status, WUNTRACED) != pid as isize || wifexited(status) {
+        return -1;
+    }
+    let mut entry = true;
+    // signal to pass on to the program
+    let mut signal = 0;
+    loop {
+        ptrace(PTRACE_SYSCALL, pid, 0, signal);
+        signal = 0;
+        waitpid_options(pid as isize, &mut status, WUNTRACED);
+        if wifexited(status) {
+            // exit has no exit stop
+            if !entry {
+                println!(" = ?");
+            }
+            println!("+++ exited with {} +++", wexitstatus(status));
+            return 0;
+        }
+        let sig = wstopsig(status);
+        if sig != SYSCALL_STOP_SIG {
+            // breakpoints are for debuggers
+            if sig != SIGTRAP {
+                println!("--- signal {} ---", sig);
+                signal = sig as usize;
+            }
+            continue;
+        }
+        let regs = ptrace_getregs(pid).unwrap();
+        if entry {
+            print!(
+                "{}({:#x}, {:#x}, {:#x})",
+                syscall_name(regs.reg(17)),
+                regs.reg(10),
+                regs.reg(11),
+                regs.reg(12)
+            );
+        } else {
+            println!(" = {}", regs.reg(10) as isize);
+        }
+        entry = !entry;
+    }
+}
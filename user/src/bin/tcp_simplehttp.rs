@@ -2,6 +2,7 @@
 #![no_main]
 
 use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
 #[macro_use]
 extern crate user_lib;
@@ -10,7 +11,7 @@ extern crate alloc;
 
 // use http://localhost:6201/ to access the http server
 
-use user_lib::{accept, listen, read, write};
+use user_lib::net::{SocketAddrV4, TcpListener, TcpStream};
 
 // get url from the tcp request list.
 fn get_url_from_tcp_request(req: &[u8]) -> String {
@@ -26,14 +27,24 @@ fn get_url_from_tcp_request(req: &[u8]) -> String {
 }
 
 // just receive GET requests
-fn handle_tcp_client(client_fd: usize) -> bool {
+fn handle_tcp_client(client: &TcpStream) -> bool {
     // a buf to receive the data from the server
     let mut buf = vec![0u8; 1024];
 
-    let len = read(client_fd as usize, &mut buf);
+    // a request may come in several segments, read until the header ends
+    let mut request: Vec<u8> = Vec::new();
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let len = client.read(&mut buf);
+        if len <= 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..len as usize]);
+    }
+    let buf = request;
+    let len = buf.len();
 
     println!("receive {} bytes", len);
-    hexdump(&buf[..len as usize]);
+    hexdump(&buf);
 
     // verify whether it is a valid HTTP request simply, [0x47,0x45,0x54, 0x20] is GET
     if len < 4 || buf[..4] != [0x47, 0x45, 0x54, 0x20] {
@@ -61,7 +72,7 @@ fn handle_tcp_client(client_fd: usize) -> bool {
         </body>
         </html>"#;
         let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnecion: Close\r\n\r\n{}", content.len(),content);
-        write(client_fd, response.as_bytes());
+        client.write_all(response.as_bytes());
         // terminate the connection immediately.
         return true;
     }
@@ -117,7 +128,7 @@ fn handle_tcp_client(client_fd: usize) -> bool {
     let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnecion: Close\r\n\r\n{}", content.len(),content);
 
     // write a response
-    write(client_fd, response.as_bytes());
+    client.write_all(response.as_bytes());
 
     false
 }
@@ -126,23 +137,28 @@ fn handle_tcp_client(client_fd: usize) -> bool {
 pub fn main() -> i32 {
     println!("This is a very simple http server");
 
-    let tcp_fd = listen(80);
-
-    if tcp_fd < 0 {
-        println!("Failed to listen on port 80");
-        return -1;
-    }
-
-    loop {
-        let client = accept(tcp_fd as usize);
-        println!("client connected: {}", client);
-
-        if client < 1 {
-            println!("Failed to accept a client on port 80");
+    let listener = match TcpListener::bind(SocketAddrV4::new([0, 0, 0, 0], 80), 8) {
+        Some(listener) => listener,
+        None => {
+            println!("Failed to listen on port 80");
             return -1;
         }
+    };
+
+    loop {
+        let (client, peer) = match listener.accept() {
+            Some(client) => client,
+            None => {
+                println!("Failed to accept a client on port 80");
+                return -1;
+            }
+        };
+        println!("client connected: {}", peer);
 
-        if handle_tcp_client(client as usize) {
+        let finish = handle_tcp_client(&client);
+        // dropping the stream sends our FIN once the response is out
+        drop(client);
+        if finish {
             break;
         }
     }
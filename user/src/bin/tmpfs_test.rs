@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, unlink, write, OpenFlags};
+
+/// Written in 1 KiB pieces, so the file spans many frames
+const SIZE_KB: usize = 1024;
+
+fn pattern(i: usize) -> u8 {
+    (i * 7 % 251) as u8
+}
+
+/// Write a file in `/tmp`, read it back and remove it
+#[no_mangle]
+pub fn main() -> i32 {
+    let path = "/tmp/tmpfs_test\0";
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut buffer = [0u8; 1024];
+    for kb in 0..SIZE_KB {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = pattern(kb * buffer.len() + i);
+        }
+        assert_eq!(write(fd, &buffer), buffer.len() as isize);
+    }
+    close(fd);
+
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    for kb in 0..SIZE_KB {
+        assert_eq!(read(fd, &mut buffer), buffer.len() as isize);
+        for (i, byte) in buffer.iter().enumerate() {
+            assert_eq!(*byte, pattern(kb * buffer.len() + i));
+        }
+    }
+    assert_eq!(read(fd, &mut buffer), 0);
+    close(fd);
+
+    assert_eq!(unlink(path), 0);
+    assert!(open(path, OpenFlags::RDONLY) < 0);
+    println!("tmpfs_test passed!");
+    0
+}
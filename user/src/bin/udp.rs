@@ -8,39 +8,42 @@ extern crate user_lib;
 #[macro_use]
 extern crate alloc;
 
-use user_lib::{connect, read, write};
+use user_lib::net::{SocketAddrV4, UdpSocket};
 
 #[no_mangle]
 pub fn main() -> i32 {
     println!("udp test open!");
 
-    let udp_fd = connect(10 << 24 | 0 << 16 | 2 << 8 | 2, 2001, 26099);
-
-    if udp_fd < 0 {
-        println!("failed to create udp connection.");
-        return -1;
-    }
+    let udp = match UdpSocket::bind(SocketAddrV4::new([0, 0, 0, 0], 2001)) {
+        Some(udp) => udp,
+        None => {
+            println!("failed to create udp socket.");
+            return -1;
+        }
+    };
+    let peer = SocketAddrV4::new([10, 0, 2, 2], 26099);
 
     let buf = "Hello rCoreOS user program!";
 
     println!("send <{}>", buf);
 
-    write(udp_fd as usize, buf.as_bytes());
+    udp.send_to(buf.as_bytes(), peer);
 
     println!("udp send done, waiting for reply.");
 
     let mut buf = vec![0u8; 1024];
 
-    let len = read(udp_fd as usize, &mut buf);
-
-    if len < 0 {
-        println!("can't receive udp packet");
-        return -1;
-    }
+    let (len, from) = match udp.recv_from(&mut buf) {
+        Some(reply) => reply,
+        None => {
+            println!("can't receive udp packet");
+            return -1;
+        }
+    };
 
-    let recv_str = String::from_utf8_lossy(&buf[..len as usize]);
+    let recv_str = String::from_utf8_lossy(&buf[..len]);
 
-    println!("receive reply <{}>", recv_str);
+    println!("receive reply <{}> from {}", recv_str, from);
 
     0
 }
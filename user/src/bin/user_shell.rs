@@ -16,7 +16,7 @@ const LINE_START: &str = ">> ";
 use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
+use user_lib::{close, dup2, env, exec, fcntl, fork, open, pipe, waitpid, OpenFlags, FD_CLOEXEC, F_SETFD};
 
 #[derive(Debug)]
 struct ProcessArguments {
@@ -74,6 +74,26 @@ impl ProcessArguments {
     }
 }
 
+/// Run `line` if it is a shell builtin, only `export` for now
+fn run_builtin(line: &str) -> bool {
+    let words: Vec<_> = line.split(' ').filter(|word| !word.is_empty()).collect();
+    if words.first() != Some(&"export") {
+        return false;
+    }
+    if words.len() == 1 {
+        for (key, value) in env::vars() {
+            println!("export {}={}", key, value);
+        }
+    }
+    for assignment in &words[1..] {
+        match assignment.split_once('=') {
+            Some((key, value)) if !key.is_empty() => env::set_var(key, value),
+            _ => println!("export: expected KEY=VALUE, got {}", assignment),
+        }
+    }
+    true
+}
+
 #[no_mangle]
 pub fn main() -> i32 {
     println!("Rust user shell");
@@ -84,7 +104,7 @@ pub fn main() -> i32 {
         match c {
             LF | CR => {
                 println!("");
-                if !line.is_empty() {
+                if !line.is_empty() && !run_builtin(line.as_str()) {
                     let splited: Vec<_> = line.as_str().split('|').collect();
                     let process_arguments_list: Vec<_> = splited
                         .iter()
@@ -117,6 +137,9 @@ pub fn main() -> i32 {
                             for _ in 0..process_arguments_list.len() - 1 {
                                 let mut pipe_fd = [0usize; 2];
                                 pipe(&mut pipe_fd);
+                                // children only keep the ends they dup2 onto stdin/stdout
+                                fcntl(pipe_fd[0], F_SETFD, FD_CLOEXEC);
+                                fcntl(pipe_fd[1], F_SETFD, FD_CLOEXEC);
                                 pipes_fd.push(pipe_fd);
                             }
                         }
@@ -130,50 +153,39 @@ pub fn main() -> i32 {
                                 let args_addr = &process_argument.args_addr;
                                 // redirect input
                                 if !input.is_empty() {
-                                    let input_fd = open(input.as_str(), OpenFlags::RDONLY);
+                                    let input_fd =
+                                        open(input.as_str(), OpenFlags::RDONLY | OpenFlags::CLOEXEC);
                                     if input_fd == -1 {
                                         println!("Error when opening file {}", input);
                                         return -4;
                                     }
-                                    let input_fd = input_fd as usize;
-                                    close(0);
-                                    assert_eq!(dup(input_fd), 0);
-                                    close(input_fd);
+                                    assert_eq!(dup2(input_fd as usize, 0), 0);
                                 }
                                 // redirect output
                                 if !output.is_empty() {
                                     let output_fd = open(
                                         output.as_str(),
-                                        OpenFlags::CREATE | OpenFlags::WRONLY,
+                                        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::CLOEXEC,
                                     );
                                     if output_fd == -1 {
                                         println!("Error when opening file {}", output);
                                         return -4;
                                     }
-                                    let output_fd = output_fd as usize;
-                                    close(1);
-                                    assert_eq!(dup(output_fd), 1);
-                                    close(output_fd);
+                                    assert_eq!(dup2(output_fd as usize, 1), 1);
                                 }
                                 // receive input from the previous process
                                 if i > 0 {
-                                    close(0);
                                     let read_end = pipes_fd.get(i - 1).unwrap()[0];
-                                    assert_eq!(dup(read_end), 0);
+                                    assert_eq!(dup2(read_end, 0), 0);
                                 }
                                 // send output to the next process
                                 if i < process_arguments_list.len() - 1 {
-                                    close(1);
                                     let write_end = pipes_fd.get(i).unwrap()[1];
-                                    assert_eq!(dup(write_end), 1);
-                                }
-                                // close all pipe ends inherited from the parent process
-                                for pipe_fd in pipes_fd.iter() {
-                                    close(pipe_fd[0]);
-                                    close(pipe_fd[1]);
+                                    assert_eq!(dup2(write_end, 1), 1);
                                 }
-                                // execute new application
-                                if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
+                                // the redirected files and pipe ends are close-on-exec,
+                                // so only stdin/stdout survive into the new application
+                                if exec(args_copy[0].as_str(), args_addr.as_slice()) < 0 {
                                     println!("Error when executing!");
                                     return -4;
                                 }
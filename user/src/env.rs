@@ -0,0 +1,106 @@
+//! The environment and the auxiliary vector a program gets from `exec`.
+//!
+//! `_start` copies the `KEY=VALUE` strings of envp, which `exec` passes on
+//! to the next program. The auxiliary vector stays on the stack, above the
+//! envp pointers.
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::c_str;
+
+pub const AT_NULL: usize = 0;
+/// Address of the program headers
+pub const AT_PHDR: usize = 3;
+/// Size of a program header
+pub const AT_PHENT: usize = 4;
+/// Number of program headers
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+/// Where a PIE was loaded, what its addresses are offset by
+pub const AT_BASE: usize = 7;
+/// Entry point of the program
+pub const AT_ENTRY: usize = 9;
+/// Address of 16 random bytes
+pub const AT_RANDOM: usize = 25;
+
+/// `KEY=VALUE` strings
+static mut ENVIRON: Vec<String> = Vec::new();
+/// Address of the auxiliary vector
+static mut AUXV: usize = 0;
+
+/// Copy the environment from the array of string pointers at `envp`, the
+/// auxiliary vector follows its null pointer
+pub(crate) fn init(envp: usize) {
+    if envp == 0 {
+        return;
+    }
+    let mut ptr = envp as *const usize;
+    loop {
+        let str_start = unsafe { ptr.read_volatile() };
+        ptr = unsafe { ptr.add(1) };
+        if str_start == 0 {
+            break;
+        }
+        environ().push(String::from(c_str(str_start)));
+    }
+    unsafe { AUXV = ptr as usize };
+}
+
+fn environ() -> &'static mut Vec<String> {
+    unsafe { &mut *core::ptr::addr_of_mut!(ENVIRON) }
+}
+
+fn split(entry: &str) -> (&str, &str) {
+    entry.split_once('=').unwrap_or((entry, ""))
+}
+
+/// The value of the variable `key`
+pub fn var(key: &str) -> Option<String> {
+    environ()
+        .iter()
+        .map(|entry| split(entry))
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| String::from(value))
+}
+
+/// Every variable and its value
+pub fn vars() -> Vec<(String, String)> {
+    environ()
+        .iter()
+        .map(|entry| split(entry))
+        .map(|(key, value)| (String::from(key), String::from(value)))
+        .collect()
+}
+
+/// Set `key` to `value`, for this program and the ones it runs
+pub fn set_var(key: &str, value: &str) {
+    remove_var(key);
+    environ().push(String::from(key) + "=" + value);
+}
+
+pub fn remove_var(key: &str) {
+    environ().retain(|entry| split(entry).0 != key);
+}
+
+/// `KEY=VALUE\0` strings to pass on to `exec`
+pub(crate) fn envp_strings() -> Vec<String> {
+    environ().iter().map(|entry| entry.clone() + "\0").collect()
+}
+
+/// The value of the auxiliary vector entry `key`, as `getauxval` returns
+pub fn getauxval(key: usize) -> Option<usize> {
+    let mut entry = unsafe { AUXV } as *const [usize; 2];
+    if entry.is_null() {
+        return None;
+    }
+    loop {
+        let [k, value] = unsafe { entry.read_volatile() };
+        if k == AT_NULL {
+            return None;
+        }
+        if k == key {
+            return Some(value);
+        }
+        entry = unsafe { entry.add(1) };
+    }
+}
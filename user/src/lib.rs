@@ -5,7 +5,9 @@
 
 #[macro_use]
 pub mod console;
+pub mod env;
 mod lang_items;
+pub mod net;
 mod syscall;
 
 extern crate alloc;
@@ -16,15 +18,53 @@ extern crate bitflags;
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
 pub use console::{flush, STDIN, STDOUT};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
 pub use syscall::*;
 
 const USER_HEAP_SIZE: usize = 16384;
+/// The heap grows by at least this much with `sbrk`
+const HEAP_GROWTH: usize = 64 * 1024;
+const PAGE_SIZE: usize = 4096;
 
 static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
 
-#[global_allocator]
 static HEAP: LockedHeap = LockedHeap::empty();
 
+/// The heap starts in `HEAP_SPACE` and takes memory from `sbrk` when it is full
+struct GrowingHeap;
+
+unsafe impl GlobalAlloc for GrowingHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = HEAP.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // the buddy block for the layout, twice that always holds an aligned one
+        let block = layout
+            .size()
+            .next_power_of_two()
+            .max(layout.align())
+            .max(core::mem::size_of::<usize>());
+        let size = (2 * block).max(HEAP_GROWTH);
+        let size = (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let start = sbrk(size as isize);
+        if start < 0 {
+            return core::ptr::null_mut();
+        }
+        heap.add_to_heap(start as usize, start as usize + size);
+        heap.alloc(layout)
+            .map_or(core::ptr::null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        HEAP.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: GrowingHeap = GrowingHeap;
+
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
@@ -44,9 +84,17 @@ fn clear_bss() {
     }
 }
 
+/// The nul-terminated string at `start`
+pub(crate) fn c_str(start: usize) -> &'static str {
+    let len = (0usize..)
+        .find(|i| unsafe { ((start + *i) as *const u8).read_volatile() == 0 })
+        .unwrap();
+    core::str::from_utf8(unsafe { core::slice::from_raw_parts(start as *const u8, len) }).unwrap()
+}
+
 #[no_mangle]
 #[link_section = ".text.entry"]
-pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
+pub extern "C" fn _start(argc: usize, argv: usize, envp: usize) -> ! {
     clear_bss();
     unsafe {
         HEAP.lock()
@@ -56,16 +104,9 @@ pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
     for i in 0..argc {
         let str_start =
             unsafe { ((argv + i * core::mem::size_of::<usize>()) as *const usize).read_volatile() };
-        let len = (0usize..)
-            .find(|i| unsafe { ((str_start + *i) as *const u8).read_volatile() == 0 })
-            .unwrap();
-        v.push(
-            core::str::from_utf8(unsafe {
-                core::slice::from_raw_parts(str_start as *const u8, len)
-            })
-            .unwrap(),
-        );
+        v.push(c_str(str_start));
     }
+    env::init(envp);
     exit(main(argc, v.as_slice()));
 }
 
@@ -82,6 +123,8 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        /// close the new fd on exec
+        const CLOEXEC = 1 << 19;
     }
 }
 
@@ -171,6 +214,45 @@ bitflags! {
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// named pipe
+        const FIFO  = 0o010000;
+    }
+}
+
+bitflags! {
+    /// Events of `poll`
+    pub struct PollEvents: i16 {
+        /// there is data to read
+        const IN    = 0x001;
+        /// writing would not block
+        const OUT   = 0x004;
+        /// error condition
+        const ERR   = 0x008;
+        /// the other end hung up
+        const HUP   = 0x010;
+        /// the fd is not open
+        const NVAL  = 0x020;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PollFd {
+    /// file descriptor, negative entries are skipped
+    pub fd: i32,
+    /// requested events
+    pub events: PollEvents,
+    /// returned events
+    pub revents: PollEvents,
+}
+
+impl PollFd {
+    pub fn new(fd: usize, events: PollEvents) -> Self {
+        Self {
+            fd: fd as i32,
+            events,
+            revents: PollEvents::empty(),
+        }
     }
 }
 
@@ -203,10 +285,20 @@ pub fn unlink(path: &str) -> isize {
     sys_unlinkat(AT_FDCWD as usize, path, 0)
 }
 
+pub fn mkfifo(path: &str) -> isize {
+    sys_mknodat(AT_FDCWD as usize, path, StatMode::FIFO.bits)
+}
+
 pub fn fstat(fd: usize, st: &Stat) -> isize {
     sys_fstat(fd, st)
 }
 
+/// Wait until one of `fds` is ready, a negative `timeout_ms` waits forever.
+/// Returns how many entries have `revents` set.
+pub fn poll(fds: &mut [PollFd], timeout_ms: isize) -> isize {
+    sys_poll(fds, timeout_ms)
+}
+
 pub fn mail_read(buf: &mut [u8]) -> isize {
     sys_mail_read(buf)
 }
@@ -240,8 +332,17 @@ pub fn fork() -> isize {
     sys_fork()
 }
 
+/// Run the program at `path` with the environment of this one
 pub fn exec(path: &str, args: &[*const u8]) -> isize {
-    sys_exec(path, args)
+    let envs = env::envp_strings();
+    let mut envp: Vec<*const u8> = envs.iter().map(|env| env.as_ptr()).collect();
+    envp.push(core::ptr::null());
+    sys_exec(path, args, envp.as_slice())
+}
+/// Run the program at `path` with the environment `envp`, `KEY=VALUE`
+/// strings ending with a null pointer
+pub fn execve(path: &str, args: &[*const u8], envp: &[*const u8]) -> isize {
+    sys_exec(path, args, envp)
 }
 
 pub fn set_priority(prio: isize) -> isize {
@@ -250,7 +351,7 @@ pub fn set_priority(prio: isize) -> isize {
 
 pub fn wait(exit_code: &mut i32) -> isize {
     loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
+        match sys_waitpid(-1, exit_code as *mut _, 0) {
             -2 => {
                 sys_yield();
             }
@@ -263,7 +364,7 @@ pub fn wait(exit_code: &mut i32) -> isize {
 
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
+        match sys_waitpid(pid as isize, exit_code as *mut _, 0) {
             -2 => {
                 sys_yield();
             }
@@ -274,6 +375,92 @@ pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     }
 }
 
+/// `waitpid_options` option to report the stops of tracees
+pub const WUNTRACED: usize = 2;
+
+/// Wait for a child with `options`. With `WUNTRACED` the status is encoded
+/// as in Linux, see `wifexited` and `wifstopped`
+pub fn waitpid_options(pid: isize, status: &mut i32, options: usize) -> isize {
+    loop {
+        match sys_waitpid(pid, status as *mut _, options) {
+            -2 => {
+                sys_yield();
+            }
+            n => {
+                return n;
+            }
+        }
+    }
+}
+
+pub fn wifexited(status: i32) -> bool {
+    status & 0x7f == 0
+}
+pub fn wexitstatus(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+pub fn wifstopped(status: i32) -> bool {
+    status & 0xff == 0x7f
+}
+pub fn wstopsig(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+
+pub const PTRACE_TRACEME: usize = 0;
+pub const PTRACE_PEEKDATA: usize = 2;
+pub const PTRACE_POKEDATA: usize = 5;
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_KILL: usize = 8;
+pub const PTRACE_GETREGS: usize = 12;
+pub const PTRACE_SETREGS: usize = 13;
+pub const PTRACE_ATTACH: usize = 16;
+pub const PTRACE_DETACH: usize = 17;
+pub const PTRACE_SYSCALL: usize = 24;
+/// Stop signal of syscall entries and exits
+pub const SYSCALL_STOP_SIG: i32 = 5 | 0x80;
+
+/// Registers of a stopped tracee: the pc, then x1 to x31
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct UserRegs {
+    pub pc: usize,
+    pub x: [usize; 31],
+}
+
+impl UserRegs {
+    /// Register `xN`, 0 for x0
+    pub fn reg(&self, n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            self.x[n - 1]
+        }
+    }
+}
+
+/// Trace a process, as `ptrace` does on Linux. Peeked words and registers
+/// are written to `data`
+pub fn ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    sys_ptrace(request, pid, addr, data)
+}
+pub fn ptrace_peek(pid: usize, addr: usize) -> Option<usize> {
+    let mut word = 0usize;
+    match ptrace(PTRACE_PEEKDATA, pid, addr, &mut word as *mut _ as usize) {
+        0 => Some(word),
+        _ => None,
+    }
+}
+pub fn ptrace_getregs(pid: usize) -> Option<UserRegs> {
+    let mut regs = UserRegs::default();
+    match ptrace(PTRACE_GETREGS, pid, 0, &mut regs as *mut _ as usize) {
+        0 => Some(regs),
+        _ => None,
+    }
+}
+pub fn ptrace_setregs(pid: usize, regs: &UserRegs) -> isize {
+    ptrace(PTRACE_SETREGS, pid, 0, regs as *const _ as usize)
+}
+
 pub fn sleep_blocking(sleep_ms: usize) {
     sys_sleep(sleep_ms);
 }
@@ -285,6 +472,23 @@ pub fn sleep(period_ms: usize)->isize {
     // }
     syscall(SYSCALL_SLEEP, [period_ms, 0, 0])
 }
+/// Move the end of the heap to `addr`, returns the end it is at after
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+
+/// Grow the heap by `increment` bytes, or shrink it if negative. Returns the
+/// old end of the heap, the start of the new memory, or -1
+pub fn sbrk(increment: isize) -> isize {
+    let old = sys_brk(0);
+    let new = (old + increment) as usize;
+    if sys_brk(new) == new as isize {
+        old
+    } else {
+        -1
+    }
+}
+
 pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
     sys_mmap(start, len, prot)
 }
@@ -300,6 +504,38 @@ pub fn spawn(path: &str) -> isize {
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
+/// Make `new_fd` refer to the file of `old_fd`, closing `new_fd` first if needed
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    if old_fd == new_fd {
+        // nothing to do, but old_fd must still be valid
+        return if fcntl(old_fd, F_GETFD, 0) < 0 { -1 } else { new_fd as isize };
+    }
+    sys_dup3(old_fd, new_fd, 0)
+}
+/// Like `dup2`, `flags` may hold `OpenFlags::CLOEXEC`
+pub fn dup3(old_fd: usize, new_fd: usize, flags: OpenFlags) -> isize {
+    sys_dup3(old_fd, new_fd, flags.bits)
+}
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+/// The close-on-exec bit of `F_GETFD`/`F_SETFD`
+pub const FD_CLOEXEC: usize = 1;
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_fcntl(fd, cmd, arg)
+}
+/// Copy the newest part of the kernel log into `buf`
+pub const SYSLOG_ACTION_READ_ALL: usize = 3;
+/// Same, then empty the kernel log
+pub const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+pub const SYSLOG_ACTION_CLEAR: usize = 5;
+/// Bytes in the kernel log
+pub const SYSLOG_ACTION_SIZE_UNREAD: usize = 9;
+/// Capacity of the kernel log
+pub const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+/// Read or clear the kernel log, as `klogctl` does on Linux
+pub fn klogctl(action: usize, buf: &mut [u8]) -> isize {
+    sys_syslog(action, buf)
+}
 pub fn pipe(pipe_fd: &mut [usize]) -> isize {
     sys_pipe(pipe_fd)
 }
@@ -309,7 +545,11 @@ pub fn task_info(info: &TaskInfo) -> isize {
 }
 
 pub fn thread_create(entry: usize, arg: usize) -> isize {
-    sys_thread_create(entry, arg)
+    sys_thread_create(entry, arg, 0)
+}
+/// Like `thread_create`, with a stack that may grow to `stack_size` bytes
+pub fn thread_create_with_stack(entry: usize, arg: usize, stack_size: usize) -> isize {
+    sys_thread_create(entry, arg, stack_size)
 }
 pub fn gettid() -> isize {
     sys_gettid()
@@ -329,7 +569,7 @@ pub fn kill(pid: usize, signal: i32) -> isize {
 }
 
 pub fn waitpid_nb(pid: usize, exit_code: &mut i32) -> isize {
-    sys_waitpid(pid as isize, exit_code as *mut _)
+    sys_waitpid(pid as isize, exit_code as *mut _, 0)
 }
 bitflags! {
     pub struct SignalFlags: i32 {
@@ -389,17 +629,6 @@ pub fn key_pressed() -> bool {
     }
 }
 
-pub fn connect(ip: u32, sport: u16, dport: u16) -> isize {
-    sys_connect(ip, sport, dport)
-}
-
-pub fn listen(sport: u16) -> isize {
-    sys_listen(sport)
-}
-
-pub fn accept(socket_fd: usize) -> isize {
-    sys_accept(socket_fd)
-}
 #[macro_export]
 macro_rules! vstore {
     ($var_ref: expr, $value: expr) => {
@@ -0,0 +1,316 @@
+//! TCP, UDP and ping sockets on top of the socket syscalls, and the
+//! interface configuration.
+//!
+//! Every socket type owns its file descriptor and closes it when dropped.
+use alloc::vec;
+use core::fmt;
+
+use crate::{
+    close, read, sys_accept, sys_bind, sys_connect, sys_ifconfig_get, sys_ifconfig_set,
+    sys_listen, sys_recvfrom, sys_sendto, sys_shutdown, sys_socket, write,
+};
+
+pub const AF_INET: usize = 2;
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+pub const SOCK_CLOEXEC: usize = 0x80000;
+
+pub const IPPROTO_ICMP: usize = 1;
+pub const ICMP_ECHO_REPLY: u8 = 0;
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+
+pub const SHUT_RD: usize = 0;
+pub const SHUT_WR: usize = 1;
+pub const SHUT_RDWR: usize = 2;
+
+/// `struct sockaddr_in`, port and address in network order
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SockAddrIn {
+    pub family: u16,
+    pub port: [u8; 2],
+    pub addr: [u8; 4],
+    pub zero: [u8; 8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketAddrV4 {
+    pub ip: [u8; 4],
+    pub port: u16,
+}
+
+impl SocketAddrV4 {
+    pub const fn new(ip: [u8; 4], port: u16) -> Self {
+        Self { ip, port }
+    }
+}
+
+impl From<SocketAddrV4> for SockAddrIn {
+    fn from(addr: SocketAddrV4) -> Self {
+        Self {
+            family: AF_INET as u16,
+            port: addr.port.to_be_bytes(),
+            addr: addr.ip,
+            zero: [0; 8],
+        }
+    }
+}
+
+impl From<SockAddrIn> for SocketAddrV4 {
+    fn from(addr: SockAddrIn) -> Self {
+        Self {
+            ip: addr.addr,
+            port: u16::from_be_bytes(addr.port),
+        }
+    }
+}
+
+impl fmt::Display for SocketAddrV4 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d] = self.ip;
+        write!(f, "{}.{}.{}.{}:{}", a, b, c, d, self.port)
+    }
+}
+
+/// Parse dotted decimal such as `10.0.2.15`
+pub fn parse_ipv4(s: &str) -> Option<[u8; 4]> {
+    let mut addr = [0u8; 4];
+    let mut parts = s.split('.');
+    for byte in addr.iter_mut() {
+        *byte = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(addr)
+}
+
+/// `IfConfig::source`: no address, a static one, or one leased over DHCP
+pub const CONFIG_NONE: u8 = 0;
+pub const CONFIG_STATIC: u8 = 1;
+pub const CONFIG_DHCP: u8 = 2;
+
+/// Configuration of the network interface
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IfConfig {
+    pub mac: [u8; 6],
+    pub prefix_len: u8,
+    pub source: u8,
+    pub ip: [u8; 4],
+    pub gateway: [u8; 4],
+    pub dns: [u8; 4],
+}
+
+pub fn ifconfig() -> IfConfig {
+    let mut config = IfConfig::default();
+    sys_ifconfig_get(&mut config);
+    config
+}
+
+/// Apply `config`, with `source` CONFIG_DHCP the address fields are ignored
+pub fn set_ifconfig(config: &IfConfig) -> bool {
+    sys_ifconfig_set(config) == 0
+}
+
+fn socket(sock_type: usize, protocol: usize) -> Option<usize> {
+    let fd = sys_socket(AF_INET, sock_type, protocol);
+    if fd < 0 {
+        None
+    } else {
+        Some(fd as usize)
+    }
+}
+
+fn bind(fd: usize, addr: SocketAddrV4) -> bool {
+    sys_bind(fd, &addr.into()) == 0
+}
+
+/// An established TCP connection
+pub struct TcpStream {
+    fd: usize,
+}
+
+impl TcpStream {
+    /// Connect to `addr`, blocking until the handshake completes
+    pub fn connect(addr: SocketAddrV4) -> Option<Self> {
+        let stream = Self {
+            fd: socket(SOCK_STREAM, 0)?,
+        };
+        if sys_connect(stream.fd, &addr.into()) != 0 {
+            return None;
+        }
+        Some(stream)
+    }
+
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    /// Returns 0 once the peer has closed its side
+    pub fn read(&self, buf: &mut [u8]) -> isize {
+        read(self.fd, buf)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> isize {
+        write(self.fd, buf)
+    }
+
+    /// Write all of `buf`, false if the connection broke first
+    pub fn write_all(&self, mut buf: &[u8]) -> bool {
+        while !buf.is_empty() {
+            let len = self.write(buf);
+            if len <= 0 {
+                return false;
+            }
+            buf = &buf[len as usize..];
+        }
+        true
+    }
+
+    /// `SHUT_WR` sends a FIN while the stream can still be read
+    pub fn shutdown(&self, how: usize) -> bool {
+        sys_shutdown(self.fd, how) == 0
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        close(self.fd);
+    }
+}
+
+pub struct TcpListener {
+    fd: usize,
+}
+
+impl TcpListener {
+    /// Listen on `addr`, a port of 0 picks a free one
+    pub fn bind(addr: SocketAddrV4, backlog: usize) -> Option<Self> {
+        let listener = Self {
+            fd: socket(SOCK_STREAM, 0)?,
+        };
+        if !bind(listener.fd, addr) || sys_listen(listener.fd, backlog) != 0 {
+            return None;
+        }
+        Some(listener)
+    }
+
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    /// Block until a client connects
+    pub fn accept(&self) -> Option<(TcpStream, SocketAddrV4)> {
+        let mut addr = SockAddrIn::default();
+        let fd = sys_accept(self.fd, &mut addr);
+        if fd < 0 {
+            return None;
+        }
+        Some((TcpStream { fd: fd as usize }, addr.into()))
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        close(self.fd);
+    }
+}
+
+pub struct UdpSocket {
+    fd: usize,
+}
+
+impl UdpSocket {
+    /// A socket on `addr`, a port of 0 picks a free one
+    pub fn bind(addr: SocketAddrV4) -> Option<Self> {
+        let socket = Self {
+            fd: socket(SOCK_DGRAM, 0)?,
+        };
+        if !bind(socket.fd, addr) {
+            return None;
+        }
+        Some(socket)
+    }
+
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    /// Send to and only receive from `addr` from now on
+    pub fn connect(&self, addr: SocketAddrV4) -> bool {
+        sys_connect(self.fd, &addr.into()) == 0
+    }
+
+    pub fn send_to(&self, buf: &[u8], addr: SocketAddrV4) -> isize {
+        sys_sendto(self.fd, buf, 0, Some(&addr.into()))
+    }
+
+    /// Block until a datagram arrives, a datagram longer than `buf` is cut short
+    pub fn recv_from(&self, buf: &mut [u8]) -> Option<(usize, SocketAddrV4)> {
+        let mut addr = SockAddrIn::default();
+        let len = sys_recvfrom(self.fd, buf, 0, &mut addr);
+        if len < 0 {
+            return None;
+        }
+        Some((len as usize, addr.into()))
+    }
+
+    /// Send to the connected peer
+    pub fn send(&self, buf: &[u8]) -> isize {
+        sys_sendto(self.fd, buf, 0, None)
+    }
+
+    pub fn recv(&self, buf: &mut [u8]) -> isize {
+        read(self.fd, buf)
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        close(self.fd);
+    }
+}
+
+/// Sends ICMP echo requests and receives the replies to them. The kernel
+/// sets the identifier and checksum of every request it sends.
+pub struct IcmpSocket {
+    fd: usize,
+}
+
+impl IcmpSocket {
+    pub fn new() -> Option<Self> {
+        Some(Self {
+            fd: socket(SOCK_DGRAM, IPPROTO_ICMP)?,
+        })
+    }
+
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    /// Send an echo request with `seq` and `payload` to `ip`
+    pub fn send_echo(&self, ip: [u8; 4], seq: u16, payload: &[u8]) -> bool {
+        let mut request = vec![0u8; 8 + payload.len()];
+        request[0] = ICMP_ECHO_REQUEST;
+        request[6..8].copy_from_slice(&seq.to_be_bytes());
+        request[8..].copy_from_slice(payload);
+        sys_sendto(self.fd, &request, 0, Some(&SocketAddrV4::new(ip, 0).into())) >= 0
+    }
+
+    /// Block until a reply arrives, returning its length, header included, and source
+    pub fn recv_from(&self, buf: &mut [u8]) -> Option<(usize, [u8; 4])> {
+        let mut addr = SockAddrIn::default();
+        let len = sys_recvfrom(self.fd, buf, 0, &mut addr);
+        if len < 0 {
+            return None;
+        }
+        Some((len as usize, addr.addr))
+    }
+}
+
+impl Drop for IcmpSocket {
+    fn drop(&mut self) {
+        close(self.fd);
+    }
+}
@@ -1,16 +1,23 @@
+use core::mem::size_of;
+
+use crate::net::{IfConfig, SockAddrIn};
 use crate::TaskInfo;
 
-use super::{Stat, TimeVal};
+use super::{PollFd, Stat, TimeVal};
 
 pub const SYSCALL_OPENAT: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
 pub const SYSCALL_READ: usize = 63;
 pub const SYSCALL_WRITE: usize = 64;
+pub const SYSCALL_POLL: usize = 73;
+pub const SYSCALL_MKNODAT: usize = 33;
 pub const SYSCALL_UNLINKAT: usize = 35;
 pub const SYSCALL_LINKAT: usize = 37;
 pub const SYSCALL_FSTAT: usize = 80;
 pub const SYSCALL_EXIT: usize = 93;
 pub const SYSCALL_SLEEP: usize = 101;
+const SYSCALL_SYSLOG: usize = 116;
+const SYSCALL_PTRACE: usize = 117;
 pub const SYSCALL_YIELD: usize = 124;
 
 const SYSCALL_KILL: usize = 129;
@@ -20,14 +27,19 @@ pub const SYSCALL_FORK: usize = 220;
 pub const SYSCALL_EXEC: usize = 221;
 pub const SYSCALL_WAITPID: usize = 260;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
+pub const SYSCALL_BRK: usize = 214;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
 pub const SYSCALL_SPAWN: usize = 400;
 pub const SYSCALL_MAIL_READ: usize = 401;
 pub const SYSCALL_MAIL_WRITE: usize = 402;
 pub const SYSCALL_DUP: usize = 24;
+pub const SYSCALL_FCNTL: usize = 25;
+pub const SYSCALL_DUP3: usize = 26;
 pub const SYSCALL_PIPE: usize = 59;
 pub const SYSCALL_TASK_INFO: usize = 410;
+const SYSCALL_IFCONFIG_GET: usize = 500;
+const SYSCALL_IFCONFIG_SET: usize = 501;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
 const SYSCALL_WAITTID: usize = 1002;
@@ -46,9 +58,14 @@ const SYSCALL_FRAMEBUFFER_FLUSH: usize = 2001;
 const SYSCALL_EVENT_GET: usize = 3000;
 const SYSCALL_KEY_PRESSED: usize = 3001;
 
-const SYSCALL_CONNECT: usize = 29;
-const SYSCALL_LISTEN: usize = 30;
-const SYSCALL_ACCEPT: usize = 31;
+const SYSCALL_SOCKET: usize = 198;
+const SYSCALL_BIND: usize = 200;
+const SYSCALL_LISTEN: usize = 201;
+const SYSCALL_ACCEPT: usize = 202;
+const SYSCALL_CONNECT: usize = 203;
+const SYSCALL_SENDTO: usize = 206;
+const SYSCALL_RECVFROM: usize = 207;
+const SYSCALL_SHUTDOWN: usize = 210;
 
 
 pub const SYSCALL_ENABLE_DEADLOCK_DETECT: usize = 469;
@@ -132,6 +149,10 @@ pub fn sys_linkat(
     )
 }
 
+pub fn sys_mknodat(dirfd: usize, path: &str, mode: u32) -> isize {
+    syscall(SYSCALL_MKNODAT, [dirfd, path.as_ptr() as usize, mode as usize])
+}
+
 pub fn sys_unlinkat(dirfd: usize, path: &str, flags: usize) -> isize {
     syscall(SYSCALL_UNLINKAT, [dirfd, path.as_ptr() as usize, flags])
 }
@@ -163,6 +184,14 @@ pub fn sys_sleep(sleep_ms: usize) -> isize {
     syscall(SYSCALL_SLEEP, [sleep_ms, 0, 0])
 }
 
+pub fn sys_syslog(action: usize, buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_SYSLOG, [action, buf.as_mut_ptr() as usize, buf.len()])
+}
+
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    syscall6(SYSCALL_PTRACE, [request, pid, addr, data, 0, 0])
+}
+
 pub fn sys_yield() -> isize {
     syscall(SYSCALL_YIELD, [0, 0, 0])
 }
@@ -179,21 +208,25 @@ pub fn sys_fork() -> isize {
     syscall(SYSCALL_FORK, [0, 0, 0])
 }
 
-pub fn sys_exec(path: &str, args: &[*const u8]) -> isize {
+pub fn sys_exec(path: &str, args: &[*const u8], envp: &[*const u8]) -> isize {
     syscall(
         SYSCALL_EXEC,
-        [path.as_ptr() as usize, args.as_ptr() as usize, 0],
+        [path.as_ptr() as usize, args.as_ptr() as usize, envp.as_ptr() as usize],
     )
 }
 
-pub fn sys_waitpid(pid: isize, xstatus: *mut i32) -> isize {
-    syscall(SYSCALL_WAITPID, [pid as usize, xstatus as usize, 0])
+pub fn sys_waitpid(pid: isize, xstatus: *mut i32, options: usize) -> isize {
+    syscall(SYSCALL_WAITPID, [pid as usize, xstatus as usize, options])
 }
 
 pub fn sys_set_priority(prio: isize) -> isize {
     syscall(SYSCALL_SET_PRIORITY, [prio as usize, 0, 0])
 }
 
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
 pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
     syscall(SYSCALL_MMAP, [start, len, prot])
 }
@@ -210,16 +243,28 @@ pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0])
 }
 
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    syscall(SYSCALL_DUP3, [old_fd, new_fd, flags as usize])
+}
+
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_FCNTL, [fd, cmd, arg])
+}
+
 pub fn sys_pipe(pipe: &mut [usize]) -> isize {
     syscall(SYSCALL_PIPE, [pipe.as_mut_ptr() as usize, 0, 0])
 }
 
+pub fn sys_poll(fds: &mut [PollFd], timeout_ms: isize) -> isize {
+    syscall(SYSCALL_POLL, [fds.as_mut_ptr() as usize, fds.len(), timeout_ms as usize])
+}
+
 pub fn sys_task_info(info: &TaskInfo) -> isize {
     syscall(SYSCALL_TASK_INFO, [info as *const _ as usize, 0, 0])
 }
 
-pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
-    syscall(SYSCALL_THREAD_CREATE, [entry, arg, 0])
+pub fn sys_thread_create(entry: usize, arg: usize, stack_size: usize) -> isize {
+    syscall(SYSCALL_THREAD_CREATE, [entry, arg, stack_size])
 }
 
 pub fn sys_gettid() -> isize {
@@ -291,18 +336,71 @@ pub fn sys_kill(pid: usize, signal: i32) -> isize {
     syscall(SYSCALL_KILL, [pid, signal as usize, 0])
 }
 
-pub fn sys_connect(dest: u32, sport: u16, dport: u16) -> isize {
+pub fn sys_socket(domain: usize, sock_type: usize, protocol: usize) -> isize {
+    syscall(SYSCALL_SOCKET, [domain, sock_type, protocol])
+}
+
+pub fn sys_bind(fd: usize, addr: &SockAddrIn) -> isize {
+    syscall(
+        SYSCALL_BIND,
+        [fd, addr as *const _ as usize, size_of::<SockAddrIn>()],
+    )
+}
+
+pub fn sys_listen(fd: usize, backlog: usize) -> isize {
+    syscall(SYSCALL_LISTEN, [fd, backlog, 0])
+}
+
+pub fn sys_accept(fd: usize, addr: &mut SockAddrIn) -> isize {
+    let mut addrlen = size_of::<SockAddrIn>() as u32;
+    syscall(
+        SYSCALL_ACCEPT,
+        [fd, addr as *mut _ as usize, &mut addrlen as *mut _ as usize],
+    )
+}
+
+pub fn sys_connect(fd: usize, addr: &SockAddrIn) -> isize {
     syscall(
         SYSCALL_CONNECT,
-        [dest as usize, sport as usize, dport as usize],
+        [fd, addr as *const _ as usize, size_of::<SockAddrIn>()],
+    )
+}
+
+/// `addr` None sends to the connected peer
+pub fn sys_sendto(fd: usize, buf: &[u8], flags: usize, addr: Option<&SockAddrIn>) -> isize {
+    let (addr, addrlen) = match addr {
+        Some(addr) => (addr as *const _ as usize, size_of::<SockAddrIn>()),
+        None => (0, 0),
+    };
+    syscall6(
+        SYSCALL_SENDTO,
+        [fd, buf.as_ptr() as usize, buf.len(), flags, addr, addrlen],
+    )
+}
+
+pub fn sys_recvfrom(fd: usize, buf: &mut [u8], flags: usize, addr: &mut SockAddrIn) -> isize {
+    let mut addrlen = size_of::<SockAddrIn>() as u32;
+    syscall6(
+        SYSCALL_RECVFROM,
+        [
+            fd,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+            flags,
+            addr as *mut _ as usize,
+            &mut addrlen as *mut _ as usize,
+        ],
     )
 }
 
-// just listen for tcp connections now
-pub fn sys_listen(sport: u16) -> isize {
-    syscall(SYSCALL_LISTEN, [sport as usize, 0, 0])
+pub fn sys_shutdown(fd: usize, how: usize) -> isize {
+    syscall(SYSCALL_SHUTDOWN, [fd, how, 0])
+}
+
+pub fn sys_ifconfig_get(config: &mut IfConfig) -> isize {
+    syscall(SYSCALL_IFCONFIG_GET, [config as *mut _ as usize, 0, 0])
 }
 
-pub fn sys_accept(socket_fd: usize) -> isize {
-    syscall(SYSCALL_ACCEPT, [socket_fd, 0, 0])
+pub fn sys_ifconfig_set(config: &IfConfig) -> isize {
+    syscall(SYSCALL_IFCONFIG_SET, [config as *const _ as usize, 0, 0])
 }